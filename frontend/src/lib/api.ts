@@ -64,6 +64,15 @@ export function clearAuthToken(): void {
   localStorage.removeItem('auth_token');
 }
 
+// Active sessions slide: the server hands back a fresh token once the current one is
+// past half its lifetime
+function adoptRefreshedToken(response: Response): void {
+  const refreshed = response.headers.get('X-Refreshed-Token');
+  if (refreshed) {
+    setAuthToken(refreshed);
+  }
+}
+
 export function isAuthenticated(): boolean {
   return getAuthToken() !== null;
 }
@@ -92,6 +101,7 @@ async function apiRequest<T>(
     headers: requestHeaders,
     body: body instanceof FormData ? body : body ? JSON.stringify(body) : undefined,
   });
+  adoptRefreshedToken(response);
 
   if (!response.ok) {
     const errorBody = await response.json().catch(() => ({}));
@@ -176,6 +186,7 @@ export async function downloadFile(fileId: string): Promise<Blob> {
   const response = await fetch(`${API_BASE}/api/files/${fileId}/download`, {
     headers: token ? { Authorization: `Bearer ${token}` } : {},
   });
+  adoptRefreshedToken(response);
 
   if (!response.ok) {
     const errorBody = await response.json().catch(() => ({}));
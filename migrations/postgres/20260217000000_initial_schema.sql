@@ -0,0 +1,27 @@
+-- Create users table
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (now()::text)
+);
+
+-- Create index on username for faster lookups
+CREATE INDEX IF NOT EXISTS idx_users_username ON users(username);
+
+-- Create files table (metadata for encrypted blobs)
+CREATE TABLE IF NOT EXISTS files (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    original_name TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    is_encrypted INTEGER NOT NULL DEFAULT 1,
+    storage_path TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (now()::text)
+);
+
+-- Create indexes for file queries
+CREATE INDEX IF NOT EXISTS idx_files_user_id ON files(user_id);
+CREATE INDEX IF NOT EXISTS idx_files_original_name ON files(original_name);
+CREATE INDEX IF NOT EXISTS idx_files_created_at ON files(created_at);
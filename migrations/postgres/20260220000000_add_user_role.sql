@@ -0,0 +1,2 @@
+-- Add role column to users (either 'user' or 'admin')
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
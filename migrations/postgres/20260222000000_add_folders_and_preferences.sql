@@ -0,0 +1,23 @@
+-- Folders (per-user tree; parent_id NULL = root)
+CREATE TABLE IF NOT EXISTS folders (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    parent_id TEXT REFERENCES folders(id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL DEFAULT (now()::text)
+);
+
+CREATE INDEX IF NOT EXISTS idx_folders_user_parent ON folders(user_id, parent_id);
+
+-- Folder a file lives in (NULL = root)
+ALTER TABLE files ADD COLUMN parent_id TEXT REFERENCES folders(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_files_parent_id ON files(parent_id);
+
+-- Per-user preferences
+CREATE TABLE IF NOT EXISTS user_preferences (
+    user_id TEXT PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    default_folder_id TEXT REFERENCES folders(id) ON DELETE SET NULL,
+    -- JSON array of {"mime_prefix": "...", "folder": "..."} rules
+    auto_folder_rules TEXT NOT NULL DEFAULT '[]'
+);
@@ -0,0 +1,11 @@
+-- Public share links (one per file; token is the unguessable URL component)
+CREATE TABLE IF NOT EXISTS shares (
+    token TEXT PRIMARY KEY NOT NULL,
+    file_id TEXT NOT NULL UNIQUE REFERENCES files(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL DEFAULT (now()::text),
+    -- RFC 3339; NULL = never expires
+    expires_at TEXT,
+    -- NULL = unlimited
+    remaining_downloads BIGINT
+);
@@ -0,0 +1,10 @@
+-- Trigram index so LOWER(original_name) LIKE '%term%' can use an index.
+-- pg_trgm may be unavailable (or need privileges we lack); search then falls back to a scan.
+DO $$
+BEGIN
+    CREATE EXTENSION IF NOT EXISTS pg_trgm;
+    CREATE INDEX IF NOT EXISTS idx_files_name_trgm ON files USING gin (LOWER(original_name) gin_trgm_ops);
+EXCEPTION WHEN OTHERS THEN
+    RAISE NOTICE 'pg_trgm unavailable; filename search will not be indexed';
+END
+$$;
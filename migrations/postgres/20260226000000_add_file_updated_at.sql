@@ -0,0 +1,2 @@
+-- Last time the file's content was replaced (NULL = never)
+ALTER TABLE files ADD COLUMN updated_at TEXT;
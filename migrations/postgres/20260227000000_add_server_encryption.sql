@@ -0,0 +1,4 @@
+-- Server-side at-rest encryption (NULL = blob stored exactly as uploaded)
+ALTER TABLE files ADD COLUMN server_encryption_algo TEXT;
+-- Hex-encoded per-file STREAM nonce prefix
+ALTER TABLE files ADD COLUMN server_encryption_nonce TEXT;
@@ -0,0 +1,17 @@
+-- Reference-counted blobs on disk. Identical uploads by the same user share one blob;
+-- the file is removed only when the last referencing row goes away.
+CREATE TABLE IF NOT EXISTS blobs (
+    storage_path TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content_hash TEXT,
+    server_encryption_algo TEXT,
+    server_encryption_nonce TEXT,
+    ref_count BIGINT NOT NULL DEFAULT 1
+);
+
+CREATE INDEX IF NOT EXISTS idx_blobs_user_hash ON blobs(user_id, content_hash);
+
+INSERT INTO blobs (storage_path, user_id, content_hash, server_encryption_algo, server_encryption_nonce, ref_count)
+SELECT storage_path, MIN(user_id), MIN(content_hash), MIN(server_encryption_algo), MIN(server_encryption_nonce), COUNT(*)
+FROM files
+GROUP BY storage_path;
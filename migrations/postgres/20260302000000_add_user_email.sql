@@ -0,0 +1,19 @@
+-- Optional contact email, verified through a single-use link
+ALTER TABLE users ADD COLUMN email TEXT;
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- NULLs never collide, so only accounts that set an email must be unique
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email ON users(email);
+
+-- Single-use verification tokens; only a SHA-256 of the token is stored
+CREATE TABLE IF NOT EXISTS email_verifications (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- The address being verified; a token is void once the user's email changes
+    email TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (now()::text),
+    -- RFC 3339
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_email_verifications_user_id ON email_verifications(user_id);
@@ -0,0 +1,13 @@
+-- Long-lived API keys for automation; only a SHA-256 of the key is stored
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    -- First characters of the key, so users can tell keys apart
+    prefix TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (now()::text),
+    last_used_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id);
@@ -0,0 +1,8 @@
+-- Free-form labels on files; stored lowercased so matching is case-insensitive
+CREATE TABLE IF NOT EXISTS file_tags (
+    file_id TEXT NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (file_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_tags_tag ON file_tags(tag);
@@ -0,0 +1,11 @@
+-- Single-use signup codes for SIGNUP_MODE=invite; only a SHA-256 of the code is stored
+CREATE TABLE IF NOT EXISTS invite_codes (
+    code_hash TEXT PRIMARY KEY NOT NULL,
+    -- Admin who generated the code (NULL once that account is deleted)
+    created_by TEXT REFERENCES users(id) ON DELETE SET NULL,
+    created_at TEXT NOT NULL DEFAULT (now()::text),
+    -- RFC 3339; NULL = never expires
+    expires_at TEXT,
+    used_at TEXT,
+    used_by TEXT REFERENCES users(id) ON DELETE SET NULL
+);
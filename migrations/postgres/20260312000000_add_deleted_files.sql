@@ -0,0 +1,9 @@
+-- Tombstones for deleted files, so sync clients and usage deltas can see deletions
+CREATE TABLE IF NOT EXISTS deleted_files (
+    file_id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    size_bytes BIGINT NOT NULL,
+    deleted_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_deleted_files_user_deleted_at ON deleted_files(user_id, deleted_at);
@@ -0,0 +1,2 @@
+-- Carried in every token; bumping it revokes all of the user's tokens at once
+ALTER TABLE users ADD COLUMN token_version BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,16 @@
+-- Responses to requests sent with an Idempotency-Key, replayed when the key is reused
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    -- Method, path and caller the key is valid for
+    scope TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    -- NULL while the first request with the key is still running
+    status BIGINT,
+    content_type TEXT,
+    body TEXT,
+    created_at TEXT NOT NULL,
+    -- RFC 3339
+    expires_at TEXT NOT NULL,
+    PRIMARY KEY (scope, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_expires_at ON idempotency_keys(expires_at);
@@ -0,0 +1,6 @@
+-- Last change to the file's content, folder or tags (NULL = none since upload)
+ALTER TABLE files ADD COLUMN modified_at TEXT;
+
+UPDATE files SET modified_at = updated_at;
+
+CREATE INDEX IF NOT EXISTS idx_files_user_modified_at ON files(user_id, modified_at);
@@ -0,0 +1,3 @@
+-- SHA-256 (hex) of the stored blob, computed while streaming the upload.
+-- NULL for files uploaded before hashing was introduced.
+ALTER TABLE files ADD COLUMN content_hash TEXT;
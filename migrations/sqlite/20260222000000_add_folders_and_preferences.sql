@@ -0,0 +1,27 @@
+-- Folders (per-user tree; parent_id NULL = root)
+CREATE TABLE IF NOT EXISTS folders (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    parent_id TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (parent_id) REFERENCES folders(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_folders_user_parent ON folders(user_id, parent_id);
+
+-- Folder a file lives in (NULL = root)
+ALTER TABLE files ADD COLUMN parent_id TEXT REFERENCES folders(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_files_parent_id ON files(parent_id);
+
+-- Per-user preferences
+CREATE TABLE IF NOT EXISTS user_preferences (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    default_folder_id TEXT,
+    -- JSON array of {"mime_prefix": "...", "folder": "..."} rules
+    auto_folder_rules TEXT NOT NULL DEFAULT '[]',
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (default_folder_id) REFERENCES folders(id) ON DELETE SET NULL
+);
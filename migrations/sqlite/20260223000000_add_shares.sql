@@ -0,0 +1,13 @@
+-- Public share links (one per file; token is the unguessable URL component)
+CREATE TABLE IF NOT EXISTS shares (
+    token TEXT PRIMARY KEY NOT NULL,
+    file_id TEXT NOT NULL UNIQUE,
+    user_id TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    -- RFC 3339; NULL = never expires
+    expires_at TEXT,
+    -- NULL = unlimited
+    remaining_downloads INTEGER,
+    FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- Argon2 hash of the share password (NULL = no password)
+ALTER TABLE shares ADD COLUMN password_hash TEXT;
@@ -0,0 +1,23 @@
+-- Trigram full-text index over filenames so substring search can use an index.
+-- External-content table: rows live in `files`, kept in sync by the triggers below.
+CREATE VIRTUAL TABLE IF NOT EXISTS files_fts USING fts5(
+    original_name,
+    content = 'files',
+    content_rowid = 'rowid',
+    tokenize = 'trigram'
+);
+
+INSERT INTO files_fts(files_fts) VALUES ('rebuild');
+
+CREATE TRIGGER IF NOT EXISTS files_fts_insert AFTER INSERT ON files BEGIN
+    INSERT INTO files_fts(rowid, original_name) VALUES (new.rowid, new.original_name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_fts_delete AFTER DELETE ON files BEGIN
+    INSERT INTO files_fts(files_fts, rowid, original_name) VALUES ('delete', old.rowid, old.original_name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_fts_rename AFTER UPDATE OF original_name ON files BEGIN
+    INSERT INTO files_fts(files_fts, rowid, original_name) VALUES ('delete', old.rowid, old.original_name);
+    INSERT INTO files_fts(rowid, original_name) VALUES (new.rowid, new.original_name);
+END;
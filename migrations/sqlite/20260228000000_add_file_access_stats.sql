@@ -0,0 +1,3 @@
+-- Download analytics (owner downloads and share-link downloads)
+ALTER TABLE files ADD COLUMN download_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN last_accessed_at TEXT;
@@ -0,0 +1,4 @@
+-- Consecutive failed logins since the last success or lockout
+ALTER TABLE users ADD COLUMN failed_login_attempts INTEGER NOT NULL DEFAULT 0;
+-- RFC 3339; logins are refused until then (NULL = not locked)
+ALTER TABLE users ADD COLUMN locked_until TEXT;
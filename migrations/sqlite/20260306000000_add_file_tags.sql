@@ -0,0 +1,9 @@
+-- Free-form labels on files; stored lowercased so matching is case-insensitive
+CREATE TABLE IF NOT EXISTS file_tags (
+    file_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (file_id, tag),
+    FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_tags_tag ON file_tags(tag);
@@ -0,0 +1,2 @@
+-- Suspended accounts keep their data but can't log in or use existing tokens
+ALTER TABLE users ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT 1;
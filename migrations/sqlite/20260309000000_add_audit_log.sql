@@ -0,0 +1,17 @@
+-- Security-relevant actions, kept after the acting user is deleted (no foreign keys)
+CREATE TABLE IF NOT EXISTS audit_log (
+    id TEXT PRIMARY KEY NOT NULL,
+    -- Acting user (NULL for failed logins to unknown accounts)
+    user_id TEXT,
+    action TEXT NOT NULL,
+    -- What the action was applied to: a file or user id (the attempted username for unknown logins)
+    target TEXT,
+    -- Action-specific context, e.g. the new role or why a login failed
+    detail TEXT,
+    ip TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at);
+CREATE INDEX IF NOT EXISTS idx_audit_log_user_id ON audit_log(user_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_audit_log_action ON audit_log(action, created_at);
@@ -0,0 +1,2 @@
+-- Index into STORAGE_ROOTS of the volume holding the user's files; fixed at signup
+ALTER TABLE users ADD COLUMN storage_root INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Client-chosen id making retried uploads idempotent; unique per user when set
+ALTER TABLE files ADD COLUMN client_file_id TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_files_user_client_file_id ON files(user_id, client_file_id);
@@ -0,0 +1,2 @@
+-- Bumped on every metadata or content change; exposed as the file's ETag for If-Match
+ALTER TABLE files ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+-- Set when the content couldn't be rendered as a thumbnail; cleared when it is replaced
+ALTER TABLE files ADD COLUMN thumbnail_failed BOOLEAN NOT NULL DEFAULT 0;
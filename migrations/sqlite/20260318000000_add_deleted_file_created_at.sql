@@ -0,0 +1,3 @@
+-- When the deleted file was uploaded, so usage deltas still count it as added (NULL for
+-- tombstones written before this column existed)
+ALTER TABLE deleted_files ADD COLUMN created_at TEXT;
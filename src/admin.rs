@@ -0,0 +1,398 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::QueryBuilder;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::audit;
+use crate::auth::{AdminUser, AuthError};
+use crate::db::Db;
+use crate::filemanager::{File, FileRepository};
+use crate::{storage, thumbnails};
+use crate::user::{ROLE_ADMIN, ROLE_USER, UserError, UserRepository, UserResponse, UserWithFileCount};
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+pub struct UserListQuery {
+    /// 1-based page number (default 1)
+    pub page: Option<i64>,
+    /// Users per page (default 50, max 500)
+    pub page_size: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AdminUserResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub file_count: i64,
+    pub total_bytes: i64,
+}
+
+impl From<UserWithFileCount> for AdminUserResponse {
+    fn from(row: UserWithFileCount) -> Self {
+        Self {
+            user: row.user.into(),
+            file_count: row.file_count,
+            total_bytes: row.total_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserListResponse {
+    pub users: Vec<AdminUserResponse>,
+    pub page: i64,
+    pub page_size: i64,
+    pub total_users: i64,
+    pub total_pages: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+pub struct AdminFileQuery {
+    /// Only files owned by this user
+    pub user_id: Option<String>,
+    /// 1-based page number (default 1)
+    pub page: Option<i64>,
+    /// Files per page (default 50, max 500)
+    pub page_size: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AdminFileListResponse {
+    /// Newest first
+    pub files: Vec<File>,
+    pub page: i64,
+    pub page_size: i64,
+    pub total_files: i64,
+    pub total_pages: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetRoleRequest {
+    /// `user` or `admin`
+    pub role: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/users",
+    tag = "admin",
+    params(UserListQuery),
+    responses(
+        (status = 200, description = "One page of users with their file counts", body = UserListResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Admin privileges required")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_users(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<UserListQuery>,
+) -> Result<Json<UserListResponse>, AuthError> {
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(50).clamp(1, 500);
+
+    let user_repo = UserRepository::new(state.db_pool.clone());
+    let users = user_repo
+        .list_users(page, page_size)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+    let total_users = user_repo
+        .count_users()
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+    Ok(Json(UserListResponse {
+        users: users.into_iter().map(AdminUserResponse::from).collect(),
+        page,
+        page_size,
+        total_users,
+        total_pages: (total_users as f64 / page_size as f64).ceil() as i64,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/admin/users/{id}",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "User ID")
+    ),
+    responses(
+        (status = 204, description = "User, their files and storage deleted"),
+        (status = 400, description = "Admins cannot delete themselves"),
+        (status = 403, description = "Admin privileges required"),
+        (status = 404, description = "User not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn delete_user(
+    AdminUser(claims): AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AuthError> {
+    if id == claims.user_id {
+        return Err(AuthError::CannotModifySelf);
+    }
+
+    // No real user id could escape its own storage prefix
+    storage::validate_key(&id).map_err(|_| AuthError::UserNotFound)?;
+    let volume = match state.storage.for_user(&state.db_pool, &id).await {
+        Ok(volume) => volume,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(AuthError::UserNotFound),
+        Err(_) => return Err(AuthError::InternalError),
+    };
+
+    // Collected up front: the rows disappear with the user
+    let file_ids: Vec<String> = sqlx::query_scalar("SELECT id FROM files WHERE user_id = $1")
+        .bind(&id)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+    let deleted = UserRepository::new(state.db_pool.clone())
+        .delete_user(&id)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+    if !deleted {
+        return Err(AuthError::UserNotFound);
+    }
+
+    // Every blob a user owns is stored under their id
+    if let Err(e) = volume.delete_prefix(&id).await {
+        tracing::warn!(error = ?e, user_id = %id, "Failed to remove user storage");
+    }
+    for file_id in &file_ids {
+        thumbnails::invalidate(volume, file_id).await;
+    }
+
+    audit::record(&state.db_pool, Some(&claims.user_id), audit::USER_DELETED, Some(&id), None);
+    tracing::info!(admin_id = %claims.user_id, user_id = %id, files = file_ids.len(), "Deleted user");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/{id}/role",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "User ID")
+    ),
+    request_body = SetRoleRequest,
+    responses(
+        (status = 200, description = "Role updated", body = UserResponse),
+        (status = 400, description = "Invalid role, or an admin changing their own role"),
+        (status = 403, description = "Admin privileges required"),
+        (status = 404, description = "User not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn set_user_role(
+    AdminUser(claims): AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<SetRoleRequest>,
+) -> Result<Json<UserResponse>, AuthError> {
+    if payload.role != ROLE_USER && payload.role != ROLE_ADMIN {
+        return Err(AuthError::InvalidRole);
+    }
+    // Keeps at least one admin around: the caller can't demote themselves
+    if id == claims.user_id {
+        return Err(AuthError::CannotModifySelf);
+    }
+
+    let user = UserRepository::new(state.db_pool.clone())
+        .set_role(&id, &payload.role)
+        .await
+        .map_err(|e| match e {
+            UserError::UserNotFound => AuthError::UserNotFound,
+            _ => AuthError::InternalError,
+        })?;
+
+    let role = Some(payload.role.as_str());
+    audit::record(&state.db_pool, Some(&claims.user_id), audit::ROLE_CHANGED, Some(&id), role);
+    Ok(Json(user.into()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/{id}/suspend",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "User ID")
+    ),
+    responses(
+        (status = 200, description = "User suspended; their files are kept", body = UserResponse),
+        (status = 400, description = "Admins cannot suspend themselves"),
+        (status = 403, description = "Admin privileges required"),
+        (status = 404, description = "User not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn suspend_user(
+    AdminUser(claims): AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UserResponse>, AuthError> {
+    if id == claims.user_id {
+        return Err(AuthError::CannotModifySelf);
+    }
+
+    let user = set_active(&state, &id, false).await?;
+    // Reactivating doesn't bring the old sessions back
+    UserRepository::new(state.db_pool.clone())
+        .revoke_tokens(&id)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+    audit::record(&state.db_pool, Some(&claims.user_id), audit::USER_SUSPENDED, Some(&id), None);
+    tracing::info!(admin_id = %claims.user_id, user_id = %id, "Suspended user");
+    Ok(Json(user))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/{id}/reactivate",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "User ID")
+    ),
+    responses(
+        (status = 200, description = "User reactivated", body = UserResponse),
+        (status = 403, description = "Admin privileges required"),
+        (status = 404, description = "User not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn reactivate_user(
+    AdminUser(claims): AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UserResponse>, AuthError> {
+    let user = set_active(&state, &id, true).await?;
+    audit::record(&state.db_pool, Some(&claims.user_id), audit::USER_REACTIVATED, Some(&id), None);
+    tracing::info!(admin_id = %claims.user_id, user_id = %id, "Reactivated user");
+    Ok(Json(user))
+}
+
+async fn set_active(state: &AppState, id: &str, is_active: bool) -> Result<UserResponse, AuthError> {
+    let user = UserRepository::new(state.db_pool.clone())
+        .set_active(id, is_active)
+        .await
+        .map_err(|e| match e {
+            UserError::UserNotFound => AuthError::UserNotFound,
+            _ => AuthError::InternalError,
+        })?;
+
+    Ok(user.into())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/files",
+    tag = "admin",
+    params(AdminFileQuery),
+    responses(
+        (status = 200, description = "One page of full file records across all users, newest first", body = AdminFileListResponse),
+        (status = 403, description = "Admin privileges required")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_files(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<AdminFileQuery>,
+) -> Result<Json<AdminFileListResponse>, AuthError> {
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(50).clamp(1, 500);
+
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM files");
+    push_owner_filter(&mut count, &query);
+    let total_files = count
+        .build_query_scalar::<i64>()
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+    let mut list = QueryBuilder::new("SELECT * FROM files");
+    push_owner_filter(&mut list, &query);
+    list.push(" ORDER BY created_at DESC, id DESC LIMIT ")
+        .push_bind(page_size)
+        .push(" OFFSET ")
+        .push_bind((page - 1) * page_size);
+    let mut files = list
+        .build_query_as::<File>()
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+    FileRepository::new(state.db_pool.clone())
+        .load_tags(&mut files)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+    Ok(Json(AdminFileListResponse {
+        files,
+        page,
+        page_size,
+        total_files,
+        total_pages: (total_files as f64 / page_size as f64).ceil() as i64,
+    }))
+}
+
+fn push_owner_filter(builder: &mut QueryBuilder<'static, Db>, query: &AdminFileQuery) {
+    if let Some(user_id) = &query.user_id {
+        builder.push(" WHERE user_id = ").push_bind(user_id.clone());
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/files/{id}",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    responses(
+        (status = 200, description = "The full file record, including its storage path and encryption details", body = File),
+        (status = 403, description = "Admin privileges required"),
+        (status = 404, description = "File not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_file(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<File>, AuthError> {
+    let file = sqlx::query_as::<_, File>("SELECT * FROM files WHERE id = $1")
+        .bind(&id)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?
+        .ok_or(AuthError::FileNotFound)?;
+
+    let mut files = [file];
+    FileRepository::new(state.db_pool.clone())
+        .load_tags(&mut files)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+    let [file] = files;
+    Ok(Json(file))
+}
@@ -0,0 +1,224 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::auth::{AuthError, Claims};
+use crate::db::DbPool;
+use crate::user::{User, generate_token, hash_token};
+
+/// Marks a bearer credential as an API key rather than a JWT
+pub const API_KEY_PREFIX: &str = "tk_";
+
+/// Characters of the key kept in clear to identify it in listings
+const DISPLAY_PREFIX_LEN: usize = 10;
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ApiKey {
+    pub id: String,
+    #[serde(skip_serializing)]
+    pub user_id: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    /// Leading characters of the key
+    pub prefix: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    /// Label to recognise the key by (1-100 characters)
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    /// The key itself; shown only in this response
+    pub key: String,
+}
+
+pub struct ApiKeyRepository {
+    pool: DbPool,
+}
+
+impl ApiKeyRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Create a key, returning its record and the plaintext key
+    pub async fn create(&self, user_id: &str, name: &str) -> Result<(ApiKey, String), AuthError> {
+        let key = format!("{}{}", API_KEY_PREFIX, generate_token());
+        let api_key = ApiKey {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            name: name.to_string(),
+            key_hash: hash_token(&key),
+            prefix: key[..DISPLAY_PREFIX_LEN].to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            last_used_at: None,
+        };
+
+        sqlx::query(
+            "INSERT INTO api_keys (id, user_id, name, key_hash, prefix, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&api_key.id)
+        .bind(&api_key.user_id)
+        .bind(&api_key.name)
+        .bind(&api_key.key_hash)
+        .bind(&api_key.prefix)
+        .bind(&api_key.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+        Ok((api_key, key))
+    }
+
+    pub async fn list(&self, user_id: &str) -> Result<Vec<ApiKey>, AuthError> {
+        sqlx::query_as::<_, ApiKey>(
+            "SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC, id",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::InternalError)
+    }
+
+    /// Revoke a key; returns whether the user had it
+    pub async fn delete(&self, id: &str, user_id: &str) -> Result<bool, AuthError> {
+        let result = sqlx::query("DELETE FROM api_keys WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::InternalError)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Resolve a presented key to its owner, recording the use if `record_use`
+    pub async fn authenticate(&self, key: &str, record_use: bool) -> Result<Option<User>, AuthError> {
+        let user_id = if record_use {
+            sqlx::query_scalar::<_, String>(
+                "UPDATE api_keys SET last_used_at = $1 WHERE key_hash = $2 RETURNING user_id",
+            )
+            .bind(chrono::Utc::now().to_rfc3339())
+            .bind(hash_token(key))
+            .fetch_optional(&self.pool)
+            .await
+        } else {
+            sqlx::query_scalar::<_, String>("SELECT user_id FROM api_keys WHERE key_hash = $1")
+                .bind(hash_token(key))
+                .fetch_optional(&self.pool)
+                .await
+        }
+        .map_err(|_| AuthError::InternalError)?;
+
+        let Some(user_id) = user_id else {
+            return Ok(None);
+        };
+
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(&user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| AuthError::InternalError)
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/api-keys",
+    tag = "auth",
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 201, description = "API key created; `key` is not shown again", body = CreatedApiKey),
+        (status = 400, description = "Invalid name"),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn create_api_key(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), AuthError> {
+    let name = payload.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Err(AuthError::InvalidApiKeyName);
+    }
+
+    let (api_key, key) = ApiKeyRepository::new(state.db_pool.clone())
+        .create(&claims.user_id, name)
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(CreatedApiKey { api_key, key })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/api-keys",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The user's API keys (without the keys themselves)", body = [ApiKey]),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_api_keys(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ApiKey>>, AuthError> {
+    let api_keys = ApiKeyRepository::new(state.db_pool.clone())
+        .list(&claims.user_id)
+        .await?;
+
+    Ok(Json(api_keys))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/auth/api-keys/{id}",
+    tag = "auth",
+    params(
+        ("id" = String, Path, description = "API key ID")
+    ),
+    responses(
+        (status = 204, description = "API key revoked"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "API key not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn revoke_api_key(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AuthError> {
+    let revoked = ApiKeyRepository::new(state.db_pool.clone())
+        .delete(&id, &claims.user_id)
+        .await?;
+
+    if revoked {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AuthError::ApiKeyNotFound)
+    }
+}
@@ -0,0 +1,195 @@
+//! Audit trail of security-relevant actions, enabled with `AUDIT_LOG=true`.
+//!
+//! Handlers call `record`, which returns immediately: the row is written by a spawned
+//! task, and a write that fails is logged at error level with the full event so it
+//! can still be recovered from the server log.
+
+use std::sync::LazyLock;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, QueryBuilder};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::auth::{AdminUser, AuthError};
+use crate::client_ip;
+use crate::db::{Db, DbPool};
+use crate::usage::parse_timestamp;
+
+pub const LOGIN_SUCCEEDED: &str = "login.succeeded";
+pub const LOGIN_FAILED: &str = "login.failed";
+pub const SIGNUP: &str = "signup";
+pub const PASSWORD_RESET: &str = "password.reset";
+pub const TOKENS_REVOKED: &str = "tokens.revoked";
+pub const FILE_UPLOADED: &str = "file.uploaded";
+pub const FILE_DELETED: &str = "file.deleted";
+pub const SHARE_CREATED: &str = "share.created";
+pub const USER_DELETED: &str = "admin.user_deleted";
+pub const ROLE_CHANGED: &str = "admin.role_changed";
+pub const USER_SUSPENDED: &str = "admin.user_suspended";
+pub const USER_REACTIVATED: &str = "admin.user_reactivated";
+pub const INVITE_CREATED: &str = "admin.invite_created";
+pub const MAINTENANCE_CHANGED: &str = "admin.maintenance_changed";
+
+static ENABLED: LazyLock<bool> = LazyLock::new(|| crate::env_flag("AUDIT_LOG"));
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct AuditEvent {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub detail: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+pub struct AuditQuery {
+    /// Only events by this user
+    pub user_id: Option<String>,
+    /// Only this action (e.g. `login.failed`)
+    pub action: Option<String>,
+    /// Only events at or after this RFC 3339 timestamp
+    pub since: Option<String>,
+    /// Only events before this RFC 3339 timestamp
+    pub until: Option<String>,
+    /// 1-based page number (default 1)
+    pub page: Option<i64>,
+    /// Events per page (default 50, max 500)
+    pub page_size: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditLogResponse {
+    /// Newest first
+    pub events: Vec<AuditEvent>,
+    pub page: i64,
+    pub page_size: i64,
+    pub total_events: i64,
+    pub total_pages: i64,
+}
+
+/// Append an event to the audit log without waiting for the write
+pub fn record(
+    pool: &DbPool,
+    user_id: Option<&str>,
+    action: &'static str,
+    target: Option<&str>,
+    detail: Option<&str>,
+) {
+    if !*ENABLED {
+        return;
+    }
+
+    let event = AuditEvent {
+        id: uuid::Uuid::new_v4().to_string(),
+        user_id: user_id.map(str::to_string),
+        action: action.to_string(),
+        target: target.map(str::to_string),
+        detail: detail.map(str::to_string),
+        ip: client_ip::current().map(|ip| ip.to_string()),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+    let pool = pool.clone();
+
+    tokio::spawn(async move {
+        let result = sqlx::query(
+            "INSERT INTO audit_log (id, user_id, action, target, detail, ip, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&event.id)
+        .bind(&event.user_id)
+        .bind(&event.action)
+        .bind(&event.target)
+        .bind(&event.detail)
+        .bind(&event.ip)
+        .bind(&event.created_at)
+        .execute(&pool)
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!(error = ?e, event = ?event, "Failed to write audit event");
+        }
+    });
+}
+
+/// Append the `WHERE` clause for `query`'s filters
+fn push_filter(
+    builder: &mut QueryBuilder<'static, Db>,
+    query: &AuditQuery,
+    since: Option<String>,
+    until: Option<String>,
+) {
+    builder.push(" WHERE 1 = 1");
+    if let Some(user_id) = &query.user_id {
+        builder.push(" AND user_id = ").push_bind(user_id.clone());
+    }
+    if let Some(action) = &query.action {
+        builder.push(" AND action = ").push_bind(action.clone());
+    }
+    if let Some(since) = since {
+        builder.push(" AND created_at >= ").push_bind(since);
+    }
+    if let Some(until) = until {
+        builder.push(" AND created_at < ").push_bind(until);
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/audit",
+    tag = "admin",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "One page of audit events, newest first", body = AuditLogResponse),
+        (status = 400, description = "Invalid timestamp"),
+        (status = 403, description = "Admin privileges required")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_audit_events(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditLogResponse>, AuthError> {
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(50).clamp(1, 500);
+    let parse = |value: &str| parse_timestamp(value).map_err(|_| AuthError::InvalidTimestamp);
+    let since = query.since.as_deref().map(parse).transpose()?;
+    let until = query.until.as_deref().map(parse).transpose()?;
+
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM audit_log");
+    push_filter(&mut count, &query, since.clone(), until.clone());
+    let total_events = count
+        .build_query_scalar::<i64>()
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+    let mut list = QueryBuilder::new("SELECT * FROM audit_log");
+    push_filter(&mut list, &query, since, until);
+    list.push(" ORDER BY created_at DESC, id DESC LIMIT ")
+        .push_bind(page_size)
+        .push(" OFFSET ")
+        .push_bind((page - 1) * page_size);
+    let events = list
+        .build_query_as::<AuditEvent>()
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+    Ok(Json(AuditLogResponse {
+        events,
+        page,
+        page_size,
+        total_events,
+        total_pages: (total_events as f64 / page_size as f64).ceil() as i64,
+    }))
+}
@@ -1,39 +1,91 @@
+use std::cell::RefCell;
 use std::fmt::Display;
+use std::sync::LazyLock;
 
 use axum::{
     Json,
-    extract::{FromRequestParts, State},
-    http::{StatusCode, header, request::Parts},
+    extract::{FromRequestParts, Query, Request, State},
+    http::{HeaderName, HeaderValue, StatusCode, header, request::Parts},
+    middleware::Next,
     response::{IntoResponse, Response},
 };
 use ed25519_dalek::SigningKey;
+use hkdf::Hkdf;
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use pkcs8::EncodePrivateKey;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use utoipa::ToSchema;
+use sha2::Sha256;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::user::{CreateUserRequest, UserRepository, UserResponse};
+use crate::user::{
+    CreateUserRequest, PasswordResetConfirm, PasswordResetRequest, ROLE_ADMIN,
+    UpdateProfileRequest, User, UserError, UserRepository, UserResponse,
+};
 use crate::AppState;
+use crate::api_keys::{API_KEY_PREFIX, ApiKeyRepository};
+use crate::audit;
+use crate::db::DbPool;
+use crate::invites::{InviteRepository, SIGNUP_MODE, SignupMode};
+use crate::mailer;
 
 pub struct Keys {
     pub(crate) encoding: EncodingKey,
     pub(crate) decoding: DecodingKey,
+    /// What tokens are signed with, and the only algorithm accepted when verifying
+    pub(crate) algorithm: Algorithm,
+}
+
+/// Shortest accepted `JWT_SECRET`, in bytes
+pub const MIN_SECRET_LEN: usize = 16;
+/// Shortest accepted `JWT_SECRET` with HS256, whose key is the secret itself (RFC 7518 §3.2)
+pub const MIN_HMAC_SECRET_LEN: usize = 32;
+
+/// HKDF context for the signing seed; changing it rotates every key
+const KEY_DERIVATION_INFO: &[u8] = b"trusty-jwt-ed25519-seed";
+
+/// The signing algorithm from `JWT_ALG`: `EdDSA` (default) or `HS256`. Panics on
+/// anything else, like a missing `JWT_SECRET` does.
+pub fn algorithm_from_env() -> Algorithm {
+    let value = std::env::var("JWT_ALG").unwrap_or_default();
+    match value.trim() {
+        "" => Algorithm::EdDSA,
+        alg if alg.eq_ignore_ascii_case("EdDSA") => Algorithm::EdDSA,
+        alg if alg.eq_ignore_ascii_case("HS256") => Algorithm::HS256,
+        alg => panic!("JWT_ALG must be EdDSA or HS256, not {:?}", alg),
+    }
 }
 
 impl Keys {
-    pub fn new(secret: &[u8]) -> Self {
-        // 1. Deterministically derive the Ed25519 key pair from the secret
-        let mut seed = [0u8; 32];
-        let len = secret.len().min(32);
-        seed[..len].copy_from_slice(&secret[..len]);
+    /// Keys for `algorithm`, which must be EdDSA or HS256
+    pub fn new(secret: &[u8], algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::EdDSA => Self::ed25519(secret),
+            // Shared with other services, so used as is rather than derived
+            Algorithm::HS256 => Self {
+                encoding: EncodingKey::from_secret(secret),
+                decoding: DecodingKey::from_secret(secret),
+                algorithm,
+            },
+            other => panic!("unsupported JWT algorithm {:?}", other),
+        }
+    }
 
-        // If secret is short, repeat pattern to fill 32 bytes
-        if len < 32 {
-            for i in len..32 {
-                seed[i] = seed[i % len];
-            }
+    /// Shortest secret accepted for `algorithm`
+    pub fn min_secret_len(algorithm: Algorithm) -> usize {
+        match algorithm {
+            Algorithm::HS256 => MIN_HMAC_SECRET_LEN,
+            _ => MIN_SECRET_LEN,
         }
+    }
+
+    fn ed25519(secret: &[u8]) -> Self {
+        // 1. Deterministically derive the Ed25519 key pair from the secret.
+        // HKDF spreads the whole secret over the 32-byte seed instead of padding it.
+        let mut seed = [0u8; 32];
+        Hkdf::<Sha256>::new(None, secret)
+            .expand(KEY_DERIVATION_INFO, &mut seed)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
 
         let signing_key = SigningKey::from_bytes(&seed);
         let verifying_key = signing_key.verifying_key();
@@ -51,15 +103,45 @@ impl Keys {
         Self {
             encoding: EncodingKey::from_ed_der(private_key_der.as_bytes()),
             decoding: DecodingKey::from_ed_der(public_key_bytes),
+            algorithm: Algorithm::EdDSA,
         }
     }
+
+    /// Header for tokens signed with these keys
+    pub fn header(&self) -> Header {
+        Header::new(self.algorithm)
+    }
+
+    /// Validation that accepts the configured algorithm only, whatever a token's header
+    /// claims, so a token can't switch to one the keys weren't meant for
+    pub fn validation(&self) -> Validation {
+        // `Validation::new` allows exactly the one algorithm given
+        Validation::new(self.algorithm)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Claims {
     pub user_id: String,
     pub username: String,
+    /// Expiry (unix time); 0 when authenticated with a non-expiring API key
     pub exp: usize,
+    /// Issue time (unix time)
+    #[serde(default)]
+    pub iat: usize,
+    /// When the session began (unix time); refreshed tokens keep it, so a session
+    /// can't be extended past `SESSION_MAX_LIFETIME_SECONDS`
+    #[serde(default)]
+    pub auth_time: usize,
+    /// The user's `token_version` when issued; bumping it revokes the token
+    #[serde(default)]
+    pub token_version: i64,
+    /// Issuer, from `JWT_ISSUER`; absent when that is unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// Audience, from `JWT_AUDIENCE`; absent when that is unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
 impl Display for Claims {
@@ -79,6 +161,15 @@ pub struct AuthBody {
     pub user: UserResponse,
 }
 
+/// Whether the presented token is still accepted
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TokenStatus {
+    /// Always true; invalid tokens get `401` instead
+    pub valid: bool,
+    /// Seconds until the token expires; `null` for API keys, which don't
+    pub expires_in: Option<u64>,
+}
+
 impl AuthBody {
     pub fn new(access_token: String, user: UserResponse) -> Self {
         Self {
@@ -89,6 +180,84 @@ impl AuthBody {
     }
 }
 
+/// Persistent lockout after consecutive failed logins, from `LOGIN_MAX_FAILURES`
+/// (default 5, 0 disables) and `LOGIN_LOCKOUT_SECONDS` (default 900)
+struct LockoutPolicy {
+    max_failures: i64,
+    lockout: chrono::Duration,
+}
+
+static LOCKOUT_POLICY: LazyLock<LockoutPolicy> = LazyLock::new(|| LockoutPolicy {
+    max_failures: std::env::var("LOGIN_MAX_FAILURES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5),
+    lockout: chrono::Duration::seconds(
+        std::env::var("LOGIN_LOCKOUT_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(900),
+    ),
+});
+
+/// Sliding sessions: a token expires after `SESSION_IDLE_TIMEOUT_SECONDS` (default 86400)
+/// without use, and no session outlives `SESSION_MAX_LIFETIME_SECONDS` (default 604800)
+/// from its login. Setting both to the same value turns sliding off.
+struct SessionPolicy {
+    idle_timeout: i64,
+    max_lifetime: i64,
+}
+
+static SESSION_POLICY: LazyLock<SessionPolicy> = LazyLock::new(|| {
+    let seconds = |name: &str, default: i64| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|&v| v > 0)
+            .unwrap_or(default)
+    };
+    let idle_timeout = seconds("SESSION_IDLE_TIMEOUT_SECONDS", 86400);
+    SessionPolicy {
+        idle_timeout,
+        max_lifetime: seconds("SESSION_MAX_LIFETIME_SECONDS", 604800).max(idle_timeout),
+    }
+});
+
+/// Optional `iss` and `aud` for tokens, from `JWT_ISSUER` and `JWT_AUDIENCE`. Each one
+/// set is put in every token and required to match, so tokens can't be swapped with
+/// other services that share the secret.
+struct TokenScope {
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+static TOKEN_SCOPE: LazyLock<TokenScope> = LazyLock::new(|| {
+    let value = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    TokenScope {
+        issuer: value("JWT_ISSUER"),
+        audience: value("JWT_AUDIENCE"),
+    }
+});
+
+/// Response header carrying a replacement for a token past half its lifetime
+pub static X_REFRESHED_TOKEN: HeaderName = HeaderName::from_static("x-refreshed-token");
+
+tokio::task_local! {
+    static REFRESHED_TOKEN: RefCell<Option<String>>;
+}
+
+/// Whole seconds until an RFC 3339 time, or None if it has passed
+fn seconds_until(timestamp: &str) -> Option<u64> {
+    let until = chrono::DateTime::parse_from_rfc3339(timestamp).ok()?;
+    let remaining = until.signed_duration_since(chrono::Utc::now()).num_seconds();
+    (remaining > 0).then_some(remaining as u64)
+}
+
 #[derive(Debug, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub username: String,
@@ -103,77 +272,410 @@ pub enum AuthError {
     InvalidToken,
     UsernameExists,
     InvalidUsername,
+    InvalidUsernameCharacters,
+    UsernameReserved,
+    UsernameBlocked,
     InvalidPassword,
-    StorageError,
+    Forbidden,
+    UserNotFound,
+    FileNotFound,
+    InvalidRole,
+    CannotModifySelf,
+    InvalidEmail,
+    EmailExists,
+    EmailNotSet,
+    EmailAlreadyVerified,
+    InvalidVerificationToken,
+    InvalidResetToken,
+    /// Too many failed logins; retry after this many seconds
+    AccountLocked(u64),
+    AccountSuspended,
+    InvalidApiKeyName,
+    ApiKeyNotFound,
+    SignupClosed,
+    InvalidInviteCode,
+    InvalidInviteExpiry,
+    InvalidTimestamp,
+    /// Signup problems keyed by the field they concern, in form order
+    InvalidFields(Vec<(&'static str, AuthError)>),
     InternalError,
 }
 
-impl IntoResponse for AuthError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+impl AuthError {
+    fn status_and_message(&self) -> (StatusCode, &'static str) {
+        match self {
             AuthError::WrongCredentials => (StatusCode::UNAUTHORIZED, "Wrong credentials"),
-            AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
+            AuthError::MissingCredentials => (StatusCode::UNAUTHORIZED, "Missing credentials"),
             AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
-            AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
+            AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
             AuthError::UsernameExists => (StatusCode::BAD_REQUEST, "Username already exists"),
             AuthError::InvalidUsername => (
                 StatusCode::BAD_REQUEST,
                 "Invalid username (must be 3-50 characters)",
             ),
+            AuthError::InvalidUsernameCharacters => (
+                StatusCode::BAD_REQUEST,
+                "Invalid username (only letters, digits, _ and - are allowed)",
+            ),
+            AuthError::UsernameReserved => (StatusCode::BAD_REQUEST, "Username is reserved"),
+            AuthError::UsernameBlocked => (StatusCode::BAD_REQUEST, "Username is not allowed"),
             AuthError::InvalidPassword => (
                 StatusCode::BAD_REQUEST,
                 "Invalid password (must be at least 6 characters)",
             ),
-            AuthError::StorageError => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to create user storage",
+            AuthError::Forbidden => (StatusCode::FORBIDDEN, "Admin privileges required"),
+            AuthError::UserNotFound => (StatusCode::NOT_FOUND, "User not found"),
+            AuthError::FileNotFound => (StatusCode::NOT_FOUND, "File not found"),
+            AuthError::InvalidRole => (StatusCode::BAD_REQUEST, "Invalid role (must be user or admin)"),
+            AuthError::CannotModifySelf => (
+                StatusCode::BAD_REQUEST,
+                "Admins cannot delete, suspend or change the role of their own account",
+            ),
+            AuthError::InvalidEmail => (StatusCode::BAD_REQUEST, "Invalid email address"),
+            AuthError::EmailExists => (StatusCode::BAD_REQUEST, "Email already in use"),
+            AuthError::EmailNotSet => (StatusCode::BAD_REQUEST, "No email address on this account"),
+            AuthError::EmailAlreadyVerified => (StatusCode::BAD_REQUEST, "Email already verified"),
+            AuthError::InvalidVerificationToken => (
+                StatusCode::BAD_REQUEST,
+                "Invalid or expired verification token",
+            ),
+            AuthError::InvalidResetToken => (StatusCode::BAD_REQUEST, "Invalid or expired reset token"),
+            AuthError::AccountLocked(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Account temporarily locked after repeated failed logins",
+            ),
+            AuthError::AccountSuspended => (StatusCode::FORBIDDEN, "Account suspended"),
+            AuthError::InvalidApiKeyName => (
+                StatusCode::BAD_REQUEST,
+                "Invalid API key name (must be 1-100 characters)",
+            ),
+            AuthError::ApiKeyNotFound => (StatusCode::NOT_FOUND, "API key not found"),
+            AuthError::SignupClosed => (StatusCode::FORBIDDEN, "Registration is closed"),
+            AuthError::InvalidInviteCode => (
+                StatusCode::FORBIDDEN,
+                "A valid, unused invite code is required to sign up",
+            ),
+            AuthError::InvalidInviteExpiry => (
+                StatusCode::BAD_REQUEST,
+                "Invalid expiry (must be a positive number of seconds)",
+            ),
+            AuthError::InvalidTimestamp => (
+                StatusCode::BAD_REQUEST,
+                "Invalid timestamp (expected RFC 3339)",
+            ),
+            // The first problem doubles as the summary for clients that only read `error`
+            AuthError::InvalidFields(errors) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                errors.first().map_or("Invalid input", |(_, error)| error.status_and_message().1),
             ),
             AuthError::InternalError => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        // RFC 6750 challenge for requests that failed bearer authentication
+        let retry_after = match self {
+            AuthError::AccountLocked(seconds) => Some(seconds),
+            _ => None,
         };
-        let body = Json(json!({
+        let challenge = match self {
+            AuthError::MissingCredentials => Some("Bearer"),
+            AuthError::InvalidToken => Some("Bearer error=\"invalid_token\""),
+            _ => None,
+        };
+        let (status, error_message) = self.status_and_message();
+        let mut body = json!({
             "error": error_message,
-        }));
-        (status, body).into_response()
+            "request_id": crate::request_id::current(),
+        });
+        if let AuthError::InvalidFields(errors) = &self {
+            let fields: serde_json::Map<String, serde_json::Value> = errors
+                .iter()
+                .map(|(field, error)| (field.to_string(), error.status_and_message().1.into()))
+                .collect();
+            body["errors"] = fields.into();
+        }
+        let mut response = (status, Json(body)).into_response();
+        if let Some(challenge) = challenge {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+        }
+        if let Some(seconds) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
 
-impl<S> FromRequestParts<S> for Claims
-where
-    S: Send + Sync,
-{
+impl FromRequestParts<AppState> for Claims {
     type Rejection = AuthError;
 
-    fn from_request_parts(
+    async fn from_request_parts(
         parts: &mut Parts,
-        _state: &S,
-    ) -> impl std::future::Future<Output = Result<Self, Self::Rejection>> + Send {
-        async move {
-            // 1. Extract the header
-            let authorization = parts
-                .headers
-                .get(header::AUTHORIZATION)
-                .and_then(|v| v.to_str().ok())
-                .ok_or(AuthError::MissingCredentials)?;
-
-            // 2. Strip "Bearer " prefix
-            let token = authorization
-                .strip_prefix("Bearer ")
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // 1. Extract the token (header, or WebSocket fallbacks)
+        let token = extract_token(parts)?;
+
+        // API keys act as their owner, with no expiry
+        if token.starts_with(API_KEY_PREFIX) {
+            let user = ApiKeyRepository::new(state.db_pool.clone())
+                // Maintenance mode leaves `last_used_at` alone
+                .authenticate(&token, !state.maintenance.is_enabled())
+                .await?
                 .ok_or(AuthError::InvalidToken)?;
+            if !user.is_active {
+                return Err(AuthError::AccountSuspended);
+            }
+            return Ok(Claims {
+                user_id: user.id,
+                username: user.username,
+                exp: 0,
+                iat: chrono::Utc::now().timestamp() as usize,
+                auth_time: 0,
+                token_version: user.token_version,
+                iss: TOKEN_SCOPE.issuer.clone(),
+                aud: TOKEN_SCOPE.audience.clone(),
+            });
+        }
+
+        // 2. Decode & Validate
+        let keys = &crate::KEYS;
+        let mut validation = keys.validation();
+        validation.validate_exp = true;
+        let scope = &*TOKEN_SCOPE;
+        if let Some(issuer) = &scope.issuer {
+            validation.set_issuer(&[issuer]);
+            validation.required_spec_claims.insert("iss".to_string());
+        }
+        if let Some(audience) = &scope.audience {
+            validation.set_audience(&[audience]);
+            validation.required_spec_claims.insert("aud".to_string());
+        }
 
-            // 3. Decode & Validate
-            let keys = &crate::KEYS;
-            let mut validation = Validation::new(Algorithm::EdDSA);
-            validation.validate_exp = true;
-            // Ensure the validation algorithms match the key type
-            validation.algorithms = vec![Algorithm::EdDSA];
+        let token_data = decode::<Claims>(&token, &keys.decoding, &validation).map_err(|e| {
+            tracing::warn!(error = ?e, "Token decoding error");
+            AuthError::InvalidToken
+        })?;
+        let claims = token_data.claims;
+
+        // 3. Reject tokens of deleted users and revoked tokens. The user row is read on
+        // every request anyway, so the version check costs nothing extra and takes
+        // effect at once; caching it would leave revoked tokens usable until it expired
+        check_user(&state.db_pool, &claims).await?;
+
+        // 4. Slide the session forward once the token is past half its lifetime
+        if let Some(refreshed) = refreshed_claims(&claims) {
+            match sign_token(&refreshed) {
+                Ok(token) => {
+                    let _ = REFRESHED_TOKEN.try_with(|slot| *slot.borrow_mut() = Some(token));
+                }
+                Err(_) => tracing::warn!(user_id = %claims.user_id, "Failed to refresh token"),
+            }
+        }
 
-            let token_data = decode::<Claims>(token, &keys.decoding, &validation).map_err(|e| {
-                eprintln!("Token decoding error: {:?}", e);
-                AuthError::InvalidToken
-            })?;
+        Ok(claims)
+    }
+}
+
+/// Reject claims whose user is gone or suspended, or whose tokens were revoked since
+async fn check_user(pool: &DbPool, claims: &Claims) -> Result<(), AuthError> {
+    let user = UserRepository::new(pool.clone())
+        .find_by_id(&claims.user_id)
+        .await
+        .map_err(|_| AuthError::InternalError)?
+        .ok_or(AuthError::InvalidToken)?;
+    // Suspension takes effect on the next request, not when the token expires
+    if !user.is_active {
+        return Err(AuthError::AccountSuspended);
+    }
+    if claims.token_version != user.token_version {
+        return Err(AuthError::InvalidToken);
+    }
+    Ok(())
+}
 
-            Ok(token_data.claims)
+impl Claims {
+    /// Check claims accepted earlier again, for connections that outlive the request
+    /// that opened them: the token mustn't have expired since, nor its user been
+    /// deleted, suspended or signed out everywhere
+    pub async fn revalidate(&self, pool: &DbPool) -> Result<(), AuthError> {
+        if self.exp != 0 && (self.exp as i64) < chrono::Utc::now().timestamp() {
+            return Err(AuthError::InvalidToken);
         }
+        check_user(pool, self).await
+    }
+}
+
+fn sign_token(claims: &Claims) -> Result<String, AuthError> {
+    let keys = &crate::KEYS;
+    encode(&keys.header(), claims, &keys.encoding).map_err(|_| AuthError::TokenCreation)
+}
+
+/// Sign an access token for `user`, starting a new session
+fn issue_token(user: &User) -> Result<String, AuthError> {
+    let policy = &*SESSION_POLICY;
+    let now = chrono::Utc::now().timestamp();
+    let claims = Claims {
+        user_id: user.id.clone(),
+        username: user.username.clone(),
+        exp: (now + policy.idle_timeout) as usize,
+        iat: now as usize,
+        auth_time: now as usize,
+        token_version: user.token_version,
+        iss: TOKEN_SCOPE.issuer.clone(),
+        aud: TOKEN_SCOPE.audience.clone(),
+    };
+
+    sign_token(&claims)
+}
+
+/// Claims for a token replacing `claims`, if they are past half their lifetime and the
+/// session still has time left to extend into
+fn refreshed_claims(claims: &Claims) -> Option<Claims> {
+    let policy = &*SESSION_POLICY;
+    let now = chrono::Utc::now().timestamp();
+    let (iat, exp) = (claims.iat as i64, claims.exp as i64);
+    if now - iat < (exp - iat) / 2 {
+        return None;
+    }
+
+    // Tokens from before sliding sessions started their session when issued
+    let session_start = if claims.auth_time == 0 { iat } else { claims.auth_time as i64 };
+    let new_exp = (now + policy.idle_timeout).min(session_start + policy.max_lifetime);
+    if new_exp <= exp {
+        return None;
+    }
+
+    Some(Claims {
+        exp: new_exp as usize,
+        iat: now as usize,
+        auth_time: session_start as usize,
+        ..claims.clone()
+    })
+}
+
+/// Return the token refreshed while authenticating this request, if any, in
+/// `X-Refreshed-Token`; clients should use it from then on
+pub async fn attach_refreshed_token(req: Request, next: Next) -> Response {
+    let (token, mut response) = REFRESHED_TOKEN
+        .scope(RefCell::new(None), async {
+            let response = next.run(req).await;
+            (REFRESHED_TOKEN.with(|slot| slot.take()), response)
+        })
+        .await;
+
+    if let Some(value) = token.and_then(|token| HeaderValue::from_str(&token).ok()) {
+        response.headers_mut().insert(X_REFRESHED_TOKEN.clone(), value);
+    }
+    response
+}
+
+/// Subprotocol name browsers use to smuggle a token into a WebSocket upgrade:
+/// `Sec-WebSocket-Protocol: bearer, <token>`
+pub const BEARER_SUBPROTOCOL: &str = "bearer";
+
+/// Pull the bearer token from the `Authorization` header.
+///
+/// WebSocket upgrades can't carry that header from a browser, so for those requests
+/// the token is also accepted from the `access_token` query parameter or the
+/// `bearer` subprotocol.
+fn extract_token(parts: &Parts) -> Result<String, AuthError> {
+    if let Some(authorization) = parts.headers.get(header::AUTHORIZATION) {
+        let authorization = authorization.to_str().map_err(|_| AuthError::InvalidToken)?;
+        return authorization
+            .strip_prefix("Bearer ")
+            .map(str::to_string)
+            .ok_or(AuthError::InvalidToken);
+    }
+
+    let is_websocket = parts
+        .headers
+        .get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    if !is_websocket {
+        return Err(AuthError::MissingCredentials);
+    }
+
+    let from_query = parts.uri.query().and_then(|query| {
+        query.split('&').find_map(|pair| {
+            pair.strip_prefix("access_token=").map(str::to_string)
+        })
+    });
+    if let Some(token) = from_query {
+        return Ok(token);
+    }
+
+    parts
+        .headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|protocols| {
+            let mut protocols = protocols.split(',').map(str::trim);
+            match (protocols.next(), protocols.next()) {
+                (Some(BEARER_SUBPROTOCOL), Some(token)) => Some(token.to_string()),
+                _ => None,
+            }
+        })
+        .ok_or(AuthError::MissingCredentials)
+}
+
+/// Claims of an authenticated user whose account currently holds the admin role.
+///
+/// The role is looked up on every request rather than trusted from the token,
+/// so demoting an admin takes effect immediately.
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub Claims);
+
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+
+        let user = UserRepository::new(state.db_pool.clone())
+            .find_by_id(&claims.user_id)
+            .await
+            .map_err(|_| AuthError::InternalError)?
+            .ok_or(AuthError::InvalidToken)?;
+
+        if user.role != ROLE_ADMIN {
+            return Err(AuthError::Forbidden);
+        }
+
+        Ok(AdminUser(claims))
+    }
+}
+
+/// Map a failed `create_user` to the field-level signup response
+fn signup_error(e: UserError) -> AuthError {
+    let field_error = |e: UserError| match e {
+        UserError::UsernameExists => Some(("username", AuthError::UsernameExists)),
+        UserError::InvalidUsername => Some(("username", AuthError::InvalidUsername)),
+        UserError::InvalidUsernameCharacters => Some(("username", AuthError::InvalidUsernameCharacters)),
+        UserError::UsernameReserved => Some(("username", AuthError::UsernameReserved)),
+        UserError::UsernameBlocked => Some(("username", AuthError::UsernameBlocked)),
+        UserError::InvalidPassword => Some(("password", AuthError::InvalidPassword)),
+        UserError::InvalidEmail => Some(("email", AuthError::InvalidEmail)),
+        UserError::EmailExists => Some(("email", AuthError::EmailExists)),
+        _ => None,
+    };
+
+    match e {
+        UserError::InvalidFields(errors) => errors
+            .into_iter()
+            .map(|(_, e)| field_error(e))
+            .collect::<Option<Vec<_>>>()
+            .map_or(AuthError::InternalError, AuthError::InvalidFields),
+        // A username or email taken between the checks and the insert
+        e => field_error(e).map_or(AuthError::InternalError, |error| AuthError::InvalidFields(vec![error])),
     }
 }
 
@@ -183,8 +685,9 @@ where
     request_body = CreateUserRequest,
     tag = "auth",
     responses(
-        (status = 201, description = "User created successfully", body = AuthBody),
-        (status = 400, description = "Invalid input or username already exists"),
+        (status = 201, description = "User created successfully; a replay for a repeated Idempotency-Key has no access_token", body = AuthBody),
+        (status = 403, description = "Signup is closed, or needs a valid invite code"),
+        (status = 422, description = "Invalid or taken fields; `errors` maps each field to its problem"),
         (status = 500, description = "Internal server error")
     )
 )]
@@ -192,32 +695,42 @@ pub async fn signup(
     State(state): State<AppState>,
     Json(payload): Json<CreateUserRequest>,
 ) -> Result<(StatusCode, Json<AuthBody>), AuthError> {
+    let invite_repo = InviteRepository::new(state.db_pool.clone());
+    let invite_code = match *SIGNUP_MODE {
+        SignupMode::Open => None,
+        SignupMode::Closed => return Err(AuthError::SignupClosed),
+        SignupMode::Invite => {
+            let code = payload.code.as_deref().ok_or(AuthError::InvalidInviteCode)?;
+            if !invite_repo.consume(code).await? {
+                return Err(AuthError::InvalidInviteCode);
+            }
+            Some(code)
+        }
+    };
+
     let user_repo = UserRepository::new(state.db_pool.clone());
 
-    let user = user_repo
-        .create_user(&payload.username, &payload.password)
-        .await
-        .map_err(|e| match e {
-            crate::user::UserError::UsernameExists => AuthError::UsernameExists,
-            crate::user::UserError::InvalidUsername => AuthError::InvalidUsername,
-            crate::user::UserError::InvalidPassword => AuthError::InvalidPassword,
-            _ => AuthError::InternalError,
-        })?;
+    let created = user_repo
+        .create_user(&payload.username, &payload.password, payload.email.as_deref(), state.storage.len())
+        .await;
+    let user = match created {
+        Ok(user) => user,
+        Err(e) => {
+            // A rejected username or password shouldn't cost the invitee their code
+            if let Some(code) = invite_code {
+                invite_repo.release(code).await?;
+            }
+            return Err(signup_error(e));
+        }
+    };
 
-    let bucket_path = state.storage_root.join(&user.id);
-    tokio::fs::create_dir_all(&bucket_path)
-        .await
-        .map_err(|_| AuthError::StorageError)?;
+    if let Some(code) = invite_code {
+        invite_repo.set_used_by(code, &user.id).await?;
+    }
 
-    let claims = Claims {
-        user_id: user.id.clone(),
-        username: user.username.clone(),
-        exp: (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
-    };
+    audit::record(&state.db_pool, Some(&user.id), audit::SIGNUP, None, None);
 
-    let header = Header::new(Algorithm::EdDSA);
-    let token = encode(&header, &claims, &crate::KEYS.encoding)
-        .map_err(|_| AuthError::TokenCreation)?;
+    let token = issue_token(&user)?;
 
     let user_response: UserResponse = user.into();
     Ok((
@@ -234,6 +747,8 @@ pub async fn signup(
     responses(
         (status = 200, description = "Login successful", body = AuthBody),
         (status = 401, description = "Invalid credentials"),
+        (status = 403, description = "Account suspended"),
+        (status = 429, description = "Account locked after repeated failures; see Retry-After"),
         (status = 500, description = "Internal server error")
     )
 )]
@@ -246,26 +761,54 @@ pub async fn login(
     let user = user_repo
         .find_by_username(&payload.username)
         .await
-        .map_err(|_| AuthError::InternalError)?
-        .ok_or(AuthError::WrongCredentials)?;
+        .map_err(|_| AuthError::InternalError)?;
+    let Some(user) = user else {
+        let username = Some(payload.username.as_str());
+        audit::record(&state.db_pool, None, audit::LOGIN_FAILED, username, Some("unknown_user"));
+        return Err(AuthError::WrongCredentials);
+    };
+
+    // A locked account is refused before the password is even checked
+    if let Some(seconds) = user.locked_until.as_deref().and_then(seconds_until) {
+        audit::record(&state.db_pool, Some(&user.id), audit::LOGIN_FAILED, None, Some("locked"));
+        return Err(AuthError::AccountLocked(seconds));
+    }
 
     let is_valid = user_repo
         .verify_password(&user, &payload.password)
         .map_err(|_| AuthError::InternalError)?;
 
     if !is_valid {
+        let reason = Some("wrong_password");
+        audit::record(&state.db_pool, Some(&user.id), audit::LOGIN_FAILED, None, reason);
+        let policy = &*LOCKOUT_POLICY;
+        if policy.max_failures > 0 {
+            let lock_until = chrono::Utc::now() + policy.lockout;
+            let locked = user_repo
+                .record_failed_login(&user.id, policy.max_failures, &lock_until.to_rfc3339())
+                .await
+                .map_err(|_| AuthError::InternalError)?;
+            if locked.is_some() {
+                tracing::warn!(user_id = %user.id, "Account locked after repeated failed logins");
+                return Err(AuthError::AccountLocked(policy.lockout.num_seconds() as u64));
+            }
+        }
         return Err(AuthError::WrongCredentials);
     }
 
-    let claims = Claims {
-        user_id: user.id.clone(),
-        username: user.username.clone(),
-        exp: (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
-    };
+    // Checked only after the password, so the response doesn't reveal suspensions to strangers
+    if !user.is_active {
+        audit::record(&state.db_pool, Some(&user.id), audit::LOGIN_FAILED, None, Some("suspended"));
+        return Err(AuthError::AccountSuspended);
+    }
 
-    let header = Header::new(Algorithm::EdDSA);
-    let token = encode(&header, &claims, &crate::KEYS.encoding)
-        .map_err(|_| AuthError::TokenCreation)?;
+    user_repo
+        .clear_failed_logins(&user.id)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+    audit::record(&state.db_pool, Some(&user.id), audit::LOGIN_SUCCEEDED, None, None);
+
+    let token = issue_token(&user)?;
 
     let user_response: UserResponse = user.into();
     Ok(Json(AuthBody::new(token, user_response)))
@@ -277,8 +820,7 @@ pub async fn login(
     tag = "auth",
     responses(
         (status = 200, description = "Current user info", body = Claims),
-        (status = 400, description = "Invalid or missing token"),
-        (status = 401, description = "Unauthorized")
+        (status = 401, description = "Invalid or missing token")
     ),
     security(
         ("bearer_auth" = [])
@@ -287,3 +829,232 @@ pub async fn login(
 pub async fn me(claims: Claims) -> Result<Json<Claims>, AuthError> {
     Ok(Json(claims))
 }
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/verify",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Token is valid, with its remaining lifetime", body = TokenStatus),
+        (status = 401, description = "Invalid or missing token")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn verify_token(claims: Claims) -> Json<TokenStatus> {
+    let expires_in = (claims.exp != 0).then(|| {
+        let now = chrono::Utc::now().timestamp();
+        (claims.exp as i64 - now).max(0) as u64
+    });
+    Json(TokenStatus { valid: true, expires_in })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/logout-all",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Every token issued so far, including this one, is revoked and every API key deleted"),
+        (status = 401, description = "Invalid or missing token")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn logout_all(claims: Claims, State(state): State<AppState>) -> Result<StatusCode, AuthError> {
+    let revoked = UserRepository::new(state.db_pool.clone())
+        .revoke_credentials(&claims.user_id)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+    if !revoked {
+        return Err(AuthError::InvalidToken);
+    }
+
+    audit::record(&state.db_pool, Some(&claims.user_id), audit::TOKENS_REVOKED, None, None);
+    tracing::info!(user_id = %claims.user_id, "Signed out everywhere; existing tokens and API keys revoked");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/auth/profile",
+    request_body = UpdateProfileRequest,
+    tag = "auth",
+    responses(
+        (status = 200, description = "Profile updated; the new token carries the new username", body = AuthBody),
+        (status = 400, description = "Invalid username or username already exists"),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn update_profile(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(payload): Json<UpdateProfileRequest>,
+) -> Result<Json<AuthBody>, AuthError> {
+    let user = UserRepository::new(state.db_pool.clone())
+        .update_username(&claims.user_id, &payload.username)
+        .await
+        .map_err(|e| match e {
+            UserError::UsernameExists => AuthError::UsernameExists,
+            UserError::InvalidUsername => AuthError::InvalidUsername,
+            UserError::InvalidUsernameCharacters => AuthError::InvalidUsernameCharacters,
+            UserError::UsernameReserved => AuthError::UsernameReserved,
+            UserError::UsernameBlocked => AuthError::UsernameBlocked,
+            UserError::UserNotFound => AuthError::InvalidToken,
+            _ => AuthError::InternalError,
+        })?;
+
+    // The old token still names the old username, so hand out one that matches
+    let token = issue_token(&user)?;
+
+    let user_response: UserResponse = user.into();
+    Ok(Json(AuthBody::new(token, user_response)))
+}
+
+/// How long an email verification link stays valid
+const EMAIL_VERIFICATION_TTL_HOURS: i64 = 24;
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/verify-email/send",
+    tag = "auth",
+    responses(
+        (status = 202, description = "Verification link sent to the account's email"),
+        (status = 400, description = "No email on the account, or already verified"),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn send_email_verification(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AuthError> {
+    let user_repo = UserRepository::new(state.db_pool.clone());
+
+    let user = user_repo
+        .find_by_id(&claims.user_id)
+        .await
+        .map_err(|_| AuthError::InternalError)?
+        .ok_or(AuthError::InvalidToken)?;
+    let email = user.email.clone().ok_or(AuthError::EmailNotSet)?;
+    if user.email_verified {
+        return Err(AuthError::EmailAlreadyVerified);
+    }
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(EMAIL_VERIFICATION_TTL_HOURS);
+    let token = user_repo
+        .create_email_verification(&user, &expires_at.to_rfc3339())
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+    let base = std::env::var("PUBLIC_URL").unwrap_or_default();
+    let link = format!(
+        "{}/api/auth/verify-email/confirm?token={}",
+        base.trim_end_matches('/'),
+        token
+    );
+    mailer::send_verification_email(&email, &link).await;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/verify-email/confirm",
+    tag = "auth",
+    params(VerifyEmailQuery),
+    responses(
+        (status = 200, description = "Email verified", body = UserResponse),
+        (status = 400, description = "Invalid or expired verification token")
+    )
+)]
+pub async fn confirm_email_verification(
+    State(state): State<AppState>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> Result<Json<UserResponse>, AuthError> {
+    let user = UserRepository::new(state.db_pool.clone())
+        .confirm_email_verification(&query.token)
+        .await
+        .map_err(|e| match e {
+            UserError::InvalidVerificationToken => AuthError::InvalidVerificationToken,
+            _ => AuthError::InternalError,
+        })?;
+
+    Ok(Json(user.into()))
+}
+
+/// How long a password reset token stays valid
+const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/password-reset/request",
+    request_body = PasswordResetRequest,
+    tag = "auth",
+    responses(
+        (status = 200, description = "If the email belongs to an account with a verified email, a reset token was sent")
+    )
+)]
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetRequest>,
+) -> Result<StatusCode, AuthError> {
+    let user_repo = UserRepository::new(state.db_pool.clone());
+
+    let user = user_repo
+        .find_by_email(&payload.email)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+    // Same response whether or not the address is known, so it can't be used to probe for accounts.
+    // Unverified addresses are ignored: nobody has proven they own them.
+    if let Some(user) = user.filter(|user| user.email_verified) {
+        let expires_at = chrono::Utc::now() + chrono::Duration::minutes(PASSWORD_RESET_TTL_MINUTES);
+        let token = user_repo
+            .create_password_reset(&user.id, &expires_at.to_rfc3339())
+            .await
+            .map_err(|_| AuthError::InternalError)?;
+        mailer::send_password_reset_email(&payload.email, &token).await;
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/password-reset/confirm",
+    request_body = PasswordResetConfirm,
+    tag = "auth",
+    responses(
+        (status = 204, description = "Password changed; all existing tokens are revoked and API keys deleted"),
+        (status = 400, description = "Invalid or expired token, or invalid password")
+    )
+)]
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetConfirm>,
+) -> Result<StatusCode, AuthError> {
+    let user = UserRepository::new(state.db_pool.clone())
+        .reset_password(&payload.token, &payload.new_password)
+        .await
+        .map_err(|e| match e {
+            UserError::InvalidResetToken => AuthError::InvalidResetToken,
+            UserError::InvalidPassword => AuthError::InvalidPassword,
+            _ => AuthError::InternalError,
+        })?;
+
+    audit::record(&state.db_pool, Some(&user.id), audit::PASSWORD_RESET, None, None);
+    tracing::info!(user_id = %user.id, "Password reset; existing tokens revoked");
+    Ok(StatusCode::NO_CONTENT)
+}
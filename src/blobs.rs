@@ -0,0 +1,127 @@
+use sqlx::FromRow;
+
+use crate::db::{Db, DbPool};
+use crate::filemanager::{FileError, StoredBlob};
+use crate::storage::Storage;
+
+/// A stored object, shared by every `files` row with the same `storage_path`
+#[derive(Debug, Clone, FromRow)]
+pub struct Blob {
+    pub storage_path: String,
+    pub user_id: String,
+    pub content_hash: Option<String>,
+    pub server_encryption_algo: Option<String>,
+    pub server_encryption_nonce: Option<String>,
+    pub ref_count: i64,
+}
+
+pub struct BlobRepository {
+    pool: DbPool,
+}
+
+impl BlobRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Take another reference to a live blob. Returns false if it is already being
+    /// released, so a blob can't be revived after its count reached zero.
+    pub async fn add_reference(&self, storage_path: &str) -> Result<bool, FileError> {
+        let result = sqlx::query(
+            "UPDATE blobs SET ref_count = ref_count + 1 WHERE storage_path = $1 AND ref_count > 0",
+        )
+        .bind(storage_path)
+        .execute(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Drop one reference, deleting the stored object once nothing points at it
+    pub async fn release(&self, storage: &dyn Storage, storage_path: &str) -> Result<(), FileError> {
+        let remaining = sqlx::query_scalar::<_, i64>(
+            "UPDATE blobs SET ref_count = ref_count - 1 WHERE storage_path = $1 RETURNING ref_count",
+        )
+        .bind(storage_path)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        // Untracked paths (None) have a single owner by definition
+        if remaining.is_some_and(|count| count > 0) {
+            return Ok(());
+        }
+
+        sqlx::query("DELETE FROM blobs WHERE storage_path = $1 AND ref_count <= 0")
+            .bind(storage_path)
+            .execute(&self.pool)
+            .await
+            .map_err(FileError::DatabaseError)?;
+
+        storage
+            .delete(storage_path)
+            .await
+            .map_err(|_| FileError::StorageError)
+    }
+}
+
+/// Record a freshly written blob at `storage_path`, or take a reference to an identical
+/// one the user already has instead. Either way the returned blob carries one reference
+/// for the new row, and it is only kept if `tx` commits. Storage is left alone: when the
+/// returned path differs, or `tx` doesn't commit, the caller deletes the new object.
+pub async fn store_or_reuse(
+    tx: &mut sqlx::Transaction<'_, Db>,
+    user_id: &str,
+    storage_path: &str,
+    stored: StoredBlob,
+) -> Result<Blob, FileError> {
+    // Finds and references in one write, so SQLite takes the write lock up front
+    // instead of upgrading a read that another writer may have outdated
+    let existing = sqlx::query_as::<_, Blob>(
+        "UPDATE blobs SET ref_count = ref_count + 1
+         WHERE ref_count > 0 AND storage_path = (
+             SELECT storage_path FROM blobs
+             WHERE user_id = $1 AND content_hash = $2 AND ref_count > 0
+             LIMIT 1
+         )
+         RETURNING *",
+    )
+    .bind(user_id)
+    .bind(&stored.content_hash)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(FileError::DatabaseError)?;
+    if let Some(existing) = existing {
+        return Ok(existing);
+    }
+
+    let (server_encryption_algo, server_encryption_nonce) = match stored.encryption {
+        Some(encryption) => (Some(encryption.algorithm), Some(encryption.nonce)),
+        None => (None, None),
+    };
+    let blob = Blob {
+        storage_path: storage_path.to_string(),
+        user_id: user_id.to_string(),
+        content_hash: Some(stored.content_hash),
+        server_encryption_algo,
+        server_encryption_nonce,
+        ref_count: 1,
+    };
+
+    sqlx::query(
+        "INSERT INTO blobs (storage_path, user_id, content_hash, server_encryption_algo, server_encryption_nonce, ref_count)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(&blob.storage_path)
+    .bind(&blob.user_id)
+    .bind(&blob.content_hash)
+    .bind(&blob.server_encryption_algo)
+    .bind(&blob.server_encryption_nonce)
+    .bind(blob.ref_count)
+    .execute(&mut **tx)
+    .await
+    .map_err(FileError::DatabaseError)?;
+
+    Ok(blob)
+}
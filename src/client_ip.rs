@@ -0,0 +1,140 @@
+//! The address of the client behind a request, including behind reverse proxies.
+//!
+//! `X-Forwarded-For` is only honored when the connecting peer is listed in `TRUSTED_PROXY`
+//! (comma-separated addresses or CIDR ranges). The header is then read from the right,
+//! skipping hops that are trusted proxies themselves, so a client can't pick its own
+//! address by sending the header. Without `TRUSTED_PROXY` the peer address is used.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, Request as HttpRequest},
+    middleware::Next,
+    response::Response,
+};
+use tower_governor::{GovernorError, key_extractor::KeyExtractor};
+
+pub static TRUSTED_PROXIES: LazyLock<Vec<Network>> = LazyLock::new(|| {
+    std::env::var("TRUSTED_PROXY")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            Network::parse(entry)
+                .unwrap_or_else(|| panic!("TRUSTED_PROXY entry {:?} is not an address or CIDR range", entry))
+        })
+        .collect()
+});
+
+tokio::task_local! {
+    static CURRENT_CLIENT_IP: Option<IpAddr>;
+}
+
+/// Resolved client address, stored in request extensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// An address range from `TRUSTED_PROXY`
+#[derive(Debug, Clone, Copy)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    /// `addr` or `addr/prefix`
+    fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+            None => (value.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { addr: addr.to_canonical(), prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether `ip` is one of the proxies listed in `TRUSTED_PROXY`
+pub(crate) fn is_trusted(ip: IpAddr) -> bool {
+    TRUSTED_PROXIES.iter().any(|network| network.contains(ip))
+}
+
+/// The client address for a request from `peer`, following `X-Forwarded-For` back
+/// through trusted proxies
+fn resolve(peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    // Repeated headers form one list, in order
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        // Anything unparsable was written by an untrusted party; stop at the last good hop
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// The client address of the request being handled on this task, if known
+pub fn current() -> Option<IpAddr> {
+    CURRENT_CLIENT_IP.try_with(|ip| *ip).ok().flatten()
+}
+
+/// Resolve the client address once per request and expose it as a `ClientIp`
+/// extension and through `current`
+pub async fn resolve_client_ip(mut req: Request, next: Next) -> Response {
+    let ip = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| resolve(peer.ip(), req.headers()));
+
+    if let Some(ip) = ip {
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+    CURRENT_CLIENT_IP.scope(ip, next.run(req)).await
+}
+
+/// Rate-limit by resolved client address rather than by the proxy's
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIpKeyExtractor;
+
+impl KeyExtractor for ClientIpKeyExtractor {
+    type Key = IpAddr;
+
+    fn extract<T>(&self, req: &HttpRequest<T>) -> Result<Self::Key, GovernorError> {
+        req.extensions()
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| *ip)
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+}
@@ -0,0 +1,54 @@
+//! One directory for everything the server keeps on disk, from `DATA_DIR`.
+//!
+//! When set, the SQLite database (`trusty.db`) and the local storage root (`storage/`)
+//! live inside it unless `DATABASE_URL` or `STORAGE_ROOT`/`STORAGE_ROOTS` say otherwise,
+//! so a deployment only has to mount and point at one path. It is resolved to an
+//! absolute path at startup, so nothing depends on the working directory.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// SQLite file kept in the data directory
+#[cfg(not(feature = "postgres"))]
+const DATABASE_FILE: &str = "trusty.db";
+/// Storage root kept in the data directory
+const STORAGE_DIR: &str = "storage";
+
+/// The absolute data directory, created if missing. Panics when it can't be created
+/// or written to, since the server can't run without it.
+pub fn from_env() -> Option<PathBuf> {
+    let dir = std::env::var("DATA_DIR").ok().filter(|dir| !dir.trim().is_empty())?;
+
+    let resolved = prepare(Path::new(dir.trim()))
+        .unwrap_or_else(|e| panic!("DATA_DIR {:?} is not a usable directory: {}", dir, e));
+    tracing::info!("Using data directory {}", resolved.display());
+    Some(resolved)
+}
+
+fn prepare(dir: &Path) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let dir = dir.canonicalize()?;
+
+    // Permissions alone don't tell (read-only mounts, ACLs), so try a write
+    let probe = dir.join(".write-test");
+    std::fs::write(&probe, b"")
+        .map_err(|e| io::Error::new(e.kind(), format!("not writable: {}", e)))?;
+    std::fs::remove_file(&probe)?;
+
+    Ok(dir)
+}
+
+/// `DATABASE_URL` for the SQLite database in `dir`
+#[cfg(not(feature = "postgres"))]
+pub fn database_url(dir: &Path) -> String {
+    format!("sqlite://{}", dir.join(DATABASE_FILE).display())
+}
+
+/// Local storage root used when none is configured: in the data directory if there is
+/// one, otherwise `./storage`
+pub fn storage_root(dir: Option<&Path>) -> PathBuf {
+    match dir {
+        Some(dir) => dir.join(STORAGE_DIR),
+        None => PathBuf::from(".").join(STORAGE_DIR),
+    }
+}
@@ -0,0 +1,181 @@
+//! Database backend selection.
+//!
+//! SQLite is the default. Building with `--features postgres` switches every
+//! repository to PostgreSQL; `DATABASE_URL` must then be a `postgres://` URL.
+//! Queries are written with `$N` placeholders, which both backends accept.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use sqlx::migrate::Migrator;
+
+#[cfg(not(feature = "postgres"))]
+pub type Db = sqlx::Sqlite;
+#[cfg(feature = "postgres")]
+pub type Db = sqlx::Postgres;
+
+pub type DbPool = sqlx::Pool<Db>;
+
+#[cfg(not(feature = "postgres"))]
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+#[cfg(feature = "postgres")]
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// Columns the server's queries rely on, per table. Checked after migrating, because
+/// `CREATE TABLE IF NOT EXISTS` leaves a same-named table from elsewhere as it is.
+const EXPECTED_COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "users",
+        &[
+            "id", "username", "password_hash", "created_at", "role", "email", "email_verified",
+            "failed_login_attempts", "locked_until", "is_active", "storage_root", "token_version",
+        ],
+    ),
+    (
+        "files",
+        &[
+            "id", "user_id", "original_name", "mime_type", "size_bytes", "is_encrypted", "storage_path",
+            "created_at", "content_hash", "parent_id", "updated_at", "server_encryption_algo",
+            "server_encryption_nonce", "download_count", "last_accessed_at", "client_file_id", "version",
+            "thumbnail_failed", "modified_at",
+        ],
+    ),
+    ("folders", &["id", "user_id", "name", "parent_id", "created_at"]),
+    ("user_preferences", &["user_id", "default_folder_id", "auto_folder_rules"]),
+    (
+        "shares",
+        &["token", "file_id", "user_id", "created_at", "expires_at", "remaining_downloads", "password_hash"],
+    ),
+    (
+        "blobs",
+        &["storage_path", "user_id", "content_hash", "server_encryption_algo", "server_encryption_nonce", "ref_count"],
+    ),
+    ("email_verifications", &["token_hash", "user_id", "email", "created_at", "expires_at"]),
+    ("password_resets", &["token_hash", "user_id", "created_at", "expires_at"]),
+    ("api_keys", &["id", "user_id", "name", "key_hash", "prefix", "created_at", "last_used_at"]),
+    ("file_tags", &["file_id", "tag"]),
+    ("invite_codes", &["code_hash", "created_by", "created_at", "expires_at", "used_at", "used_by"]),
+    ("audit_log", &["id", "user_id", "action", "target", "detail", "ip", "created_at"]),
+    ("deleted_files", &["file_id", "user_id", "size_bytes", "deleted_at", "created_at"]),
+    (
+        "idempotency_keys",
+        &["scope", "idempotency_key", "status", "content_type", "body", "created_at", "expires_at"],
+    ),
+];
+
+#[cfg(not(feature = "postgres"))]
+const TABLE_COLUMNS_QUERY: &str = "SELECT name FROM pragma_table_info($1)";
+#[cfg(feature = "postgres")]
+const TABLE_COLUMNS_QUERY: &str = "SELECT column_name::TEXT FROM information_schema.columns
+     WHERE table_schema = current_schema() AND table_name = $1";
+
+/// Expected columns (`table.column`) the database lacks; a missing table lists all of its
+/// columns. Empty when the schema is what the queries expect.
+pub async fn missing_columns(pool: &DbPool) -> Result<Vec<String>, sqlx::Error> {
+    let mut missing = Vec::new();
+    for (table, columns) in EXPECTED_COLUMNS {
+        let present: Vec<String> = sqlx::query_scalar(TABLE_COLUMNS_QUERY)
+            .bind(table)
+            .fetch_all(pool)
+            .await?;
+        missing.extend(
+            columns
+                .iter()
+                .filter(|column| !present.iter().any(|name| name == *column))
+                .map(|column| format!("{}.{}", table, column)),
+        );
+    }
+    Ok(missing)
+}
+
+/// Connection pool tuning, from `DB_MAX_CONNECTIONS`, `DB_ACQUIRE_TIMEOUT_SECS` and
+/// (SQLite only) `SQLITE_WAL` and `DB_BUSY_TIMEOUT_SECS`
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    /// How long a request waits for a free connection before failing
+    pub acquire_timeout: Duration,
+    /// Use write-ahead logging so readers don't block the writer (default on)
+    #[cfg(not(feature = "postgres"))]
+    pub sqlite_wal: bool,
+    /// How long SQLite waits for a lock held by another connection before failing
+    #[cfg(not(feature = "postgres"))]
+    pub busy_timeout: Duration,
+}
+
+impl PoolSettings {
+    pub fn from_env() -> Self {
+        let parse = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        Self {
+            max_connections: parse("DB_MAX_CONNECTIONS").map_or(10, |n| n.max(1) as u32),
+            acquire_timeout: Duration::from_secs(parse("DB_ACQUIRE_TIMEOUT_SECS").unwrap_or(30)),
+            // On unless explicitly disabled, e.g. for databases on network filesystems
+            #[cfg(not(feature = "postgres"))]
+            sqlite_wal: std::env::var("SQLITE_WAL").is_err() || crate::env_flag("SQLITE_WAL"),
+            #[cfg(not(feature = "postgres"))]
+            busy_timeout: Duration::from_secs(parse("DB_BUSY_TIMEOUT_SECS").unwrap_or(5)),
+        }
+    }
+}
+
+/// Connect to the configured database, creating the SQLite file if it's missing
+#[cfg(not(feature = "postgres"))]
+pub async fn connect(database_url: &str, settings: &PoolSettings) -> Result<DbPool, sqlx::Error> {
+    use sqlx::sqlite::{
+        SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous,
+    };
+
+    let mut connect_options = database_url
+        .parse::<SqliteConnectOptions>()?
+        .create_if_missing(true)
+        .busy_timeout(settings.busy_timeout);
+    if settings.sqlite_wal {
+        // NORMAL is durable against application crashes in WAL mode and avoids an fsync per commit
+        connect_options = connect_options
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal);
+    }
+
+    SqlitePoolOptions::new()
+        .max_connections(settings.max_connections)
+        .acquire_timeout(settings.acquire_timeout)
+        .connect_with(connect_options)
+        .await
+}
+
+/// Connect to the configured database
+#[cfg(feature = "postgres")]
+pub async fn connect(database_url: &str, settings: &PoolSettings) -> Result<DbPool, sqlx::Error> {
+    use sqlx::postgres::PgPoolOptions;
+
+    PgPoolOptions::new()
+        .max_connections(settings.max_connections)
+        .acquire_timeout(settings.acquire_timeout)
+        .connect(database_url)
+        .await
+}
+
+/// Whether the SQLite `files_fts` trigram index is present
+static FILENAME_FTS: AtomicBool = AtomicBool::new(false);
+
+/// Whether filename search can use the `files_fts` index
+pub fn filename_fts() -> bool {
+    FILENAME_FTS.load(Ordering::Relaxed)
+}
+
+/// Check for the `files_fts` table once migrations have run. Without it (no FTS5 in
+/// the linked SQLite, or PostgreSQL) search falls back to plain `LIKE` matching.
+#[cfg(not(feature = "postgres"))]
+pub async fn detect_filename_fts(pool: &DbPool) {
+    let present = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'files_fts'",
+    )
+    .fetch_one(pool)
+    .await
+    .is_ok_and(|count| count > 0);
+
+    FILENAME_FTS.store(present, Ordering::Relaxed);
+}
+
+#[cfg(feature = "postgres")]
+pub async fn detect_filename_fts(_pool: &DbPool) {}
@@ -0,0 +1,172 @@
+//! Optional server-side encryption of stored blobs.
+//!
+//! When `STORAGE_ENCRYPTION_KEY` is set, new blobs are stored as XChaCha20-Poly1305
+//! STREAM ciphertext in fixed-size chunks, with a random per-file nonce prefix kept in
+//! the `files` row. Blobs without a recorded algorithm are read back as plaintext, so
+//! files stored before encryption was enabled keep working.
+
+use std::io;
+use std::sync::LazyLock;
+
+use argon2::{
+    Argon2,
+    password_hash::rand_core::{OsRng, RngCore},
+};
+use async_stream::try_stream;
+use axum::body::Bytes;
+use chacha20poly1305::{
+    Key, KeyInit, XChaCha20Poly1305,
+    aead::stream::{DecryptorBE32, EncryptorBE32},
+};
+use futures_util::{Stream, StreamExt};
+
+use crate::storage::ByteStream;
+
+/// Value recorded in `files.server_encryption_algo`
+pub const ALGORITHM: &str = "XChaCha20Poly1305-STREAM-BE32";
+
+/// Plaintext bytes per STREAM segment
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Poly1305 tag appended to every segment
+const TAG_SIZE: usize = 16;
+/// XChaCha20's 24-byte nonce minus the 5 bytes STREAM-BE32 uses for its counter
+const NONCE_PREFIX_SIZE: usize = 19;
+
+/// Salt for deriving the key from a passphrase; fixed so the key is stable across restarts
+const KEY_DERIVATION_SALT: &[u8] = b"trusty-storage-encryption";
+
+static KEY: LazyLock<Option<Key>> = LazyLock::new(|| {
+    std::env::var("STORAGE_ENCRYPTION_KEY")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .map(|secret| derive_key(&secret))
+});
+
+pub type BlobStream = ByteStream<'static>;
+
+/// How a blob was encrypted, as stored alongside the file
+#[derive(Debug, Clone)]
+pub struct BlobEncryption {
+    pub algorithm: String,
+    /// Hex-encoded STREAM nonce prefix
+    pub nonce: String,
+}
+
+/// Use 64 hex characters as the raw key; derive one with Argon2 from anything else
+fn derive_key(secret: &str) -> Key {
+    if let Ok(bytes) = hex::decode(secret)
+        && let Ok(raw) = <[u8; 32]>::try_from(bytes.as_slice())
+    {
+        return Key::from(raw);
+    }
+
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(secret.as_bytes(), KEY_DERIVATION_SALT, &mut key)
+        .expect("Failed to derive STORAGE_ENCRYPTION_KEY");
+    Key::from(key)
+}
+
+/// Whether new blobs are encrypted; also derives the key up front so startup pays for it
+pub fn enabled() -> bool {
+    KEY.is_some()
+}
+
+/// Stored size of a blob holding `plaintext_len` bytes, stored with `algorithm`
+pub fn stored_len(plaintext_len: u64, algorithm: Option<&str>) -> u64 {
+    match algorithm {
+        // Every segment carries a tag, and even an empty blob has one (final) segment
+        Some(ALGORITHM) => {
+            let segments = plaintext_len.div_ceil(CHUNK_SIZE as u64).max(1);
+            plaintext_len + segments * TAG_SIZE as u64
+        }
+        _ => plaintext_len,
+    }
+}
+
+fn aead_error(_: chacha20poly1305::aead::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "blob encryption/decryption failed")
+}
+
+/// Encrypt a blob's bytes on their way to storage when a storage key is configured,
+/// returning the stream to store and how it was encrypted
+pub fn encrypt<'a>(data: ByteStream<'a>) -> (ByteStream<'a>, Option<BlobEncryption>) {
+    let Some(key) = KEY.as_ref() else {
+        return (data, None);
+    };
+
+    let mut nonce = [0u8; NONCE_PREFIX_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+
+    let encryptor = EncryptorBE32::from_aead(XChaCha20Poly1305::new(key), (&nonce).into());
+    let encryption = BlobEncryption {
+        algorithm: ALGORITHM.to_string(),
+        nonce: hex::encode(nonce),
+    };
+    (encrypt_segments(data, encryptor).boxed(), Some(encryption))
+}
+
+fn encrypt_segments<'a>(
+    mut data: ByteStream<'a>,
+    mut encryptor: EncryptorBE32<XChaCha20Poly1305>,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + 'a {
+    try_stream! {
+        let mut buffer = Vec::with_capacity(CHUNK_SIZE * 2);
+        while let Some(chunk) = data.next().await {
+            buffer.extend_from_slice(&chunk?);
+            // Hold back up to one full chunk: the final segment must go through `encrypt_last`
+            while buffer.len() > CHUNK_SIZE {
+                let ciphertext = encryptor.encrypt_next(&buffer[..CHUNK_SIZE]).map_err(aead_error)?;
+                buffer.drain(..CHUNK_SIZE);
+                yield Bytes::from(ciphertext);
+            }
+        }
+
+        let ciphertext = encryptor.encrypt_last(buffer.as_slice()).map_err(aead_error)?;
+        yield Bytes::from(ciphertext);
+    }
+}
+
+/// Turn a stored blob back into its original bytes, decrypting if needed
+pub fn decrypt(data: BlobStream, algorithm: Option<&str>, nonce: Option<&str>) -> io::Result<BlobStream> {
+    let Some(algorithm) = algorithm else {
+        return Ok(data);
+    };
+
+    if algorithm != ALGORITHM {
+        return Err(io::Error::other(format!("unsupported blob encryption {}", algorithm)));
+    }
+    let key = KEY
+        .as_ref()
+        .ok_or_else(|| io::Error::other("blob is encrypted but STORAGE_ENCRYPTION_KEY is not set"))?;
+    let nonce = nonce
+        .and_then(|nonce| hex::decode(nonce).ok())
+        .filter(|nonce| nonce.len() == NONCE_PREFIX_SIZE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid blob nonce"))?;
+
+    let decryptor = DecryptorBE32::from_aead(XChaCha20Poly1305::new(key), nonce.as_slice().into());
+    Ok(decrypt_segments(data, decryptor).boxed())
+}
+
+fn decrypt_segments(
+    mut data: BlobStream,
+    mut decryptor: DecryptorBE32<XChaCha20Poly1305>,
+) -> impl Stream<Item = io::Result<Bytes>> + Send {
+    const SEGMENT_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
+
+    try_stream! {
+        let mut buffer = Vec::with_capacity(SEGMENT_SIZE * 2);
+        while let Some(chunk) = data.next().await {
+            buffer.extend_from_slice(&chunk?);
+            // As when encrypting, the last full segment waits in case it is the final one
+            while buffer.len() > SEGMENT_SIZE {
+                let plaintext = decryptor.decrypt_next(&buffer[..SEGMENT_SIZE]).map_err(aead_error)?;
+                buffer.drain(..SEGMENT_SIZE);
+                yield Bytes::from(plaintext);
+            }
+        }
+
+        let plaintext = decryptor.decrypt_last(buffer.as_slice()).map_err(aead_error)?;
+        yield Bytes::from(plaintext);
+    }
+}
@@ -0,0 +1,190 @@
+//! Data portability: everything a user has stored, as one ZIP archive.
+//!
+//! The archive holds each file's stored bytes under `files/<original name>` (still
+//! client-side encrypted, exactly as a download returns them) and a `metadata.json`
+//! with the profile and every file record. It is written into a pipe while the
+//! response streams out, so no blob or archive is ever held in memory whole.
+
+use std::collections::HashSet;
+
+use async_zip::{Compression, ZipDateTime, ZipEntryBuilder, tokio::write::ZipFileWriter};
+use axum::{
+    body::Body,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures_util::{AsyncWriteExt, StreamExt};
+use serde::Serialize;
+use tokio::io::DuplexStream;
+use tokio_util::io::ReaderStream;
+
+use crate::AppState;
+use crate::auth::Claims;
+use crate::filemanager::{File, FileError, FileRepository, FileResponse, open_stored_file};
+use crate::user::{UserRepository, UserResponse};
+
+/// Bytes buffered between the archive writer and the response body
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Files per `load_tags` query, well below the databases' bind-parameter limits
+const TAG_BATCH: usize = 500;
+
+#[derive(Debug, Serialize)]
+struct ExportMetadata {
+    exported_at: String,
+    user: UserResponse,
+    files: Vec<ExportedFile>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedFile {
+    #[serde(flatten)]
+    file: FileResponse,
+    /// Entry holding the content (null if the blob could not be read)
+    archive_path: Option<String>,
+}
+
+/// Archive-safe version of a user-supplied name: no directories, no `.`/`..`
+fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+    match name.trim() {
+        "" | "." | ".." => "unnamed".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Give each file a unique entry name. Later duplicates (in creation order) get
+/// ` (2)`, ` (3)`, … before the extension; comparison ignores case so extracting
+/// on a case-insensitive filesystem doesn't overwrite anything either.
+fn archive_paths(files: &[File]) -> Vec<String> {
+    let mut taken = HashSet::new();
+
+    files
+        .iter()
+        .map(|file| {
+            let name = sanitize_name(&file.original_name);
+            let (stem, extension) = match name.rsplit_once('.') {
+                Some((stem, extension)) if !stem.is_empty() => {
+                    (stem.to_string(), format!(".{}", extension))
+                }
+                _ => (name.clone(), String::new()),
+            };
+
+            let mut candidate = name;
+            let mut n = 2;
+            while !taken.insert(candidate.to_lowercase()) {
+                candidate = format!("{} ({}){}", stem, n, extension);
+                n += 1;
+            }
+            format!("files/{}", candidate)
+        })
+        .collect()
+}
+
+fn modification_date(timestamp: &str) -> ZipDateTime {
+    let date = chrono::DateTime::parse_from_rfc3339(timestamp)
+        .map(|date| date.with_timezone(&chrono::Utc))
+        .unwrap_or_else(|_| chrono::Utc::now());
+    ZipDateTime::from_chrono(&date)
+}
+
+/// Write the archive; an error leaves it truncated, which the client sees as a broken ZIP
+async fn write_archive(
+    state: &AppState,
+    user: UserResponse,
+    files: Vec<File>,
+    pipe: DuplexStream,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut zip = ZipFileWriter::with_tokio(pipe);
+    let paths = archive_paths(&files);
+    let mut exported = Vec::with_capacity(files.len());
+
+    for (file, path) in files.into_iter().zip(paths) {
+        // A missing blob shouldn't sink the whole export; metadata.json records the gap
+        let mut blob = match open_stored_file(state, &file).await {
+            Ok(blob) => blob,
+            Err(e) => {
+                tracing::warn!(error = ?e, file_id = %file.id, "Skipping unreadable file in export");
+                exported.push(ExportedFile { file: file.into(), archive_path: None });
+                continue;
+            }
+        };
+
+        let modified = file.updated_at.as_deref().unwrap_or(&file.created_at);
+        let entry = ZipEntryBuilder::new(path.clone().into(), Compression::Stored)
+            .last_modification_date(modification_date(modified));
+        let mut writer = zip.write_entry_stream(entry).await?;
+        while let Some(chunk) = blob.next().await {
+            writer.write_all(&chunk?).await?;
+        }
+        writer.close().await?;
+
+        exported.push(ExportedFile { file: file.into(), archive_path: Some(path) });
+    }
+
+    let metadata = ExportMetadata {
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        user,
+        files: exported,
+    };
+    let json = serde_json::to_vec_pretty(&metadata)?;
+    let entry = ZipEntryBuilder::new("metadata.json".to_string().into(), Compression::Stored)
+        .last_modification_date(modification_date(&metadata.exported_at));
+    zip.write_entry_whole(entry, &json).await?;
+
+    zip.close().await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/export",
+    tag = "auth",
+    responses(
+        (status = 200, description = "ZIP of every stored file plus metadata.json", content_type = "application/zip"),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn export_data(claims: Claims, State(state): State<AppState>) -> Result<Response, FileError> {
+    let user = UserRepository::new(state.db_pool.clone())
+        .find_by_id(&claims.user_id)
+        .await
+        .map_err(|_| FileError::InternalError)?
+        .ok_or(FileError::NotFound)?;
+
+    let file_repo = FileRepository::new(state.db_pool.clone());
+    let mut files = file_repo.list_all_files(&user.id).await?;
+    for batch in files.chunks_mut(TAG_BATCH) {
+        file_repo.load_tags(batch).await?;
+    }
+
+    let filename = format!("trusty-export-{}.zip", chrono::Utc::now().format("%Y-%m-%d"));
+    let user_id = user.id.clone();
+    let (reader, writer) = tokio::io::duplex(PIPE_CAPACITY);
+
+    // The writer only makes progress as the client reads, and stops once it disconnects
+    tokio::spawn(async move {
+        if let Err(e) = write_archive(&state, user.into(), files, writer).await {
+            tracing::warn!(error = %e, user_id = %user_id, "Data export aborted");
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+        .into_response())
+}
@@ -0,0 +1,161 @@
+//! The whole file index in one response, for backups and reporting.
+//!
+//! Every file record of the user is streamed oldest first, as newline-delimited JSON
+//! or CSV. Rows are written out as the database cursor yields them, so a library of
+//! any size is never held in memory; a database error mid-way aborts the response
+//! rather than ending it early, so a truncated index can't pass for a complete one.
+
+use std::io;
+
+use async_stream::stream;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State, rejection::QueryRejection},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::auth::Claims;
+use crate::filemanager::{FileError, FileRepository, FileResponse};
+
+/// Files per `load_tags` query, well below the databases' bind-parameter limits
+const TAG_BATCH: usize = 500;
+
+const CSV_HEADER: &[u8] = b"id,original_name,mime_type,size_bytes,created_at,updated_at,parent_id,\
+content_hash,download_count,last_accessed_at,client_file_id,version,tags\r\n";
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexFormat {
+    /// One `FileResponse` object per line
+    #[default]
+    Json,
+    /// RFC 4180 with a header row; tags are joined with `;`
+    Csv,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct IndexExportQuery {
+    /// `json` (default) or `csv`
+    #[serde(default)]
+    pub format: IndexFormat,
+}
+
+/// Quote a CSV field if it holds a separator, quote or line break
+fn push_csv_field(row: &mut Vec<u8>, value: &str) {
+    if value.contains([',', '"', '\r', '\n']) {
+        row.push(b'"');
+        row.extend_from_slice(value.replace('"', "\"\"").as_bytes());
+        row.push(b'"');
+    } else {
+        row.extend_from_slice(value.as_bytes());
+    }
+}
+
+fn push_csv_row(out: &mut Vec<u8>, file: &FileResponse) {
+    let fields = [
+        file.id.clone(),
+        file.original_name.clone(),
+        file.mime_type.clone(),
+        file.size_bytes.to_string(),
+        file.created_at.clone(),
+        file.updated_at.clone().unwrap_or_default(),
+        file.parent_id.clone().unwrap_or_default(),
+        file.content_hash.clone().unwrap_or_default(),
+        file.download_count.to_string(),
+        file.last_accessed_at.clone().unwrap_or_default(),
+        file.client_file_id.clone().unwrap_or_default(),
+        file.version.to_string(),
+        file.tags.join(";"),
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        push_csv_field(out, field);
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/export",
+    tag = "files",
+    params(IndexExportQuery),
+    responses(
+        (status = 200, description = "Every file record, oldest first, streamed as newline-delimited JSON or CSV", body = Vec<FileResponse>),
+        (status = 400, description = "Invalid format"),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn export_file_index(
+    claims: Claims,
+    State(state): State<AppState>,
+    query: Result<Query<IndexExportQuery>, QueryRejection>,
+) -> Result<Response, FileError> {
+    let Query(query) = query.map_err(|_| FileError::InvalidExportFormat)?;
+    let format = query.format;
+
+    let file_repo = FileRepository::new(state.db_pool.clone());
+    let files = file_repo.stream_all_files(&claims.user_id).chunks(TAG_BATCH);
+    let user_id = claims.user_id;
+
+    let body = stream! {
+        if let IndexFormat::Csv = format {
+            yield Ok(Bytes::from_static(CSV_HEADER));
+        }
+
+        futures_util::pin_mut!(files);
+        while let Some(batch) = files.next().await {
+            let loaded = match batch.into_iter().collect::<Result<Vec<_>, _>>() {
+                Ok(mut batch) => file_repo.load_tags(&mut batch).await.map(|()| batch),
+                Err(e) => Err(e),
+            };
+            let batch = match loaded {
+                Ok(batch) => batch,
+                Err(e) => {
+                    tracing::error!(error = ?e, user_id = %user_id, "File index export aborted");
+                    yield Err(io::Error::other("file index export aborted"));
+                    break;
+                }
+            };
+
+            let mut chunk = Vec::new();
+            for file in batch.into_iter().map(FileResponse::from) {
+                match format {
+                    IndexFormat::Json => {
+                        serde_json::to_writer(&mut chunk, &file).expect("file records serialize to JSON");
+                        chunk.push(b'\n');
+                    }
+                    IndexFormat::Csv => push_csv_row(&mut chunk, &file),
+                }
+            }
+            yield Ok(Bytes::from(chunk));
+        }
+    };
+
+    let (content_type, extension) = match format {
+        IndexFormat::Json => ("application/x-ndjson", "ndjson"),
+        IndexFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+    };
+    let filename = format!("trusty-files-{}.{}", chrono::Utc::now().format("%Y-%m-%d"), extension);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
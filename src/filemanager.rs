@@ -1,19 +1,38 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_stream::{stream, try_stream};
 use axum::{
     Json,
-    extract::{Multipart, Path, Query, State},
-    http::{HeaderMap, StatusCode, header},
+    body::Bytes,
+    extract::{Multipart, Path, Query, State, multipart::Field},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri, header},
     response::{IntoResponse, Response},
 };
-use serde::{Deserialize, Serialize};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use metrics::counter;
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
-use sqlx::{FromRow, SqlitePool};
-use tokio::io::AsyncWriteExt;
-use tokio_util::io::ReaderStream;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, QueryBuilder};
+use tokio::sync::{Semaphore, SemaphorePermit};
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::AppState;
+use crate::{AppState, audit, encryption, maintenance_mode, monitoring, static_files, thumbnails};
+use crate::blobs::{self, Blob, BlobRepository};
+use crate::encryption::{BlobEncryption, BlobStream};
+use crate::db::{Db, DbPool};
 use crate::auth::Claims;
+use crate::folders::FolderRepository;
+use crate::preferences::resolve_upload_folder;
+use crate::receipt::UploadReceipt;
+use crate::storage::Storage;
+use crate::upload_policy::UPLOAD_POLICY;
+use crate::usage::parse_timestamp;
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct File {
@@ -24,6 +43,24 @@ pub struct File {
     pub size_bytes: i64,
     pub storage_path: String,
     pub created_at: String,
+    pub content_hash: Option<String>,
+    pub parent_id: Option<String>,
+    pub updated_at: Option<String>,
+    /// At-rest encryption applied by the server (NULL = stored as uploaded)
+    pub server_encryption_algo: Option<String>,
+    pub server_encryption_nonce: Option<String>,
+    pub download_count: i64,
+    pub last_accessed_at: Option<String>,
+    /// Idempotency key chosen by the uploading client, unique per user
+    pub client_file_id: Option<String>,
+    /// Bumped on every metadata or content change (see `version_etag`)
+    pub version: i64,
+    /// Rendering the current content as a thumbnail failed (see `thumbnails::available`)
+    pub thumbnail_failed: bool,
+    /// Loaded separately from `file_tags` (see `FileRepository::load_tags`)
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -32,6 +69,35 @@ pub struct FileMetadata {
     pub mime_type: String,
     pub size_bytes: i64,
     pub client_encryption_algo: String,
+    /// Destination folder; when omitted, the user's auto-folder rules and default folder apply
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    /// Reject the upload (422) unless exactly this many bytes arrive
+    #[serde(default)]
+    pub expected_size: Option<i64>,
+    /// Reject the upload (422) unless the received bytes hash to this SHA-256 (hex)
+    #[serde(default)]
+    pub expected_sha256: Option<String>,
+    /// Idempotency key (1-255 characters): if this user already uploaded a file with
+    /// the same id, that file is returned with 200 instead of storing a duplicate
+    #[serde(default)]
+    pub client_file_id: Option<String>,
+    /// What to do when the destination folder already has a file named `original_name`
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+/// How an upload handles a file of the same name in its folder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Keep both, storing the new file as `name (1).ext`, `name (2).ext`…
+    #[default]
+    Rename,
+    /// Delete the existing file once the new one is stored
+    Replace,
+    /// Reject the upload with 409
+    Error,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -41,27 +107,255 @@ pub struct FileResponse {
     pub mime_type: String,
     pub size_bytes: i64,
     pub created_at: String,
+    /// Containing folder (null = root)
+    pub parent_id: Option<String>,
+    /// Last time the content was replaced (null = never)
+    pub updated_at: Option<String>,
+    /// SHA-256 (hex) of the stored bytes, as computed by the server
+    pub content_hash: Option<String>,
+    /// Downloads by the owner and through share links
+    pub download_count: i64,
+    /// Last download (null = never downloaded)
+    pub last_accessed_at: Option<String>,
+    /// Idempotency key given at upload, if any
+    pub client_file_id: Option<String>,
+    /// Changes with every move, tag change or content replacement; send it back as
+    /// `If-Match: W/"<version>"` to update only if nobody else has since
+    pub version: i64,
+    /// Lowercased, sorted
+    pub tags: Vec<String>,
+    /// Whether a thumbnail can be had: the file has an image type and rendering its
+    /// content hasn't failed before
+    pub has_thumbnail: bool,
+    /// Where to get it; null when `has_thumbnail` is false
+    pub thumbnail_url: Option<String>,
 }
 
 impl From<File> for FileResponse {
     fn from(file: File) -> Self {
+        let has_thumbnail = thumbnails::available(&file);
+        let thumbnail_url = has_thumbnail.then(|| format!("/api/files/{}/thumbnail", file.id));
         Self {
             id: file.id,
             original_name: file.original_name,
             mime_type: file.mime_type,
             size_bytes: file.size_bytes,
             created_at: file.created_at,
+            parent_id: file.parent_id,
+            updated_at: file.updated_at,
+            content_hash: file.content_hash,
+            download_count: file.download_count,
+            last_accessed_at: file.last_accessed_at,
+            client_file_id: file.client_file_id,
+            version: file.version,
+            tags: file.tags,
+            has_thumbnail,
+            thumbnail_url,
         }
     }
 }
 
+/// Multipart body of an upload: exactly one `metadata` and one `file` field, in either
+/// order (documentation only; the fields are streamed rather than deserialized)
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct UploadForm {
+    /// JSON-encoded upload metadata, before or after `file`
+    metadata: FileMetadata,
+    /// The client-encrypted file content
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+/// Multipart body of a multi-file upload (documentation only). The two fields repeat,
+/// each `file` directly after its own `metadata`.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct MultiUploadForm {
+    /// JSON-encoded metadata for the file that follows
+    metadata: Vec<FileMetadata>,
+    /// Client-encrypted file contents
+    #[schema(value_type = Vec<String>, format = Binary)]
+    file: Vec<Vec<u8>>,
+}
+
+/// Multipart body replacing a file's content (documentation only)
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ContentForm {
+    /// The new client-encrypted file content
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+/// Upload result: the stored file plus a signed receipt proving when and what was stored
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadResponse {
+    #[serde(flatten)]
+    pub file: FileResponse,
+    pub receipt: UploadReceipt,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MoveFileRequest {
+    /// Destination folder (null = root)
+    #[serde(default)]
+    pub folder_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddTagRequest {
+    /// 1-50 characters without commas; stored lowercased
+    pub tag: String,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CopyFileRequest {
+    /// Name for the copy (defaults to "Copy of <original name>")
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchRequest {
+    /// Files to update (1-500, each at most once)
+    pub ids: Vec<String>,
+    /// Tags to add to every file (same rules as for a single file)
+    #[serde(default)]
+    pub add_tags: Vec<String>,
+    /// Tags to remove from every file; missing ones are ignored
+    #[serde(default)]
+    pub remove_tags: Vec<String>,
+    /// Destination folder (null = root); omit to leave files where they are
+    #[serde(default, deserialize_with = "explicit_null")]
+    #[schema(value_type = Option<String>)]
+    pub move_to: Option<Option<String>>,
+}
+
+/// Tell an explicit `null` (`Some(None)`) apart from an omitted field (`None`)
+fn explicit_null<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<String>>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Updated,
+    /// Missing or owned by someone else; left untouched
+    NotFound,
+    /// The file would end up with more than 20 tags; left untouched
+    TooManyTags,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResult {
+    pub id: String,
+    pub status: BatchStatus,
+    /// The file after the update, when `status` is `updated`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileResponse>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResponse {
+    /// One entry per requested id, in request order
+    pub results: Vec<BatchResult>,
+    pub updated: i64,
+    pub skipped: i64,
+}
+
 #[derive(Deserialize, ToSchema, IntoParams)]
 pub struct FileQuery {
     pub q: Option<String>,
+    /// `name` (default, also used for unrecognized values), `size`, `date`, `type` or `downloads`;
+    /// the column names `original_name`, `size_bytes`, `created_at`, `mime_type` and
+    /// `download_count` also work
     pub sort: Option<String>,
+    /// `asc` (default) or `desc`
     pub direction: Option<String>,
     pub page: Option<i64>,
     pub page_size: Option<i64>,
+    /// Only files whose MIME type starts with this (e.g. `image/`)
+    pub mime_prefix: Option<String>,
+    /// Minimum size in bytes (inclusive)
+    pub min_size: Option<i64>,
+    /// Maximum size in bytes (inclusive)
+    pub max_size: Option<i64>,
+    /// Only files created at or after this RFC 3339 timestamp
+    pub created_after: Option<String>,
+    /// Only files created before this RFC 3339 timestamp
+    pub created_before: Option<String>,
+    /// Only files with this tag (case-insensitive)
+    pub tag: Option<String>,
+    /// Keyset pagination, newest first: pass an empty `cursor` to start, then each
+    /// response's `next_cursor`. `sort`, `direction` and `page` are ignored in this mode.
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct DownloadQuery {
+    /// Answer `204` with just the content hash in `X-Content-Hash` (and `ETag`)
+    #[serde(default)]
+    pub hash_only: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+pub struct QuickListQuery {
+    /// Files to return (default 10, max 100)
+    pub limit: Option<i64>,
+}
+
+/// Listing filters shared by `list_files`, `stream_files` and `count_files`
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    pub search: Option<String>,
+    pub mime_prefix: Option<String>,
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    /// Normalized via `usage::parse_timestamp`
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    /// Lowercased to match the stored tags
+    pub tag: Option<String>,
+}
+
+impl FileFilter {
+    pub fn from_query(query: &FileQuery) -> Result<Self, FileError> {
+        Ok(Self {
+            search: query.q.clone(),
+            mime_prefix: query.mime_prefix.clone(),
+            min_size: query.min_size,
+            max_size: query.max_size,
+            created_after: query.created_after.as_deref().map(parse_timestamp).transpose()?,
+            created_before: query.created_before.as_deref().map(parse_timestamp).transpose()?,
+            tag: query.tag.as_deref().map(|tag| tag.trim().to_lowercase()),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+pub struct ChangesQuery {
+    /// Report changes from this time on (RFC 3339), normally the previous response's `until`
+    pub since: String,
+}
+
+/// A file deleted since the requested time
+#[derive(Debug, Serialize, ToSchema, FromRow)]
+pub struct DeletedFile {
+    pub id: String,
+    pub deleted_at: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChangesResponse {
+    pub since: String,
+    /// Pass as `since` on the next call. Slightly behind the server's clock, so a change
+    /// being written during this call is reported again next time rather than missed.
+    pub until: String,
+    /// Files uploaded since `since`
+    pub created: Vec<FileResponse>,
+    /// Older files whose content, folder or tags changed since `since`
+    pub updated: Vec<FileResponse>,
+    pub deleted: Vec<DeletedFile>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -71,6 +365,36 @@ pub struct FileListResponse {
     pub page: i64,
     pub page_size: i64,
     pub total_pages: i64,
+    /// Cursor for the next page in cursor mode (absent on the last page)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Position after the last row of a cursor-paginated page: `(created_at, id)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileCursor {
+    pub created_at: String,
+    pub id: String,
+}
+
+impl FileCursor {
+    /// Opaque, URL-safe form handed to clients
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}\n{}", self.created_at, self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, FileError> {
+        let decoded = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or(FileError::InvalidCursor)?;
+        let (created_at, id) = decoded.split_once('\n').ok_or(FileError::InvalidCursor)?;
+        Ok(Self {
+            created_at: created_at.to_string(),
+            id: id.to_string(),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -79,75 +403,351 @@ pub enum FileError {
     NotFound,
     Unauthorized,
     StorageError,
+    InsufficientStorage,
     InvalidMetadata,
+    DuplicateField,
+    UnpairedField,
+    TooManyFiles,
+    InvalidBatch,
+    FolderNotFound,
+    InvalidFolderName,
+    InvalidTimestamp,
+    ShareNotFound,
+    InvalidShareSettings,
+    SharePasswordRequired,
+    NotAnImage,
+    IntegrityMismatch,
+    InvalidCursor,
+    InvalidTag,
+    TooManyTags,
+    TagNotFound,
+    VersionMismatch,
+    NameConflict,
+    UnsupportedType,
+    UploadsBusy,
+    /// A write refused in maintenance mode
+    ReadOnly,
+    InvalidExportFormat,
     InternalError,
 }
 
 impl IntoResponse for FileError {
     fn into_response(self) -> Response {
+        let retry_after = match self {
+            FileError::UploadsBusy => Some(UPLOAD_SLOT_TIMEOUT.as_secs()),
+            FileError::ReadOnly => Some(maintenance_mode::RETRY_AFTER_SECS),
+            _ => None,
+        };
         let (status, error_message) = match self {
             FileError::DatabaseError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database error"),
             FileError::NotFound => (StatusCode::NOT_FOUND, "File not found"),
             FileError::Unauthorized => (StatusCode::FORBIDDEN, "You don't own this file"),
             FileError::StorageError => (StatusCode::INTERNAL_SERVER_ERROR, "Storage error"),
+            FileError::InsufficientStorage => (
+                StatusCode::INSUFFICIENT_STORAGE,
+                "Not enough storage space for this upload",
+            ),
             FileError::InvalidMetadata => (StatusCode::BAD_REQUEST, "Invalid metadata"),
+            FileError::DuplicateField => (
+                StatusCode::BAD_REQUEST,
+                "Duplicate field (send exactly one metadata and one file field)",
+            ),
+            FileError::UnpairedField => (
+                StatusCode::BAD_REQUEST,
+                "Each file field must directly follow its own metadata field",
+            ),
+            FileError::TooManyFiles => (
+                StatusCode::BAD_REQUEST,
+                "Too many files in one upload (at most 100)",
+            ),
+            FileError::InvalidBatch => (
+                StatusCode::BAD_REQUEST,
+                "Invalid batch (1-500 distinct ids and at least one operation)",
+            ),
+            FileError::FolderNotFound => (StatusCode::NOT_FOUND, "Folder not found"),
+            FileError::InvalidFolderName => (
+                StatusCode::BAD_REQUEST,
+                "Invalid folder name (must be 1-255 characters without '/')",
+            ),
+            FileError::InvalidTimestamp => (
+                StatusCode::BAD_REQUEST,
+                "Invalid timestamp (expected RFC 3339)",
+            ),
+            FileError::ShareNotFound => (
+                StatusCode::NOT_FOUND,
+                "Share link not found or expired",
+            ),
+            FileError::InvalidShareSettings => (
+                StatusCode::BAD_REQUEST,
+                "Invalid share settings (expiry and download limit must be positive, password non-empty)",
+            ),
+            FileError::SharePasswordRequired => (
+                StatusCode::UNAUTHORIZED,
+                "Missing or incorrect share password",
+            ),
+            FileError::NotAnImage => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "File is not a supported image",
+            ),
+            FileError::IntegrityMismatch => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Uploaded content does not match the expected size or SHA-256",
+            ),
+            FileError::InvalidCursor => (StatusCode::BAD_REQUEST, "Invalid pagination cursor"),
+            FileError::InvalidExportFormat => (StatusCode::BAD_REQUEST, "Invalid export format (csv or json)"),
+            FileError::InvalidTag => (
+                StatusCode::BAD_REQUEST,
+                "Invalid tag (must be 1-50 characters without commas)",
+            ),
+            FileError::TooManyTags => (
+                StatusCode::BAD_REQUEST,
+                "Too many tags (at most 20 per file)",
+            ),
+            FileError::TagNotFound => (StatusCode::NOT_FOUND, "Tag not found on this file"),
+            FileError::VersionMismatch => (
+                StatusCode::PRECONDITION_FAILED,
+                "File was changed since it was read (If-Match doesn't match its ETag)",
+            ),
+            FileError::NameConflict => (
+                StatusCode::CONFLICT,
+                "A file with this name already exists in the folder",
+            ),
+            FileError::UnsupportedType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "This type of file can't be uploaded here",
+            ),
+            FileError::UploadsBusy => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many uploads in progress, try again shortly",
+            ),
+            FileError::ReadOnly => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                maintenance_mode::READ_ONLY_MESSAGE,
+            ),
             FileError::InternalError => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
             }
         };
         let body = Json(json!({
             "error": error_message,
+            "request_id": crate::request_id::current(),
         }));
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(seconds) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
 
 pub struct FileRepository {
-    pool: SqlitePool,
+    pool: DbPool,
 }
 
 impl FileRepository {
-    pub fn new(pool: SqlitePool) -> Self {
+    pub fn new(pool: DbPool) -> Self {
         Self { pool }
     }
 
     pub async fn create_file(&self, file: &File) -> Result<(), FileError> {
-        sqlx::query(
-            "INSERT INTO files (id, user_id, original_name, mime_type, size_bytes, storage_path, created_at) 
-             VALUES (?, ?, ?, ?, ?, ?, ?)"
+        insert_file(&self.pool, file).await
+    }
+
+    pub async fn get_file_by_client_id(
+        &self,
+        user_id: &str,
+        client_file_id: &str,
+    ) -> Result<Option<File>, FileError> {
+        sqlx::query_as::<_, File>("SELECT * FROM files WHERE user_id = $1 AND client_file_id = $2")
+            .bind(user_id)
+            .bind(client_file_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(FileError::DatabaseError)
+    }
+
+    /// The user's file named exactly `name` in folder `parent_id` (root when `None`)
+    pub async fn find_by_name(
+        &self,
+        user_id: &str,
+        parent_id: Option<&str>,
+        name: &str,
+    ) -> Result<Option<File>, FileError> {
+        sqlx::query_as::<_, File>(
+            "SELECT * FROM files
+             WHERE user_id = $1 AND original_name = $2
+               AND (parent_id = $3 OR (parent_id IS NULL AND $3 IS NULL))
+             LIMIT 1",
         )
-        .bind(&file.id)
-        .bind(&file.user_id)
-        .bind(&file.original_name)
-        .bind(&file.mime_type)
-        .bind(file.size_bytes)
-        .bind(&file.storage_path)
-        .bind(&file.created_at)
-        .execute(&self.pool)
+        .bind(user_id)
+        .bind(name)
+        .bind(parent_id)
+        .fetch_optional(&self.pool)
         .await
-        .map_err(FileError::DatabaseError)?;
-
-        Ok(())
+        .map_err(FileError::DatabaseError)
     }
 
     pub async fn list_files(
         &self,
         user_id: &str,
-        search_query: Option<&str>,
+        filter: &FileFilter,
         sort: Option<&str>,
         direction: Option<&str>,
         page: i64,
         page_size: i64,
     ) -> Result<Vec<File>, FileError> {
-        let mut query = String::from("SELECT * FROM files WHERE user_id = ?");
+        Self::list_query(user_id, filter, sort, direction, page, page_size)
+            .build_query_as::<File>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(FileError::DatabaseError)
+    }
+
+    /// Keyset-paginated listing, newest first: up to `limit` files strictly after `cursor`.
+    /// Unlike offset paging, rows added meanwhile never shift later pages.
+    pub async fn list_files_after(
+        &self,
+        user_id: &str,
+        filter: &FileFilter,
+        cursor: Option<&FileCursor>,
+        limit: i64,
+    ) -> Result<Vec<File>, FileError> {
+        let mut query = QueryBuilder::new("SELECT * FROM files");
+        Self::push_filter(&mut query, user_id, filter);
+
+        if let Some(cursor) = cursor {
+            query
+                .push(" AND (created_at, id) < (")
+                .push_bind(cursor.created_at.clone())
+                .push(", ")
+                .push_bind(cursor.id.clone())
+                .push(")");
+        }
+
+        query
+            .push(" ORDER BY created_at DESC, id DESC LIMIT ")
+            .push_bind(limit);
+
+        query
+            .build_query_as::<File>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(FileError::DatabaseError)
+    }
+
+    /// Same listing as `list_files`, tags included, but yields rows as they are read from
+    /// the database instead of collecting the whole page first.
+    pub fn stream_files(
+        &self,
+        user_id: &str,
+        filter: &FileFilter,
+        sort: Option<&str>,
+        direction: Option<&str>,
+        page: i64,
+        page_size: i64,
+    ) -> impl Stream<Item = Result<File, FileError>> + Send + 'static {
+        let pool = self.pool.clone();
+        let mut query = Self::list_query(user_id, filter, sort, direction, page, page_size);
+        let mut tag_query =
+            QueryBuilder::new("SELECT file_id, tag FROM file_tags WHERE file_id IN (SELECT id FROM (SELECT * FROM files");
+        Self::push_listing(&mut tag_query, user_id, filter, sort, direction, page, page_size);
+        tag_query.push(") AS page) ORDER BY tag");
+
+        try_stream! {
+            // The page's tags come first, in one query, so the cursor below is the only
+            // connection held while streaming
+            let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+            let rows: Vec<(String, String)> = tag_query
+                .build_query_as()
+                .fetch_all(&pool)
+                .await
+                .map_err(FileError::DatabaseError)?;
+            for (file_id, tag) in rows {
+                tags.entry(file_id).or_default().push(tag);
+            }
+
+            let mut rows = query.build_query_as::<File>().fetch(&pool);
+            while let Some(mut file) = rows.try_next().await.map_err(FileError::DatabaseError)? {
+                file.tags = tags.remove(&file.id).unwrap_or_default();
+                yield file;
+            }
+        }
+    }
 
-        if search_query.is_some() {
-            query.push_str(" AND original_name LIKE ?");
+    /// Append the `WHERE` clause for a user's files matching `filter`
+    fn push_filter(query: &mut QueryBuilder<'static, Db>, user_id: &str, filter: &FileFilter) {
+        query.push(" WHERE user_id = ").push_bind(user_id.to_string());
+
+        // Every whitespace-separated term must appear somewhere in the name
+        for term in filter.search.iter().flat_map(|q| q.split_whitespace()) {
+            // Trigrams can't match terms shorter than three characters
+            if crate::db::filename_fts() && term.chars().count() >= 3 {
+                query
+                    .push(" AND rowid IN (SELECT rowid FROM files_fts WHERE files_fts MATCH ")
+                    .push_bind(format!("\"{}\"", term.replace('"', "\"\"")))
+                    .push(")");
+            } else {
+                query
+                    .push(" AND LOWER(original_name) LIKE LOWER(")
+                    .push_bind(format!("%{}%", term))
+                    .push(")");
+            }
+        }
+        if let Some(prefix) = &filter.mime_prefix {
+            query.push(" AND mime_type LIKE ").push_bind(format!("{}%", prefix));
+        }
+        if let Some(min_size) = filter.min_size {
+            query.push(" AND size_bytes >= ").push_bind(min_size);
+        }
+        if let Some(max_size) = filter.max_size {
+            query.push(" AND size_bytes <= ").push_bind(max_size);
         }
+        if let Some(after) = &filter.created_after {
+            query.push(" AND created_at >= ").push_bind(after.clone());
+        }
+        if let Some(before) = &filter.created_before {
+            query.push(" AND created_at < ").push_bind(before.clone());
+        }
+        if let Some(tag) = &filter.tag {
+            query
+                .push(" AND id IN (SELECT file_id FROM file_tags WHERE tag = ")
+                .push_bind(tag.clone())
+                .push(")");
+        }
+    }
 
+    fn list_query(
+        user_id: &str,
+        filter: &FileFilter,
+        sort: Option<&str>,
+        direction: Option<&str>,
+        page: i64,
+        page_size: i64,
+    ) -> QueryBuilder<'static, Db> {
+        let mut query = QueryBuilder::new("SELECT * FROM files");
+        Self::push_listing(&mut query, user_id, filter, sort, direction, page, page_size);
+        query
+    }
+
+    /// Append the filter, order and page of a listing to a `... FROM files` query
+    fn push_listing(
+        query: &mut QueryBuilder<'static, Db>,
+        user_id: &str,
+        filter: &FileFilter,
+        sort: Option<&str>,
+        direction: Option<&str>,
+        page: i64,
+        page_size: i64,
+    ) {
+        Self::push_filter(query, user_id, filter);
+
+        // Identifiers can't be bound, so only whitelisted columns reach the SQL
         let sort_field = match sort {
-            Some("size") => "size_bytes",
-            Some("date") => "created_at",
+            Some("size") | Some("size_bytes") => "size_bytes",
+            Some("date") | Some("created_at") => "created_at",
+            Some("type") | Some("mime_type") => "mime_type",
+            Some("downloads") | Some("download_count") => "download_count",
+            // Documented default, including unrecognized values
             _ => "original_name",
         };
 
@@ -156,25 +756,42 @@ impl FileRepository {
             _ => "ASC",
         };
 
-        query.push_str(&format!(" ORDER BY {} {}", sort_field, sort_dir));
-        
-        let offset = (page - 1) * page_size;
-        query.push_str(&format!(" LIMIT {} OFFSET {}", page_size, offset));
-
-        let mut query_builder = sqlx::query_as::<_, File>(&query).bind(user_id);
+        // `id` breaks ties so pages don't overlap or skip rows with equal sort keys
+        query.push(format!(" ORDER BY {} {}, id {}", sort_field, sort_dir, sort_dir));
 
-        if let Some(q) = search_query {
-            query_builder = query_builder.bind(format!("%{}%", q));
-        }
+        query
+            .push(" LIMIT ")
+            .push_bind(page_size)
+            .push(" OFFSET ")
+            .push_bind((page - 1) * page_size);
+    }
 
-        query_builder
+    /// Every file the user owns, oldest first
+    pub async fn list_all_files(&self, user_id: &str) -> Result<Vec<File>, FileError> {
+        sqlx::query_as::<_, File>("SELECT * FROM files WHERE user_id = $1 ORDER BY created_at, id")
+            .bind(user_id)
             .fetch_all(&self.pool)
             .await
             .map_err(FileError::DatabaseError)
     }
 
+    /// Every file of a user like `list_all_files`, yielded as rows are read
+    pub fn stream_all_files(&self, user_id: &str) -> impl Stream<Item = Result<File, FileError>> + Send + 'static {
+        let pool = self.pool.clone();
+        let user_id = user_id.to_string();
+
+        try_stream! {
+            let mut rows = sqlx::query_as::<_, File>("SELECT * FROM files WHERE user_id = $1 ORDER BY created_at, id")
+                .bind(&user_id)
+                .fetch(&pool);
+            while let Some(file) = rows.try_next().await.map_err(FileError::DatabaseError)? {
+                yield file;
+            }
+        }
+    }
+
     pub async fn get_file(&self, id: &str, user_id: &str) -> Result<Option<File>, FileError> {
-        sqlx::query_as::<_, File>("SELECT * FROM files WHERE id = ? AND user_id = ?")
+        sqlx::query_as::<_, File>("SELECT * FROM files WHERE id = $1 AND user_id = $2")
             .bind(id)
             .bind(user_id)
             .fetch_optional(&self.pool)
@@ -182,223 +799,1767 @@ impl FileRepository {
             .map_err(FileError::DatabaseError)
     }
 
-    pub async fn count_files(
-        &self,
-        user_id: &str,
-        search_query: Option<&str>,
-    ) -> Result<i64, FileError> {
-        let mut query = String::from("SELECT COUNT(*) as count FROM files WHERE user_id = ?");
-
-        if search_query.is_some() {
-            query.push_str(" AND original_name LIKE ?");
-        }
-
-        let mut query_builder = sqlx::query_scalar::<_, i64>(&query).bind(user_id);
-
-        if let Some(q) = search_query {
-            query_builder = query_builder.bind(format!("%{}%", q));
-        }
+    pub async fn count_files(&self, user_id: &str, filter: &FileFilter) -> Result<i64, FileError> {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) as count FROM files");
+        Self::push_filter(&mut query, user_id, filter);
 
-        query_builder
+        query
+            .build_query_scalar::<i64>()
             .fetch_one(&self.pool)
             .await
             .map_err(FileError::DatabaseError)
     }
 
-    pub async fn delete_file(&self, id: &str, user_id: &str) -> Result<bool, FileError> {
-        let result = sqlx::query("DELETE FROM files WHERE id = ? AND user_id = ?")
+    pub async fn set_content_hash(&self, id: &str, content_hash: &str) -> Result<(), FileError> {
+        sqlx::query("UPDATE files SET content_hash = $1 WHERE id = $2")
+            .bind(content_hash)
             .bind(id)
-            .bind(user_id)
             .execute(&self.pool)
             .await
             .map_err(FileError::DatabaseError)?;
 
-        Ok(result.rows_affected() > 0)
+        Ok(())
     }
-}
 
-#[utoipa::path(
-    post,
-    path = "/api/files/upload",
-    tag = "files",
-    responses(
-        (status = 201, description = "File uploaded successfully", body = FileResponse),
-        (status = 400, description = "Invalid request"),
-        (status = 500, description = "Internal server error")
-    ),
-    security(
-        ("bearer_auth" = [])
-    )
-)]
-pub async fn upload_file(
-    claims: Claims,
-    State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> Result<(StatusCode, Json<FileResponse>), FileError> {
-    let mut metadata: Option<FileMetadata> = None;
-    let mut file_id: Option<String> = None;
-    let mut storage_path: Option<String> = None;
-    let mut actual_size: i64 = 0;
+    /// Count a download; a single `UPDATE` so concurrent downloads don't lose increments
+    pub async fn record_download(&self, id: &str) -> Result<(), FileError> {
+        sqlx::query(
+            "UPDATE files SET download_count = download_count + 1, last_accessed_at = $1 WHERE id = $2",
+        )
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)?;
 
-    const MAX_FILE_SIZE: usize = 100 * 1024 * 1024; // 100MB limit
+        Ok(())
+    }
 
-    while let Some(field) = multipart.next_field().await.map_err(|_| FileError::InvalidMetadata)? {
-        let field_name = field.name().unwrap_or("").to_string();
-
-        if field_name == "metadata" {
-            let data = field.bytes().await.map_err(|_| FileError::InvalidMetadata)?;
-            metadata = Some(serde_json::from_slice(&data).map_err(|_| FileError::InvalidMetadata)?);
-        } else if field_name == "file" {
-            // Generate file ID and path
-            let id = Uuid::new_v4().to_string();
-            let path = format!("{}/{}.bin", claims.user_id, id);
-            let full_path = state.storage_root.join(&path);
-
-            // Create user directory if it doesn't exist
-            if let Some(parent) = full_path.parent() {
-                tokio::fs::create_dir_all(parent)
-                    .await
-                    .map_err(|_| FileError::StorageError)?;
-            }
+    /// Fill in `tags` for each file, a query per `TAG_BATCH` files
+    pub async fn load_tags(&self, files: &mut [File]) -> Result<(), FileError> {
+        for batch in files.chunks_mut(TAG_BATCH) {
+            self.load_tag_batch(batch).await?;
+        }
+        Ok(())
+    }
 
-            // Stream file to disk
-            let mut file_handle = tokio::fs::File::create(&full_path)
-                .await
-                .map_err(|_| FileError::StorageError)?;
-
-            let mut size = 0usize;
-            let mut stream = field;
-
-            while let Some(chunk) = stream.chunk().await.map_err(|_| FileError::StorageError)? {
-                size += chunk.len();
-                if size > MAX_FILE_SIZE {
-                    // Clean up partial file
-                    drop(file_handle);
-                    let _ = tokio::fs::remove_file(&full_path).await;
-                    return Err(FileError::InvalidMetadata); // File too large
-                }
-                file_handle.write_all(&chunk)
-                    .await
-                    .map_err(|_| FileError::StorageError)?;
-            }
+    async fn load_tag_batch(&self, files: &mut [File]) -> Result<(), FileError> {
+        let mut query = QueryBuilder::<Db>::new("SELECT file_id, tag FROM file_tags WHERE file_id IN (");
+        let mut ids = query.separated(", ");
+        for file in files.iter() {
+            ids.push_bind(file.id.clone());
+        }
+        query.push(") ORDER BY tag");
 
-            file_handle.flush()
-                .await
-                .map_err(|_| FileError::StorageError)?;
+        let rows: Vec<(String, String)> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(FileError::DatabaseError)?;
 
-            actual_size = size as i64;
-            file_id = Some(id);
-            storage_path = Some(path);
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        for (file_id, tag) in rows {
+            tags.entry(file_id).or_default().push(tag);
         }
+        for file in files.iter_mut() {
+            file.tags = tags.remove(&file.id).unwrap_or_default();
+        }
+        Ok(())
     }
 
-    let metadata = metadata.ok_or(FileError::InvalidMetadata)?;
-    let file_id = file_id.ok_or(FileError::InvalidMetadata)?;
-    let storage_path = storage_path.ok_or(FileError::InvalidMetadata)?;
+    /// Tag a file unless it already has `MAX_TAGS_PER_FILE` tags. Returns false only
+    /// when the cap was hit; adding a tag the file already has succeeds.
+    pub async fn add_tag(&self, file_id: &str, tag: &str, expected_version: Option<i64>) -> Result<bool, FileError> {
+        let mut tx = self.pool.begin().await.map_err(FileError::DatabaseError)?;
+        if !bump_version(&mut tx, file_id, expected_version).await? {
+            return Err(FileError::VersionMismatch);
+        }
 
-    let file = File {
-        id: file_id.clone(),
-        user_id: claims.user_id.clone(),
-        original_name: metadata.original_name,
-        mime_type: metadata.mime_type,
-        size_bytes: actual_size, // Use actual size from stream
-        storage_path,
-        created_at: chrono::Utc::now().to_rfc3339(),
-    };
+        // Count and insert in one statement so concurrent requests can't exceed the cap
+        let result = sqlx::query(
+            "INSERT INTO file_tags (file_id, tag)
+             SELECT $1, $2 WHERE (SELECT COUNT(*) FROM file_tags WHERE file_id = $3) < $4
+             ON CONFLICT (file_id, tag) DO NOTHING",
+        )
+        .bind(file_id)
+        .bind(tag)
+        .bind(file_id)
+        .bind(MAX_TAGS_PER_FILE)
+        .execute(&mut *tx)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        if result.rows_affected() > 0 {
+            tx.commit().await.map_err(FileError::DatabaseError)?;
+            return Ok(true);
+        }
 
-    let file_repo = FileRepository::new(state.db_pool);
-    file_repo.create_file(&file).await?;
+        // Nothing changed, so the version bump is rolled back with the transaction
+        let exists = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM file_tags WHERE file_id = $1 AND tag = $2",
+        )
+        .bind(file_id)
+        .bind(tag)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(FileError::DatabaseError)?;
 
-    Ok((StatusCode::CREATED, Json(file.into())))
-}
+        Ok(exists > 0)
+    }
 
-#[utoipa::path(
-    get,
-    path = "/api/files",
-    tag = "files",
-    params(FileQuery),
-    responses(
-        (status = 200, description = "Files retrieved successfully", body = FileListResponse),
-        (status = 500, description = "Internal server error")
-    ),
-    security(
-        ("bearer_auth" = [])
-    )
-)]
-pub async fn get_files_handler(
-    claims: Claims,
-    State(state): State<AppState>,
-    Query(query): Query<FileQuery>,
-) -> Result<Json<FileListResponse>, FileError> {
-    let file_repo = FileRepository::new(state.db_pool.clone());
+    /// Returns whether the file had the tag
+    pub async fn remove_tag(&self, file_id: &str, tag: &str, expected_version: Option<i64>) -> Result<bool, FileError> {
+        let mut tx = self.pool.begin().await.map_err(FileError::DatabaseError)?;
+        if !bump_version(&mut tx, file_id, expected_version).await? {
+            return Err(FileError::VersionMismatch);
+        }
 
-    let page = query.page.unwrap_or(1).max(1);
-    let page_size = query.page_size.unwrap_or(20).max(1).min(100);
+        let result = sqlx::query("DELETE FROM file_tags WHERE file_id = $1 AND tag = $2")
+            .bind(file_id)
+            .bind(tag)
+            .execute(&mut *tx)
+            .await
+            .map_err(FileError::DatabaseError)?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
 
-    let total = file_repo
-        .count_files(&claims.user_id, query.q.as_deref())
-        .await?;
+        tx.commit().await.map_err(FileError::DatabaseError)?;
+        Ok(true)
+    }
 
-    let files = file_repo
-        .list_files(
-            &claims.user_id,
-            query.q.as_deref(),
-            query.sort.as_deref(),
-            query.direction.as_deref(),
-            page,
-            page_size,
+    /// Put a file in another folder (`None` = root), if it is still at
+    /// `expected_version` (when given); only the row changes
+    pub async fn move_file(
+        &self,
+        id: &str,
+        user_id: &str,
+        parent_id: Option<&str>,
+        expected_version: Option<i64>,
+    ) -> Result<Option<File>, FileError> {
+        sqlx::query_as::<_, File>(
+            "UPDATE files SET parent_id = $1, version = version + 1, modified_at = $2
+             WHERE id = $3 AND user_id = $4 AND ($5 IS NULL OR version = $5)
+             RETURNING *",
         )
-        .await?;
+        .bind(parent_id)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(id)
+        .bind(user_id)
+        .bind(expected_version)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)
+    }
 
-    let total_pages = (total as f64 / page_size as f64).ceil() as i64;
-    let responses: Vec<FileResponse> = files.into_iter().map(|f| f.into()).collect();
+    /// Files among `ids` owned by `user_id`, in no particular order
+    pub async fn get_files(&self, ids: &[String], user_id: &str) -> Result<Vec<File>, FileError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
 
-    Ok(Json(FileListResponse {
-        files: responses,
-        total,
-        page,
-        page_size,
-        total_pages,
-    }))
-}
+        let mut query = QueryBuilder::<Db>::new("SELECT * FROM files WHERE user_id = ");
+        query.push_bind(user_id.to_string()).push(" AND id IN (");
+        let mut list = query.separated(", ");
+        for id in ids {
+            list.push_bind(id.clone());
+        }
+        query.push(")");
 
-#[utoipa::path(
-    get,
-    path = "/api/files/{id}/download",
-    tag = "files",
-    params(
-        ("id" = String, Path, description = "File ID")
-    ),
-    responses(
-        (status = 200, description = "File download", content_type = "application/octet-stream"),
-        (status = 404, description = "File not found"),
-        (status = 403, description = "Unauthorized")
-    ),
-    security(
-        ("bearer_auth" = [])
+        query
+            .build_query_as::<File>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(FileError::DatabaseError)
+    }
+
+    /// Apply tag changes and a move to each of `ids` in one transaction. Tags are
+    /// expected normalized; a file that would exceed `MAX_TAGS_PER_FILE` is left as is.
+    pub async fn apply_batch(
+        &self,
+        ids: &[String],
+        user_id: &str,
+        add_tags: &[String],
+        remove_tags: &[String],
+        move_to: Option<Option<&str>>,
+    ) -> Result<Vec<BatchStatus>, FileError> {
+        let mut tx = self.pool.begin().await.map_err(FileError::DatabaseError)?;
+        let mut statuses = Vec::with_capacity(ids.len());
+
+        for id in ids {
+            let owned =
+                sqlx::query_scalar::<_, String>("SELECT id FROM files WHERE id = $1 AND user_id = $2")
+                    .bind(id)
+                    .bind(user_id)
+                    .fetch_optional(&mut *tx)
+                    .await
+                    .map_err(FileError::DatabaseError)?;
+            if owned.is_none() {
+                statuses.push(BatchStatus::NotFound);
+                continue;
+            }
+
+            let mut tags: BTreeSet<String> = sqlx::query_scalar("SELECT tag FROM file_tags WHERE file_id = $1")
+                .bind(id)
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(FileError::DatabaseError)?
+                .into_iter()
+                .collect();
+            for tag in remove_tags {
+                tags.remove(tag);
+            }
+            tags.extend(add_tags.iter().cloned());
+            if tags.len() as i64 > MAX_TAGS_PER_FILE {
+                statuses.push(BatchStatus::TooManyTags);
+                continue;
+            }
+
+            for tag in remove_tags {
+                sqlx::query("DELETE FROM file_tags WHERE file_id = $1 AND tag = $2")
+                    .bind(id)
+                    .bind(tag)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(FileError::DatabaseError)?;
+            }
+            for tag in add_tags {
+                sqlx::query(
+                    "INSERT INTO file_tags (file_id, tag) VALUES ($1, $2) ON CONFLICT (file_id, tag) DO NOTHING",
+                )
+                .bind(id)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await
+                .map_err(FileError::DatabaseError)?;
+            }
+            if let Some(parent_id) = move_to {
+                sqlx::query("UPDATE files SET parent_id = $1 WHERE id = $2")
+                    .bind(parent_id)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(FileError::DatabaseError)?;
+            }
+            bump_version(&mut tx, id, None).await?;
+            statuses.push(BatchStatus::Updated);
+        }
+
+        tx.commit().await.map_err(FileError::DatabaseError)?;
+        Ok(statuses)
+    }
+
+    /// Delete a file row, leaving a tombstone. Returns the `storage_path` the row held,
+    /// or None if there was no such row.
+    pub async fn delete_file(&self, id: &str, user_id: &str) -> Result<Option<String>, FileError> {
+        delete_file_row(&self.pool, id, user_id)
+            .await
+            .map_err(FileError::DatabaseError)
+    }
+
+    /// Files created, or whose content or metadata changed, at or after `since`, oldest first
+    pub async fn changed_since(&self, user_id: &str, since: &str) -> Result<Vec<File>, FileError> {
+        sqlx::query_as::<_, File>(
+            "SELECT * FROM files
+             WHERE user_id = $1 AND (created_at >= $2 OR modified_at >= $2)
+             ORDER BY COALESCE(modified_at, created_at), id",
+        )
+        .bind(user_id)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)
+    }
+
+    /// Tombstones of files deleted at or after `since`, oldest first
+    pub async fn deleted_since(&self, user_id: &str, since: &str) -> Result<Vec<DeletedFile>, FileError> {
+        sqlx::query_as::<_, DeletedFile>(
+            "SELECT file_id AS id, deleted_at FROM deleted_files
+             WHERE user_id = $1 AND deleted_at >= $2
+             ORDER BY deleted_at, file_id",
+        )
+        .bind(user_id)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)
+    }
+}
+
+async fn insert_file<'e>(executor: impl sqlx::Executor<'e, Database = Db>, file: &File) -> Result<(), FileError> {
+    sqlx::query(
+        "INSERT INTO files (id, user_id, original_name, mime_type, size_bytes, storage_path, created_at, content_hash, parent_id, server_encryption_algo, server_encryption_nonce, client_file_id, thumbnail_failed) 
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"
+    )
+    .bind(&file.id)
+    .bind(&file.user_id)
+    .bind(&file.original_name)
+    .bind(&file.mime_type)
+    .bind(file.size_bytes)
+    .bind(&file.storage_path)
+    .bind(&file.created_at)
+    .bind(&file.content_hash)
+    .bind(&file.parent_id)
+    .bind(&file.server_encryption_algo)
+    .bind(&file.server_encryption_nonce)
+    .bind(&file.client_file_id)
+    .bind(file.thumbnail_failed)
+    .execute(executor)
+    .await
+    .map_err(FileError::DatabaseError)?;
+
+    Ok(())
+}
+
+/// Point a file at new content within `tx`, if it is still at `expected_version` (when given).
+/// Returns the updated file and the `storage_path` it held before, which the caller
+/// releases once `tx` commits.
+async fn update_content(
+    tx: &mut sqlx::Transaction<'_, Db>,
+    id: &str,
+    user_id: &str,
+    size_bytes: i64,
+    blob: &Blob,
+    expected_version: Option<i64>,
+) -> Result<Option<(File, String)>, FileError> {
+    // A no-op write locks the row, so the path read here is the one this update replaces
+    // even when another replace or a delete races it
+    let previous = sqlx::query_scalar::<_, String>(
+        "UPDATE files SET storage_path = storage_path WHERE id = $1 AND user_id = $2 RETURNING storage_path",
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(FileError::DatabaseError)?;
+    let Some(previous) = previous else {
+        return Ok(None);
+    };
+
+    let updated = sqlx::query_as::<_, File>(
+        "UPDATE files SET size_bytes = $1, content_hash = $2, updated_at = $3, modified_at = $3, storage_path = $4,
+             server_encryption_algo = $5, server_encryption_nonce = $6, version = version + 1,
+             thumbnail_failed = FALSE
+         WHERE id = $7 AND user_id = $8 AND ($9 IS NULL OR version = $9)
+         RETURNING *",
+    )
+    .bind(size_bytes)
+    .bind(&blob.content_hash)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(&blob.storage_path)
+    .bind(&blob.server_encryption_algo)
+    .bind(&blob.server_encryption_nonce)
+    .bind(id)
+    .bind(user_id)
+    .bind(expected_version)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(FileError::DatabaseError)?;
+
+    Ok(updated.map(|file| (file, previous)))
+}
+
+/// Point a file at a freshly stored blob. As with uploads, the blob's reference and the
+/// row commit together, and the new object is deleted unless the row ends up holding it.
+/// Returns what `update_content` does: None if the file is gone or past `expected_version`.
+async fn record_content(
+    pool: &DbPool,
+    storage: &dyn Storage,
+    id: &str,
+    user_id: &str,
+    storage_path: &str,
+    stored: StoredBlob,
+    expected_version: Option<i64>,
+) -> Result<Option<(File, String)>, FileError> {
+    let size_bytes = stored.size_bytes;
+    let recorded: Result<Option<(File, String)>, FileError> = async {
+        let mut tx = pool.begin().await.map_err(FileError::DatabaseError)?;
+        let blob = blobs::store_or_reuse(&mut tx, user_id, storage_path, stored).await?;
+        let updated = update_content(&mut tx, id, user_id, size_bytes, &blob, expected_version).await?;
+        if updated.is_some() {
+            tx.commit().await.map_err(FileError::DatabaseError)?;
+        }
+        Ok(updated)
+    }
+    .await;
+
+    // Rolled back, or an identical blob was reused: nothing refers to the new object
+    if !matches!(&recorded, Ok(Some((file, _))) if file.storage_path == storage_path) {
+        let _ = storage.delete(storage_path).await;
+    }
+    recorded
+}
+
+/// Bump a file's version and `modified_at` within `tx`, if it is still at `expected`
+/// (any version when `None`). Returns false when it has moved on or the file is gone.
+async fn bump_version(
+    tx: &mut sqlx::Transaction<'_, Db>,
+    id: &str,
+    expected: Option<i64>,
+) -> Result<bool, FileError> {
+    let result = sqlx::query(
+        "UPDATE files SET version = version + 1, modified_at = $1
+         WHERE id = $2 AND ($3 IS NULL OR version = $3)",
+    )
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(id)
+    .bind(expected)
+    .execute(&mut **tx)
+    .await
+    .map_err(FileError::DatabaseError)?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete a file row, leaving a tombstone in `deleted_files` so sync clients and usage
+/// deltas learn of the deletion. Returns the `storage_path` the row held, or None if
+/// there was no such row.
+pub(crate) async fn delete_file_row(pool: &DbPool, id: &str, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // Deleting first means that of two racing deletes, only one finds the row
+    let deleted = sqlx::query_as::<_, (String, i64, String)>(
+        "DELETE FROM files WHERE id = $1 AND user_id = $2 RETURNING storage_path, size_bytes, created_at",
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((storage_path, size_bytes, created_at)) = deleted else {
+        return Ok(None);
+    };
+
+    sqlx::query(
+        "INSERT INTO deleted_files (file_id, user_id, size_bytes, deleted_at, created_at)
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(size_bytes)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(&created_at)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Some(storage_path))
+}
+
+/// Files a single batch request may touch
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Tags a single file may carry
+const MAX_TAGS_PER_FILE: i64 = 20;
+const MAX_TAG_LEN: usize = 50;
+
+/// Files per tag query in `load_tags`, well below the databases' bind-parameter limits
+const TAG_BATCH: usize = 500;
+
+/// Trim and lowercase a tag, rejecting empty, overlong or comma-containing ones
+fn normalize_tag(tag: &str) -> Result<String, FileError> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty()
+        || tag.chars().count() > MAX_TAG_LEN
+        || tag.contains(',')
+        || tag.chars().any(char::is_control)
+    {
+        return Err(FileError::InvalidTag);
+    }
+    Ok(tag)
+}
+
+/// Parse a client-supplied MIME type, lowercasing it; empty means `application/octet-stream`
+fn normalize_mime_type(mime_type: &str) -> Result<String, FileError> {
+    let mime_type = mime_type.trim();
+    if mime_type.is_empty() {
+        return Ok(mime::APPLICATION_OCTET_STREAM.to_string());
+    }
+
+    let parsed: mime::Mime = mime_type.parse().map_err(|_| FileError::InvalidMetadata)?;
+    Ok(parsed.to_string().to_ascii_lowercase())
+}
+
+/// Reject a file the upload policy doesn't allow. Checked as soon as its metadata
+/// arrives, so when that comes first the file isn't stored at all.
+fn check_upload_type(metadata: &FileMetadata) -> Result<(), FileError> {
+    let mime_type = normalize_mime_type(&metadata.mime_type)?;
+    if UPLOAD_POLICY.allows(&metadata.original_name, &mime_type) {
+        Ok(())
+    } else {
+        Err(FileError::UnsupportedType)
+    }
+}
+
+/// Largest accepted upload
+const MAX_FILE_SIZE: usize = 100 * 1024 * 1024; // 100MB limit
+
+/// Body limit for the multipart upload routes: one maximal file plus room for the
+/// metadata field and multipart framing
+pub const MAX_UPLOAD_BODY_SIZE: usize = MAX_FILE_SIZE + 1024 * 1024;
+
+/// Uploads streamed at once when `MAX_CONCURRENT_UPLOADS` is unset
+const DEFAULT_CONCURRENT_UPLOADS: usize = 8;
+/// How long an upload waits for a free slot before it is turned away with `503`
+const UPLOAD_SLOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Slots for uploads streaming their body, from `MAX_CONCURRENT_UPLOADS` (0 = unlimited).
+/// Large uploads arriving together would otherwise compete for disk IO and buffers.
+pub fn upload_slots_from_env() -> Arc<Semaphore> {
+    let permits = std::env::var("MAX_CONCURRENT_UPLOADS")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_CONCURRENT_UPLOADS);
+    let permits = if permits == 0 { Semaphore::MAX_PERMITS } else { permits };
+    Arc::new(Semaphore::new(permits))
+}
+
+/// Wait briefly for an upload slot; it is given back when the permit is dropped, so
+/// on every return path of the handler holding it
+async fn acquire_upload_slot(state: &AppState) -> Result<SemaphorePermit<'_>, FileError> {
+    match tokio::time::timeout(UPLOAD_SLOT_TIMEOUT, state.upload_slots.acquire()).await {
+        Ok(Ok(permit)) => Ok(permit),
+        Ok(Err(_closed)) => Err(FileError::InternalError),
+        Err(_elapsed) => Err(FileError::UploadsBusy),
+    }
+}
+
+/// Relative path for a new blob, sharded by the first bytes of its id
+/// (`<user_id>/ab/cd/<id>.bin`) so no directory grows without bound.
+/// Existing rows keep whatever path they were stored with.
+///
+/// User ids are UUIDs; anything else would let the id pick a directory.
+fn blob_storage_path(user_id: &str, blob_id: &str) -> Result<String, FileError> {
+    if Uuid::parse_str(user_id).is_err() {
+        tracing::error!(user_id = %user_id, "Refusing to store a blob for a malformed user id");
+        return Err(FileError::InternalError);
+    }
+    Ok(format!("{}/{}/{}/{}.bin", user_id, &blob_id[..2], &blob_id[2..4], blob_id))
+}
+
+/// The storage volume holding `user_id`'s blobs
+pub(crate) async fn user_storage<'a>(state: &'a AppState, user_id: &str) -> Result<&'a dyn Storage, FileError> {
+    state
+        .storage
+        .for_user(&state.db_pool, user_id)
+        .await
+        .map_err(|_| FileError::StorageError)
+}
+
+/// What `store_field` stored
+pub struct StoredBlob {
+    pub size_bytes: i64,
+    /// SHA-256 (hex) of the bytes as uploaded
+    pub content_hash: String,
+    pub encryption: Option<BlobEncryption>,
+}
+
+/// Stream a multipart field into storage under `key`, encrypting it at rest when
+/// configured. Nothing is kept if the upload fails or is too large.
+///
+/// Refused up front when storage can't hold the `declared` size, and cut off once the
+/// bytes actually received outgrow the space that was free, since `declared` is only
+/// the client's word.
+async fn store_field(
+    storage: &dyn Storage,
+    mut field: Field<'_>,
+    key: &str,
+    declared: u64,
+) -> Result<StoredBlob, FileError> {
+    let available = match storage.available_space().await {
+        Ok(available) => available,
+        Err(e) => {
+            tracing::warn!(error = ?e, "Failed to check available storage space");
+            None
+        }
+    };
+    if available.is_some_and(|available| declared > available) {
+        return Err(FileError::InsufficientStorage);
+    }
+    let limit = available.map_or(MAX_FILE_SIZE, |available| {
+        usize::try_from(available).unwrap_or(usize::MAX).min(MAX_FILE_SIZE)
+    });
+
+    let mut size = 0usize;
+    let mut hasher = Sha256::new();
+
+    let counted = (&mut size, &mut hasher);
+    let plaintext = try_stream! {
+        let (size, hasher) = counted;
+        while let Some(chunk) = field.chunk().await.map_err(|_| io::Error::other("failed to read upload"))? {
+            *size += chunk.len();
+            if *size > limit {
+                Err(io::Error::other("upload too large"))?;
+            }
+            hasher.update(&chunk);
+            yield chunk;
+        }
+    };
+
+    let (data, encryption) = encryption::encrypt(plaintext.boxed());
+    let result = storage.put(key, data).await;
+    if size > limit && limit < MAX_FILE_SIZE {
+        return Err(FileError::InsufficientStorage);
+    } else if size > limit {
+        return Err(FileError::InvalidMetadata); // File too large
+    }
+    result.map_err(|_| FileError::StorageError)?;
+
+    Ok(StoredBlob {
+        size_bytes: size as i64,
+        content_hash: hex::encode(hasher.finalize()),
+        encryption,
+    })
+}
+
+/// A blob written by `upload_file` but not yet recorded in the database
+struct StagedBlob {
+    file_id: String,
+    storage_path: String,
+    stored: StoredBlob,
+}
+
+/// How far `upload_file` has got through the multipart body. The `metadata` and
+/// `file` fields may arrive in either order, but each only once.
+enum UploadState {
+    Empty,
+    /// Metadata read, file still to come
+    Metadata(FileMetadata),
+    /// File stored, metadata still to come
+    Stored(StagedBlob),
+    Ready(FileMetadata, StagedBlob),
+}
+
+impl UploadState {
+    /// The blob written so far, which must be deleted if the upload is abandoned
+    fn staged(&self) -> Option<&StagedBlob> {
+        match self {
+            UploadState::Stored(staged) | UploadState::Ready(_, staged) => Some(staged),
+            UploadState::Empty | UploadState::Metadata(_) => None,
+        }
+    }
+
+    fn accept_metadata(&mut self, metadata: FileMetadata) -> Result<(), FileError> {
+        *self = match std::mem::replace(self, UploadState::Empty) {
+            UploadState::Empty => UploadState::Metadata(metadata),
+            UploadState::Stored(staged) => UploadState::Ready(metadata, staged),
+            current => {
+                *self = current;
+                return Err(FileError::DuplicateField);
+            }
+        };
+        Ok(())
+    }
+
+    fn accept_file(&mut self, staged: StagedBlob) {
+        *self = match std::mem::replace(self, UploadState::Empty) {
+            UploadState::Empty => UploadState::Stored(staged),
+            UploadState::Metadata(metadata) => UploadState::Ready(metadata, staged),
+            // `receive_upload` checks `staged` before writing a second file
+            UploadState::Stored(_) | UploadState::Ready(..) => unreachable!("second file field stored"),
+        };
+    }
+}
+
+/// How `receive_upload` ended
+enum Received {
+    /// A new file, belonging in this folder
+    New(Option<String>),
+    /// The user already uploaded a file under the metadata's `client_file_id`
+    Existing(Box<File>),
+}
+
+const MAX_CLIENT_FILE_ID_LEN: usize = 255;
+
+/// The file `user_id` already uploaded under `metadata`'s `client_file_id`, if any
+async fn find_previous_upload(
+    state: &AppState,
+    user_id: &str,
+    metadata: &FileMetadata,
+) -> Result<Option<File>, FileError> {
+    let Some(client_file_id) = metadata.client_file_id.as_deref() else {
+        return Ok(None);
+    };
+    if client_file_id.is_empty() || client_file_id.chars().count() > MAX_CLIENT_FILE_ID_LEN {
+        return Err(FileError::InvalidMetadata);
+    }
+
+    FileRepository::new(state.db_pool.clone())
+        .get_file_by_client_id(user_id, client_file_id)
+        .await
+}
+
+/// Read the multipart body into `upload` and check what arrived, returning the
+/// folder the file belongs in. Unless a new file was received, the caller deletes
+/// any staged blob.
+async fn receive_upload(
+    state: &AppState,
+    storage: &dyn Storage,
+    user_id: &str,
+    multipart: &mut Multipart,
+    upload: &mut UploadState,
+) -> Result<Received, FileError> {
+    while let Some(field) = multipart.next_field().await.map_err(|_| FileError::InvalidMetadata)? {
+        match field.name() {
+            Some("metadata") => {
+                let data = field.bytes().await.map_err(|_| FileError::InvalidMetadata)?;
+                let metadata = serde_json::from_slice(&data).map_err(|_| FileError::InvalidMetadata)?;
+                check_upload_type(&metadata)?;
+                upload.accept_metadata(metadata)?;
+                // A retried upload stops here, before its file is read when that comes second
+                if let UploadState::Metadata(metadata) | UploadState::Ready(metadata, _) = upload
+                    && let Some(existing) = find_previous_upload(state, user_id, metadata).await?
+                {
+                    return Ok(Received::Existing(Box::new(existing)));
+                }
+            }
+            Some("file") => {
+                // Rejected before reading, so the body isn't stored only to be thrown away
+                if upload.staged().is_some() {
+                    return Err(FileError::DuplicateField);
+                }
+                let file_id = Uuid::new_v4().to_string();
+                let storage_path = blob_storage_path(user_id, &file_id)?;
+                // Only known when the metadata came first
+                let declared = match upload {
+                    UploadState::Metadata(metadata) => u64::try_from(metadata.size_bytes).unwrap_or(0),
+                    _ => 0,
+                };
+                let stored = store_field(storage, field, &storage_path, declared).await?;
+                upload.accept_file(StagedBlob { file_id, storage_path, stored });
+            }
+            // Unknown fields are skipped
+            _ => {}
+        }
+    }
+
+    let UploadState::Ready(metadata, staged) = upload else {
+        return Err(FileError::InvalidMetadata);
+    };
+
+    check_upload(state, user_id, metadata, staged).await.map(Received::New)
+}
+
+/// Check a received file against its metadata, normalizing the MIME type, and return
+/// the folder it belongs in
+async fn check_upload(
+    state: &AppState,
+    user_id: &str,
+    metadata: &mut FileMetadata,
+    staged: &StagedBlob,
+) -> Result<Option<String>, FileError> {
+    // The type ends up in download headers, so only well-formed values are kept
+    metadata.mime_type = normalize_mime_type(&metadata.mime_type)?;
+
+    // Catch truncated or corrupted uploads the client can describe up front
+    let stored = &staged.stored;
+    let size_matches = metadata
+        .expected_size
+        .is_none_or(|expected| expected == stored.size_bytes);
+    let hash_matches = metadata
+        .expected_sha256
+        .as_deref()
+        .is_none_or(|expected| expected.eq_ignore_ascii_case(&stored.content_hash));
+    if !size_matches || !hash_matches {
+        return Err(FileError::IntegrityMismatch);
+    }
+
+    resolve_upload_folder(&state.db_pool, user_id, metadata.folder_id.as_deref(), &metadata.mime_type).await
+}
+
+/// How `record_upload` ended
+enum Recorded {
+    /// A new file, and with `on_conflict: replace` the file of the same name that it
+    /// replaces, which is still there for the caller to remove
+    Created { file: File, replaces: Option<Box<File>> },
+    /// A concurrent retry recorded the same `client_file_id` first; this is its file
+    Existing(File),
+}
+
+/// `name` with ` (n)` before its extension, e.g. `report (1).pdf`
+fn numbered_name(name: &str, n: u32) -> String {
+    match name.rfind('.') {
+        // A leading dot (".bashrc") starts the name rather than an extension
+        Some(dot) if dot > 0 => format!("{} ({}){}", &name[..dot], n, &name[dot..]),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+/// Apply `metadata.on_conflict` when the folder already has a file of the same name:
+/// pick a free name, or return the file to replace. Best effort, since uploads
+/// running at the same time don't see each other's names.
+async fn resolve_name_conflict(
+    pool: &DbPool,
+    user_id: &str,
+    metadata: &mut FileMetadata,
+    parent_id: Option<&str>,
+) -> Result<Option<Box<File>>, FileError> {
+    let file_repo = FileRepository::new(pool.clone());
+    let Some(existing) = file_repo
+        .find_by_name(user_id, parent_id, &metadata.original_name)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    match metadata.on_conflict {
+        OnConflict::Error => Err(FileError::NameConflict),
+        OnConflict::Replace => Ok(Some(Box::new(existing))),
+        OnConflict::Rename => {
+            for n in 1.. {
+                let candidate = numbered_name(&metadata.original_name, n);
+                if file_repo.find_by_name(user_id, parent_id, &candidate).await?.is_none() {
+                    metadata.original_name = candidate;
+                    break;
+                }
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Record a checked file: its blob, then its row
+async fn record_upload(
+    pool: &DbPool,
+    storage: &dyn Storage,
+    user_id: &str,
+    mut metadata: FileMetadata,
+    staged: StagedBlob,
+    parent_id: Option<String>,
+) -> Result<Recorded, FileError> {
+    let StagedBlob { file_id, storage_path, stored } = staged;
+
+    let replaces = match resolve_name_conflict(pool, user_id, &mut metadata, parent_id.as_deref()).await {
+        Ok(replaces) => replaces,
+        Err(e) => {
+            let _ = storage.delete(&storage_path).await;
+            return Err(e);
+        }
+    };
+
+    let actual_size = stored.size_bytes; // Use actual size from stream
+    let client_file_id = metadata.client_file_id.clone();
+
+    // The blob's reference and the row holding it commit together, and storage is only
+    // touched once it's known which of them exist
+    let recorded: Result<File, FileError> = async {
+        let mut tx = pool.begin().await.map_err(FileError::DatabaseError)?;
+        let blob = blobs::store_or_reuse(&mut tx, user_id, &storage_path, stored).await?;
+        let file = File {
+            id: file_id,
+            user_id: user_id.to_string(),
+            original_name: metadata.original_name,
+            mime_type: metadata.mime_type,
+            size_bytes: actual_size,
+            storage_path: blob.storage_path.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            content_hash: blob.content_hash,
+            parent_id,
+            updated_at: None,
+            server_encryption_algo: blob.server_encryption_algo,
+            server_encryption_nonce: blob.server_encryption_nonce,
+            download_count: 0,
+            last_accessed_at: None,
+            client_file_id: metadata.client_file_id,
+            version: 1,
+            thumbnail_failed: false,
+            tags: Vec::new(),
+        };
+        insert_file(&mut *tx, &file).await?;
+        tx.commit().await.map_err(FileError::DatabaseError)?;
+        Ok(file)
+    }
+    .await;
+
+    let file = match recorded {
+        Ok(file) => file,
+        Err(e) => {
+            // Rolled back, so nothing refers to the new object
+            let _ = storage.delete(&storage_path).await;
+            // Lost a race with a concurrent retry of the same upload
+            if let Some(client_file_id) = client_file_id.as_deref()
+                && let Ok(Some(existing)) = FileRepository::new(pool.clone())
+                    .get_file_by_client_id(user_id, client_file_id)
+                    .await
+            {
+                return Ok(Recorded::Existing(existing));
+            }
+            return Err(e);
+        }
+    };
+    // An identical blob was reused
+    if file.storage_path != storage_path {
+        let _ = storage.delete(&storage_path).await;
+    }
+    Ok(Recorded::Created { file, replaces })
+}
+
+/// Count and audit a newly created upload
+fn log_upload(state: &AppState, file: &File) {
+    counter!(monitoring::UPLOAD_BYTES_TOTAL).increment(file.size_bytes as u64);
+    let name = Some(file.original_name.as_str());
+    audit::record(&state.db_pool, Some(&file.user_id), audit::FILE_UPLOADED, Some(&file.id), name);
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/upload",
+    tag = "files",
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "File uploaded successfully", body = UploadResponse),
+        (status = 200, description = "A file with this client_file_id was already uploaded; it is returned unchanged", body = UploadResponse),
+        (status = 400, description = "Invalid request"),
+        (status = 409, description = "The folder already has a file of this name and on_conflict is error"),
+        (status = 415, description = "File type or extension not allowed by ALLOWED_MIME_TYPES/ALLOWED_EXTENSIONS"),
+        (status = 422, description = "Content doesn't match expected_size/expected_sha256"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Too many uploads in progress; see Retry-After")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn upload_file(
+    claims: Claims,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<UploadResponse>), FileError> {
+    let _slot = acquire_upload_slot(&state).await?;
+    let storage = user_storage(&state, &claims.user_id).await?;
+    let mut upload = UploadState::Empty;
+
+    let received = receive_upload(&state, storage, &claims.user_id, &mut multipart, &mut upload).await;
+    // Whatever went wrong, or if the file was uploaded before, a blob already written
+    // must not be left behind
+    if !matches!(received, Ok(Received::New(_)))
+        && let Some(staged) = upload.staged()
+    {
+        let _ = storage.delete(&staged.storage_path).await;
+    }
+    let parent_id = match received? {
+        Received::New(parent_id) => parent_id,
+        Received::Existing(file) => return already_uploaded(&state, *file).await,
+    };
+    let UploadState::Ready(metadata, staged) = upload else {
+        return Err(FileError::InternalError);
+    };
+
+    let file = match record_upload(&state.db_pool, storage, &claims.user_id, metadata, staged, parent_id).await? {
+        Recorded::Created { file, replaces } => {
+            if let Some(replaced) = replaces {
+                remove_file(&state, storage, &replaced).await?;
+            }
+            file
+        }
+        Recorded::Existing(file) => return already_uploaded(&state, file).await,
+    };
+    log_upload(&state, &file);
+
+    let receipt = UploadReceipt::issue(&file).map_err(|_| FileError::InternalError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(UploadResponse {
+            file: file.into(),
+            receipt,
+        }),
+    ))
+}
+
+/// Answer a retried upload with the file it created the first time
+async fn already_uploaded(
+    state: &AppState,
+    mut file: File,
+) -> Result<(StatusCode, Json<UploadResponse>), FileError> {
+    FileRepository::new(state.db_pool.clone())
+        .load_tags(std::slice::from_mut(&mut file))
+        .await?;
+    let receipt = UploadReceipt::issue(&file).map_err(|_| FileError::InternalError)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(UploadResponse {
+            file: file.into(),
+            receipt,
+        }),
+    ))
+}
+
+/// Most files one multi-file upload may carry
+const MAX_FILES_PER_UPLOAD: usize = 100;
+
+/// A file read by `receive_uploads`, not yet recorded
+enum PendingUpload {
+    New {
+        metadata: FileMetadata,
+        staged: StagedBlob,
+        parent_id: Option<String>,
+    },
+    /// Already uploaded under its `client_file_id`; the file field was skipped
+    Existing(File),
+}
+
+impl PendingUpload {
+    fn staged(&self) -> Option<&StagedBlob> {
+        match self {
+            PendingUpload::New { staged, .. } => Some(staged),
+            PendingUpload::Existing(_) => None,
+        }
+    }
+}
+
+/// Read a multi-file body into `pending`. Each `file` field belongs to the `metadata`
+/// field right before it, and is stored and checked as it streams in. On error, the
+/// caller deletes the blobs staged in `pending`.
+async fn receive_uploads(
+    state: &AppState,
+    storage: &dyn Storage,
+    user_id: &str,
+    multipart: &mut Multipart,
+    pending: &mut Vec<PendingUpload>,
+) -> Result<(), FileError> {
+    let mut current: Option<FileMetadata> = None;
+    let mut client_file_ids = BTreeSet::new();
+
+    while let Some(field) = multipart.next_field().await.map_err(|_| FileError::InvalidMetadata)? {
+        match field.name() {
+            Some("metadata") => {
+                if current.is_some() {
+                    return Err(FileError::UnpairedField);
+                }
+                if pending.len() == MAX_FILES_PER_UPLOAD {
+                    return Err(FileError::TooManyFiles);
+                }
+                let data = field.bytes().await.map_err(|_| FileError::InvalidMetadata)?;
+                let metadata: FileMetadata =
+                    serde_json::from_slice(&data).map_err(|_| FileError::InvalidMetadata)?;
+                check_upload_type(&metadata)?;
+                // Two files under one idempotency key can't both be kept
+                if let Some(client_file_id) = &metadata.client_file_id
+                    && !client_file_ids.insert(client_file_id.clone())
+                {
+                    return Err(FileError::InvalidMetadata);
+                }
+                current = Some(metadata);
+            }
+            Some("file") => {
+                let mut metadata = current.take().ok_or(FileError::UnpairedField)?;
+                // Skipping the field leaves its body unread
+                if let Some(existing) = find_previous_upload(state, user_id, &metadata).await? {
+                    pending.push(PendingUpload::Existing(existing));
+                    continue;
+                }
+
+                let file_id = Uuid::new_v4().to_string();
+                let storage_path = blob_storage_path(user_id, &file_id)?;
+                let declared = u64::try_from(metadata.size_bytes).unwrap_or(0);
+                let stored = store_field(storage, field, &storage_path, declared).await?;
+                let staged = StagedBlob { file_id, storage_path, stored };
+
+                match check_upload(state, user_id, &mut metadata, &staged).await {
+                    Ok(parent_id) => pending.push(PendingUpload::New { metadata, staged, parent_id }),
+                    Err(e) => {
+                        let _ = storage.delete(&staged.storage_path).await;
+                        return Err(e);
+                    }
+                }
+            }
+            // Unknown fields are skipped
+            _ => {}
+        }
+    }
+
+    if current.is_some() {
+        return Err(FileError::UnpairedField);
+    }
+    if pending.is_empty() {
+        return Err(FileError::InvalidMetadata);
+    }
+    Ok(())
+}
+
+/// Remove files recorded by a multi-file upload that failed later on
+async fn discard_recorded(state: &AppState, storage: &dyn Storage, files: &[File]) {
+    let file_repo = FileRepository::new(state.db_pool.clone());
+    let blob_repo = BlobRepository::new(state.db_pool.clone());
+    for file in files {
+        let storage_path = match file_repo.delete_file(&file.id, &file.user_id).await {
+            Ok(Some(storage_path)) => storage_path,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::error!(error = ?e, file_id = %file.id, "Failed to roll back uploaded file");
+                continue;
+            }
+        };
+        if let Err(e) = blob_repo.release(storage, &storage_path).await {
+            tracing::warn!(error = ?e, storage_path = %storage_path, "Failed to release blob");
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/upload/multiple",
+    tag = "files",
+    request_body(content = MultiUploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "Every file uploaded, in request order", body = Vec<UploadResponse>),
+        (status = 400, description = "Invalid metadata, unpaired field or too many files"),
+        (status = 409, description = "A file's name is taken in its folder and its on_conflict is error"),
+        (status = 415, description = "A file's type or extension is not allowed"),
+        (status = 422, description = "A file doesn't match its expected_size/expected_sha256"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Too many uploads in progress; see Retry-After")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn upload_files(
+    claims: Claims,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<Vec<UploadResponse>>), FileError> {
+    let _slot = acquire_upload_slot(&state).await?;
+    let storage = user_storage(&state, &claims.user_id).await?;
+
+    let mut pending = Vec::new();
+    let received = receive_uploads(&state, storage, &claims.user_id, &mut multipart, &mut pending).await;
+    if let Err(e) = received {
+        for staged in pending.iter().filter_map(PendingUpload::staged) {
+            let _ = storage.delete(&staged.storage_path).await;
+        }
+        return Err(e);
+    }
+
+    // Nothing is recorded until the whole body has arrived, and a failure while
+    // recording removes the files recorded before it, so the upload is all or nothing
+    let mut files = Vec::with_capacity(pending.len());
+    let mut created = Vec::new();
+    let mut replaced = Vec::new();
+    let mut pending = pending.into_iter();
+    while let Some(upload) = pending.next() {
+        let (metadata, staged, parent_id) = match upload {
+            PendingUpload::Existing(file) => {
+                files.push(file);
+                continue;
+            }
+            PendingUpload::New { metadata, staged, parent_id } => (metadata, staged, parent_id),
+        };
+        match record_upload(&state.db_pool, storage, &claims.user_id, metadata, staged, parent_id).await {
+            Ok(Recorded::Created { file, replaces }) => {
+                created.push(file.clone());
+                files.push(file);
+                replaced.extend(replaces.map(|file| *file));
+            }
+            Ok(Recorded::Existing(file)) => files.push(file),
+            Err(e) => {
+                for staged in pending.as_slice().iter().filter_map(PendingUpload::staged) {
+                    let _ = storage.delete(&staged.storage_path).await;
+                }
+                discard_recorded(&state, storage, &created).await;
+                return Err(e);
+            }
+        }
+    }
+
+    // Replaced files go only now, so a failed upload leaves them in place
+    for file in &replaced {
+        remove_file(&state, storage, file).await?;
+    }
+    for file in &created {
+        log_upload(&state, file);
+    }
+    FileRepository::new(state.db_pool.clone()).load_tags(&mut files).await?;
+
+    let uploads = files
+        .into_iter()
+        .map(|file| {
+            let receipt = UploadReceipt::issue(&file).map_err(|_| FileError::InternalError)?;
+            Ok(UploadResponse {
+                file: file.into(),
+                receipt,
+            })
+        })
+        .collect::<Result<Vec<_>, FileError>>()?;
+
+    Ok((StatusCode::CREATED, Json(uploads)))
+}
+
+/// Open a file's blob as a stream of the bytes originally uploaded
+pub async fn open_stored_file(state: &AppState, file: &File) -> io::Result<BlobStream> {
+    let storage = state.storage.for_user(&state.db_pool, &file.user_id).await?;
+    let blob = storage.get(&file.storage_path).await?;
+    encryption::decrypt(
+        blob,
+        file.server_encryption_algo.as_deref(),
+        file.server_encryption_nonce.as_deref(),
+    )
+}
+
+/// Compute the SHA-256 (hex) of a file's content as uploaded
+pub async fn hash_stored_file(state: &AppState, file: &File) -> io::Result<String> {
+    let mut stream = open_stored_file(state, file).await?;
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = stream.next().await {
+        hasher.update(&chunk?);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// A file's content hash, computed and stored first for files uploaded before hashing
+/// existed (only computed in maintenance mode)
+pub(crate) async fn ensure_content_hash(state: &AppState, file: &mut File) -> Result<String, FileError> {
+    if let Some(content_hash) = &file.content_hash {
+        return Ok(content_hash.clone());
+    }
+
+    let content_hash = hash_stored_file(state, file)
+        .await
+        .map_err(|_| FileError::StorageError)?;
+    if !state.maintenance.is_enabled() {
+        FileRepository::new(state.db_pool.clone())
+            .set_content_hash(&file.id, &content_hash)
+            .await?;
+    }
+    file.content_hash = Some(content_hash.clone());
+    Ok(content_hash)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files",
+    tag = "files",
+    params(FileQuery),
+    responses(
+        (status = 200, description = "Files retrieved successfully", body = FileListResponse,
+            headers(
+                ("X-Total-Count" = i64, description = "Total matching files"),
+                ("Link" = String, description = "RFC 5988 first/prev/next/last page links")
+            )
+        ),
+        (status = 400, description = "Invalid timestamp or cursor"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_files_handler(
+    claims: Claims,
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(query): Query<FileQuery>,
+) -> Result<(HeaderMap, Json<FileListResponse>), FileError> {
+    let file_repo = FileRepository::new(state.db_pool.clone());
+
+    let filter = FileFilter::from_query(&query)?;
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(20).max(1).min(100);
+
+    let total = file_repo.count_files(&claims.user_id, &filter).await?;
+
+    if let Some(cursor) = query.cursor.as_deref() {
+        let cursor = match cursor {
+            "" => None,
+            cursor => Some(FileCursor::decode(cursor)?),
+        };
+
+        // One extra row tells whether another page follows
+        let mut files = file_repo
+            .list_files_after(&claims.user_id, &filter, cursor.as_ref(), page_size + 1)
+            .await?;
+        let next_cursor = if files.len() as i64 > page_size {
+            files.truncate(page_size as usize);
+            files.last().map(|file| {
+                FileCursor {
+                    created_at: file.created_at.clone(),
+                    id: file.id.clone(),
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+        file_repo.load_tags(&mut files).await?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(X_TOTAL_COUNT.clone(), HeaderValue::from(total));
+
+        return Ok((
+            headers,
+            Json(FileListResponse {
+                files: files.into_iter().map(FileResponse::from).collect(),
+                total,
+                page: 1,
+                page_size,
+                total_pages: (total as f64 / page_size as f64).ceil() as i64,
+                next_cursor,
+            }),
+        ));
+    }
+
+    let mut files = file_repo
+        .list_files(
+            &claims.user_id,
+            &filter,
+            query.sort.as_deref(),
+            query.direction.as_deref(),
+            page,
+            page_size,
+        )
+        .await?;
+    file_repo.load_tags(&mut files).await?;
+
+    let total_pages = (total as f64 / page_size as f64).ceil() as i64;
+    let responses: Vec<FileResponse> = files.into_iter().map(|f| f.into()).collect();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(X_TOTAL_COUNT.clone(), HeaderValue::from(total));
+    if let Ok(link) = HeaderValue::from_str(&pagination_links(&uri, page, page_size, total_pages)) {
+        headers.insert(header::LINK, link);
+    }
+
+    Ok((
+        headers,
+        Json(FileListResponse {
+            files: responses,
+            total,
+            page,
+            page_size,
+            total_pages,
+            next_cursor: None,
+        }),
+    ))
+}
+
+/// Total number of matching items, alongside the paginated body
+pub static X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+/// RFC 5988 `Link` header with `first`/`prev`/`next`/`last` pages of the same query
+fn pagination_links(uri: &Uri, page: i64, page_size: i64, total_pages: i64) -> String {
+    // Keep every other parameter (already percent-encoded) as the client sent it
+    let params: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && key != "page" && key != "page_size"
+        })
+        .collect();
+    let link = |target: i64, rel: &str| {
+        let mut query = params.clone();
+        let paging = format!("page={}&page_size={}", target, page_size);
+        query.push(&paging);
+        format!("<{}?{}>; rel=\"{}\"", uri.path(), query.join("&"), rel)
+    };
+
+    let last = total_pages.max(1);
+    let mut links = vec![link(1, "first")];
+    if page > 1 {
+        links.push(link((page - 1).min(last), "prev"));
+    }
+    if page < last {
+        links.push(link(page + 1, "next"));
+    }
+    links.push(link(last, "last"));
+    links.join(", ")
+}
+
+/// Largest page size accepted by the streamed listing
+const STREAM_MAX_PAGE_SIZE: i64 = 1000;
+
+#[utoipa::path(
+    get,
+    path = "/api/files/stream",
+    tag = "files",
+    params(FileQuery),
+    responses(
+        (status = 200, description = "JSON array of files, streamed as rows are read", body = Vec<FileResponse>),
+        (status = 400, description = "Invalid timestamp"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn stream_files_handler(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<FileQuery>,
+) -> Response {
+    let file_repo = FileRepository::new(state.db_pool.clone());
+
+    let filter = match FileFilter::from_query(&query) {
+        Ok(filter) => filter,
+        Err(e) => return e.into_response(),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(STREAM_MAX_PAGE_SIZE).clamp(1, STREAM_MAX_PAGE_SIZE);
+
+    let files = file_repo.stream_files(
+        &claims.user_id,
+        &filter,
+        query.sort.as_deref(),
+        query.direction.as_deref(),
+        page,
+        page_size,
+    );
+
+    // Emit `[`, then each file prefixed by a comma after the first, then `]`.
+    // A database error mid-stream ends the array early so the body stays valid JSON.
+    let body = stream! {
+        yield Ok::<_, std::convert::Infallible>(Bytes::from_static(b"["));
+
+        let mut first = true;
+        futures_util::pin_mut!(files);
+        while let Some(result) = files.next().await {
+            let file = match result {
+                Ok(file) => file,
+                Err(e) => {
+                    tracing::error!(error = ?e, "Streamed file listing aborted");
+                    break;
+                }
+            };
+            let Ok(json) = serde_json::to_vec(&FileResponse::from(file)) else {
+                break;
+            };
+
+            let mut chunk = Vec::with_capacity(json.len() + 1);
+            if !first {
+                chunk.push(b',');
+            }
+            chunk.extend_from_slice(&json);
+            first = false;
+            yield Ok(Bytes::from(chunk));
+        }
+
+        yield Ok(Bytes::from_static(b"]"));
+    };
+
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// How far `ChangesResponse::until` trails the clock: longer than a file row takes
+/// from getting its timestamp to being committed
+const CHANGES_OVERLAP: chrono::Duration = chrono::Duration::seconds(5);
+
+#[utoipa::path(
+    get,
+    path = "/api/files/changes",
+    tag = "files",
+    params(ChangesQuery),
+    responses(
+        (status = 200, description = "Files created, updated or deleted since the timestamp", body = ChangesResponse),
+        (status = 400, description = "Invalid timestamp")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn file_changes(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<ChangesResponse>, FileError> {
+    let since = parse_timestamp(&query.since)?;
+    // Taken before reading, so nothing written after the reads is skipped next time
+    let until = (chrono::Utc::now() - CHANGES_OVERLAP).to_rfc3339();
+
+    let file_repo = FileRepository::new(state.db_pool.clone());
+    let mut changed = file_repo.changed_since(&claims.user_id, &since).await?;
+    let deleted = file_repo.deleted_since(&claims.user_id, &since).await?;
+    file_repo.load_tags(&mut changed).await?;
+
+    let (created, updated): (Vec<File>, Vec<File>) =
+        changed.into_iter().partition(|file| file.created_at >= since);
+
+    Ok(Json(ChangesResponse {
+        // Never behind `since`, so a client whose clock runs ahead doesn't go backwards
+        until: until.max(since.clone()),
+        since,
+        created: created.into_iter().map(Into::into).collect(),
+        updated: updated.into_iter().map(Into::into).collect(),
+        deleted,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/recent",
+    tag = "files",
+    params(QuickListQuery),
+    responses(
+        (status = 200, description = "The most recently uploaded files, newest first", body = [FileResponse])
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn recent_files(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<QuickListQuery>,
+) -> Result<Json<Vec<FileResponse>>, FileError> {
+    quick_list(&state, &claims.user_id, "date", query.limit).await.map(Json)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/largest",
+    tag = "files",
+    params(QuickListQuery),
+    responses(
+        (status = 200, description = "The largest files, biggest first", body = [FileResponse])
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn largest_files(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<QuickListQuery>,
+) -> Result<Json<Vec<FileResponse>>, FileError> {
+    quick_list(&state, &claims.user_id, "size", query.limit).await.map(Json)
+}
+
+/// The first `limit` of a user's files by `sort`, descending
+async fn quick_list(
+    state: &AppState,
+    user_id: &str,
+    sort: &str,
+    limit: Option<i64>,
+) -> Result<Vec<FileResponse>, FileError> {
+    let limit = limit.unwrap_or(10).clamp(1, 100);
+    let file_repo = FileRepository::new(state.db_pool.clone());
+    let mut files = file_repo
+        .list_files(user_id, &FileFilter::default(), Some(sort), Some("desc"), 1, limit)
+        .await?;
+    file_repo.load_tags(&mut files).await?;
+
+    Ok(files.into_iter().map(FileResponse::from).collect())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{id}",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    responses(
+        (status = 200, description = "File metadata", body = FileResponse,
+            headers(("ETag" = String, description = "Weak ETag of the file's version, for If-Match"))
+        ),
+        (status = 404, description = "File not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_file_metadata(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<(HeaderMap, Json<FileResponse>), FileError> {
+    let file_repo = FileRepository::new(state.db_pool.clone());
+    let mut file = file_repo
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
+    file_repo.load_tags(std::slice::from_mut(&mut file)).await?;
+
+    Ok((version_etag(file.version), Json(file.into())))
+}
+
+/// `ETag` header for a file at `version`. Weak, since it tracks the file's metadata
+/// rather than identifying the bytes of a representation.
+fn version_etag(version: i64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(etag) = HeaderValue::from_str(&format!("W/\"{}\"", version)) {
+        headers.insert(header::ETAG, etag);
+    }
+    headers
+}
+
+/// The version a request's `If-Match` insists on: `None` without the header or for
+/// `*`. Takes a single ETag, compared weakly; anything that isn't one of ours can't
+/// match, so it fails the precondition.
+fn if_match_version(headers: &HeaderMap) -> Result<Option<i64>, FileError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value.to_str().map_err(|_| FileError::VersionMismatch)?.trim();
+    if value == "*" {
+        return Ok(None);
+    }
+
+    value
+        .trim_start_matches("W/")
+        .strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+        .and_then(|version| version.parse().ok())
+        .map(Some)
+        .ok_or(FileError::VersionMismatch)
+}
+
+/// Why a conditional update matched no row: the file is gone, or it has changed
+async fn missed_update(file_repo: &FileRepository, id: &str, user_id: &str, expected_version: Option<i64>) -> FileError {
+    if expected_version.is_none() {
+        return FileError::NotFound;
+    }
+    match file_repo.get_file(id, user_id).await {
+        Ok(Some(_)) => FileError::VersionMismatch,
+        Ok(None) => FileError::NotFound,
+        Err(e) => e,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{id}/download",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID"),
+        DownloadQuery
+    ),
+    responses(
+        (status = 200, description = "File download", content_type = "application/octet-stream"),
+        (status = 204, description = "`hash_only`: the content hash in `X-Content-Hash` and `ETag`, without the file"),
+        (status = 304, description = "`If-None-Match` carries the content hash; the client already has this content"),
+        (status = 404, description = "File not found"),
+        (status = 403, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn download_file(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, FileError> {
+    let file_repo = FileRepository::new(state.db_pool.clone());
+
+    let mut file = file_repo
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
+
+    if query.hash_only {
+        let content_hash = ensure_content_hash(&state, &mut file).await?;
+        let mut response_headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(&content_hash) {
+            response_headers.insert(X_CONTENT_HASH.clone(), value);
+        }
+        if let Some(etag) = content_etag(&file) {
+            response_headers.insert(header::ETAG, etag);
+        }
+        return Ok((StatusCode::NO_CONTENT, response_headers).into_response());
+    }
+
+    // Nothing is transferred, so it doesn't count as a download either
+    if let Some(etag) = content_etag(&file)
+        .filter(|etag| etag.to_str().is_ok_and(|etag| static_files::if_none_match(&headers, etag)))
+    {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    stream_file(&state, &file).await
+}
+
+/// Raw SHA-256 (hex) of a file's content, sent by `?hash_only=true` downloads
+pub static X_CONTENT_HASH: HeaderName = HeaderName::from_static("x-content-hash");
+
+/// Strong ETag of a download: the content hash, quoted
+fn content_etag(file: &File) -> Option<HeaderValue> {
+    file.content_hash
+        .as_ref()
+        .and_then(|hash| HeaderValue::from_str(&format!("\"{}\"", hash)).ok())
+}
+
+#[utoipa::path(
+    head,
+    path = "/api/files/{id}/download",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    responses(
+        (status = 200, description = "Download headers (type, length, name, ETag) without the body"),
+        (status = 404, description = "File not found")
+    ),
+    security(
+        ("bearer_auth" = [])
     )
 )]
-pub async fn download_file(
+pub async fn download_file_head(
     claims: Claims,
     State(state): State<AppState>,
     Path(id): Path<String>,
-) -> Result<Response, FileError> {
-    let file_repo = FileRepository::new(state.db_pool.clone());
-
-    let file = file_repo
+) -> Result<HeaderMap, FileError> {
+    let file = FileRepository::new(state.db_pool.clone())
         .get_file(&id, &claims.user_id)
         .await?
         .ok_or(FileError::NotFound)?;
 
-    let full_path = state.storage_root.join(&file.storage_path);
+    // Not a download: the blob isn't read and `download_count` is left alone
+    Ok(download_headers(&state, &file).await)
+}
 
-    let file_handle = tokio::fs::File::open(&full_path)
+/// Stream a stored file back as an attachment
+pub(crate) async fn stream_file(state: &AppState, file: &File) -> Result<Response, FileError> {
+    let stream = open_stored_file(state, file)
         .await
         .map_err(|_| FileError::StorageError)?;
 
-    let stream = ReaderStream::new(file_handle);
+    counter!(monitoring::DOWNLOAD_BYTES_TOTAL).increment(file.size_bytes as u64);
+    // Maintenance mode writes nothing, so the download goes uncounted
+    if !state.maintenance.is_enabled() {
+        FileRepository::new(state.db_pool.clone())
+            .record_download(&file.id)
+            .await?;
+    }
+
     let body = axum::body::Body::from_stream(stream);
 
+    Ok((download_headers(state, file).await, body).into_response())
+}
+
+/// Whether the stored blob is exactly as large as `size_bytes` implies, so the stored
+/// size can be promised as `Content-Length`
+async fn blob_size_matches(state: &AppState, file: &File) -> bool {
+    let Ok(storage) = user_storage(state, &file.user_id).await else {
+        return false;
+    };
+    let Ok(Some(actual)) = storage.size(&file.storage_path).await else {
+        return false;
+    };
+
+    let expected = encryption::stored_len(file.size_bytes as u64, file.server_encryption_algo.as_deref());
+    if actual != expected {
+        tracing::warn!(
+            file_id = %file.id,
+            expected,
+            actual,
+            "Blob size doesn't match the file record; sending without Content-Length"
+        );
+        return false;
+    }
+    true
+}
+
+/// Headers describing a download, shared by `GET` and `HEAD`
+async fn download_headers(state: &AppState, file: &File) -> HeaderMap {
     // Sanitize filename to prevent header injection
     let safe_filename = sanitize_filename(&file.original_name);
 
@@ -407,14 +2568,21 @@ pub async fn download_file(
         header::CONTENT_TYPE,
         "application/octet-stream".parse().unwrap(),
     );
+    // A wrong length would cut the body short or leave the client waiting, so a
+    // mismatched blob is sent chunked instead
+    if blob_size_matches(state, file).await {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(file.size_bytes));
+    }
     headers.insert(
         header::CONTENT_DISPOSITION,
         format!("attachment; filename=\"{}\"", safe_filename)
             .parse()
             .unwrap_or_else(|_| "attachment; filename=\"download.bin\"".parse().unwrap()),
     );
-
-    Ok((headers, body).into_response())
+    if let Some(etag) = content_etag(file) {
+        headers.insert(header::ETAG, etag);
+    }
+    headers
 }
 
 /// Sanitize filename by removing/replacing invalid header characters
@@ -466,13 +2634,568 @@ pub async fn delete_file(
         .await?
         .ok_or(FileError::NotFound)?;
 
-    let full_path = state.storage_root.join(&file.storage_path);
+    let storage = user_storage(&state, &claims.user_id).await?;
+    if !remove_file(&state, storage, &file).await? {
+        return Err(FileError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    tokio::fs::remove_file(&full_path)
+/// Delete a file on its owner's behalf: its row (leaving a tombstone), thumbnails and
+/// its reference to the blob. Returns false if the row was already gone, in which case
+/// whoever deleted it released the blob.
+async fn remove_file(state: &AppState, storage: &dyn Storage, file: &File) -> Result<bool, FileError> {
+    let Some(storage_path) = FileRepository::new(state.db_pool.clone())
+        .delete_file(&file.id, &file.user_id)
+        .await?
+    else {
+        return Ok(false);
+    };
+    thumbnails::invalidate(storage, &file.id).await;
+    let name = Some(file.original_name.as_str());
+    audit::record(&state.db_pool, Some(&file.user_id), audit::FILE_DELETED, Some(&file.id), name);
+
+    // The row is gone either way; a blob already missing from storage counts as deleted,
+    // and one that fails to delete is only wasted space
+    if let Err(e) = BlobRepository::new(state.db_pool.clone())
+        .release(storage, &storage_path)
         .await
-        .map_err(|_| FileError::StorageError)?;
+    {
+        tracing::warn!(error = ?e, storage_path = %storage_path, "Failed to release blob");
+    }
+    Ok(true)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/{id}/copy",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    request_body = CopyFileRequest,
+    responses(
+        (status = 201, description = "File copied", body = FileResponse),
+        (status = 400, description = "Invalid name"),
+        (status = 404, description = "File not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn copy_file(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    payload: Option<Json<CopyFileRequest>>,
+) -> Result<(StatusCode, Json<FileResponse>), FileError> {
+    let Json(payload) = payload.unwrap_or_default();
+    let file_repo = FileRepository::new(state.db_pool.clone());
+
+    let source = file_repo
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
 
-    file_repo.delete_file(&id, &claims.user_id).await?;
+    let original_name = match payload.name {
+        Some(name) if name.trim().is_empty() => return Err(FileError::InvalidMetadata),
+        Some(name) => name.trim().to_string(),
+        None => format!("Copy of {}", source.original_name),
+    };
 
-    Ok(StatusCode::NO_CONTENT)
+    // Identical content, so the copy just references the source's blob
+    let storage = user_storage(&state, &claims.user_id).await?;
+    let blob_repo = BlobRepository::new(state.db_pool.clone());
+    if !blob_repo.add_reference(&source.storage_path).await? {
+        return Err(FileError::NotFound);
+    }
+
+    let copy = File {
+        id: Uuid::new_v4().to_string(),
+        user_id: claims.user_id.clone(),
+        original_name,
+        mime_type: source.mime_type,
+        size_bytes: source.size_bytes,
+        storage_path: source.storage_path,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        content_hash: source.content_hash,
+        parent_id: source.parent_id,
+        updated_at: None,
+        server_encryption_algo: source.server_encryption_algo,
+        server_encryption_nonce: source.server_encryption_nonce,
+        download_count: 0,
+        last_accessed_at: None,
+        client_file_id: None,
+        version: 1,
+        // Same content, so the same outcome
+        thumbnail_failed: source.thumbnail_failed,
+        tags: Vec::new(),
+    };
+
+    if let Err(e) = file_repo.create_file(&copy).await {
+        let _ = blob_repo.release(storage, &copy.storage_path).await;
+        return Err(e);
+    }
+
+    Ok((StatusCode::CREATED, Json(copy.into())))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/{id}/move",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    request_body = MoveFileRequest,
+    responses(
+        (status = 200, description = "File moved", body = FileResponse),
+        (status = 404, description = "File or destination folder not found"),
+        (status = 412, description = "If-Match doesn't match the file's current ETag")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn move_file(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<MoveFileRequest>,
+) -> Result<(HeaderMap, Json<FileResponse>), FileError> {
+    let expected_version = if_match_version(&headers)?;
+    // Someone else's folder is indistinguishable from a missing one
+    if let Some(folder_id) = payload.folder_id.as_deref() {
+        FolderRepository::new(state.db_pool.clone())
+            .get_folder(folder_id, &claims.user_id)
+            .await?
+            .ok_or(FileError::FolderNotFound)?;
+    }
+
+    let file_repo = FileRepository::new(state.db_pool.clone());
+    let moved = file_repo
+        .move_file(&id, &claims.user_id, payload.folder_id.as_deref(), expected_version)
+        .await?;
+    let Some(mut file) = moved else {
+        return Err(missed_update(&file_repo, &id, &claims.user_id, expected_version).await);
+    };
+    file_repo.load_tags(std::slice::from_mut(&mut file)).await?;
+
+    Ok((version_etag(file.version), Json(file.into())))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/{id}/tags",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    request_body = AddTagRequest,
+    responses(
+        (status = 200, description = "Tag added (or already present)", body = FileResponse),
+        (status = 400, description = "Invalid tag, or the file already has 20 tags"),
+        (status = 404, description = "File not found"),
+        (status = 412, description = "If-Match doesn't match the file's current ETag")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn add_file_tag(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<AddTagRequest>,
+) -> Result<(HeaderMap, Json<FileResponse>), FileError> {
+    let tag = normalize_tag(&payload.tag)?;
+    let expected_version = if_match_version(&headers)?;
+    let file_repo = FileRepository::new(state.db_pool.clone());
+
+    file_repo
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
+
+    if !file_repo.add_tag(&id, &tag, expected_version).await? {
+        return Err(FileError::TooManyTags);
+    }
+    tagged_file(&file_repo, &id, &claims.user_id).await
+}
+
+/// A file as it is after a tag change, with its new ETag
+async fn tagged_file(
+    file_repo: &FileRepository,
+    id: &str,
+    user_id: &str,
+) -> Result<(HeaderMap, Json<FileResponse>), FileError> {
+    let mut file = file_repo.get_file(id, user_id).await?.ok_or(FileError::NotFound)?;
+    file_repo.load_tags(std::slice::from_mut(&mut file)).await?;
+
+    Ok((version_etag(file.version), Json(file.into())))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/files/{id}/tags/{tag}",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID"),
+        ("tag" = String, Path, description = "Tag to remove (case-insensitive)")
+    ),
+    responses(
+        (status = 200, description = "Tag removed", body = FileResponse),
+        (status = 404, description = "File not found or doesn't have the tag"),
+        (status = 412, description = "If-Match doesn't match the file's current ETag")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn remove_file_tag(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((id, tag)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<(HeaderMap, Json<FileResponse>), FileError> {
+    let expected_version = if_match_version(&headers)?;
+    let file_repo = FileRepository::new(state.db_pool.clone());
+
+    file_repo
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
+
+    if !file_repo.remove_tag(&id, &tag.trim().to_lowercase(), expected_version).await? {
+        return Err(FileError::TagNotFound);
+    }
+    tagged_file(&file_repo, &id, &claims.user_id).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/batch",
+    tag = "files",
+    request_body = BatchRequest,
+    responses(
+        (status = 200, description = "Per-file results; files not owned by the caller are skipped", body = BatchResponse),
+        (status = 400, description = "Invalid batch or tag"),
+        (status = 404, description = "Destination folder not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn batch_update_files(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(payload): Json<BatchRequest>,
+) -> Result<Json<BatchResponse>, FileError> {
+    let distinct: BTreeSet<&String> = payload.ids.iter().collect();
+    let has_operation =
+        !payload.add_tags.is_empty() || !payload.remove_tags.is_empty() || payload.move_to.is_some();
+    if payload.ids.is_empty()
+        || payload.ids.len() > MAX_BATCH_SIZE
+        || distinct.len() != payload.ids.len()
+        || !has_operation
+    {
+        return Err(FileError::InvalidBatch);
+    }
+
+    let add_tags = payload
+        .add_tags
+        .iter()
+        .map(|tag| normalize_tag(tag))
+        .collect::<Result<Vec<_>, _>>()?;
+    let remove_tags: Vec<String> = payload.remove_tags.iter().map(|tag| tag.trim().to_lowercase()).collect();
+    let move_to = payload.move_to.as_ref().map(Option::as_deref);
+
+    // Someone else's folder is indistinguishable from a missing one
+    if let Some(Some(folder_id)) = move_to {
+        FolderRepository::new(state.db_pool.clone())
+            .get_folder(folder_id, &claims.user_id)
+            .await?
+            .ok_or(FileError::FolderNotFound)?;
+    }
+
+    let file_repo = FileRepository::new(state.db_pool.clone());
+    let statuses = file_repo
+        .apply_batch(&payload.ids, &claims.user_id, &add_tags, &remove_tags, move_to)
+        .await?;
+
+    let updated_ids: Vec<String> = payload
+        .ids
+        .iter()
+        .zip(&statuses)
+        .filter(|(_, status)| **status == BatchStatus::Updated)
+        .map(|(id, _)| id.clone())
+        .collect();
+    let mut files = file_repo.get_files(&updated_ids, &claims.user_id).await?;
+    file_repo.load_tags(&mut files).await?;
+    let mut files: HashMap<String, File> = files.into_iter().map(|file| (file.id.clone(), file)).collect();
+
+    let results: Vec<BatchResult> = payload
+        .ids
+        .into_iter()
+        .zip(statuses)
+        .map(|(id, status)| BatchResult {
+            file: files.remove(&id).map(FileResponse::from),
+            id,
+            status,
+        })
+        .collect();
+    let updated = updated_ids.len() as i64;
+
+    Ok(Json(BatchResponse {
+        skipped: results.len() as i64 - updated,
+        results,
+        updated,
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/files/{id}/content",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    request_body(content = ContentForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Content replaced", body = FileResponse),
+        (status = 400, description = "Missing or oversized file field"),
+        (status = 404, description = "File not found"),
+        (status = 412, description = "If-Match doesn't match the file's current ETag"),
+        (status = 503, description = "Too many uploads in progress; see Retry-After")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn replace_file_content(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<(HeaderMap, Json<FileResponse>), FileError> {
+    let expected_version = if_match_version(&headers)?;
+    let file_repo = FileRepository::new(state.db_pool.clone());
+
+    let file = file_repo
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
+    // Checked again when the row is updated; this only saves streaming a doomed body
+    if expected_version.is_some_and(|expected| expected != file.version) {
+        return Err(FileError::VersionMismatch);
+    }
+
+    // New content goes to a fresh blob: the old one may be shared with other files,
+    // and stays intact until the row points at the replacement
+    let storage = user_storage(&state, &claims.user_id).await?;
+    let storage_path = blob_storage_path(&claims.user_id, &Uuid::new_v4().to_string())?;
+
+    let _slot = acquire_upload_slot(&state).await?;
+    let mut written = None;
+    while let Some(field) = multipart.next_field().await.map_err(|_| FileError::InvalidMetadata)? {
+        if field.name() == Some("file") {
+            written = Some(store_field(storage, field, &storage_path, 0).await?);
+            break;
+        }
+    }
+    let stored = written.ok_or(FileError::InvalidMetadata)?;
+
+    let recorded =
+        record_content(&state.db_pool, storage, &id, &claims.user_id, &storage_path, stored, expected_version).await?;
+    let Some((mut updated, previous_path)) = recorded else {
+        return Err(missed_update(&file_repo, &id, &claims.user_id, expected_version).await);
+    };
+
+    let blob_repo = BlobRepository::new(state.db_pool.clone());
+    if let Err(e) = blob_repo.release(storage, &previous_path).await {
+        tracing::warn!(error = ?e, storage_path = %previous_path, "Failed to release blob");
+    }
+    thumbnails::invalidate(storage, &file.id).await;
+    file_repo.load_tags(std::slice::from_mut(&mut updated)).await?;
+
+    counter!(monitoring::UPLOAD_BYTES_TOTAL).increment(updated.size_bytes as u64);
+
+    Ok((version_etag(updated.version), Json(updated.into())))
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    const USER_ID: &str = "00000000-0000-4000-8000-000000000001";
+
+    /// A migrated database with one user, and a storage root, in a fresh temp directory
+    async fn setup() -> (TempDir, DbPool, LocalStorage) {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}?mode=rwc", dir.path().join("trusty.db").display());
+        let pool = DbPool::connect(&url).await.unwrap();
+        crate::db::MIGRATOR.run(&pool).await.unwrap();
+        sqlx::query("INSERT INTO users (id, username, password_hash) VALUES ($1, 'tester', 'x')")
+            .bind(USER_ID)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let storage = LocalStorage::new(dir.path().join("storage")).await.unwrap();
+        (dir, pool, storage)
+    }
+
+    /// Write `content` under a fresh blob key, as the handlers do before recording it
+    async fn stage(storage: &LocalStorage, file_id: &str, content: &'static [u8]) -> StagedBlob {
+        let storage_path = blob_storage_path(USER_ID, &Uuid::new_v4().to_string()).unwrap();
+        let data = futures_util::stream::iter([Ok(Bytes::from_static(content))]).boxed();
+        storage.put(&storage_path, data).await.unwrap();
+        StagedBlob {
+            file_id: file_id.to_string(),
+            storage_path,
+            stored: StoredBlob {
+                size_bytes: content.len() as i64,
+                content_hash: hex::encode(Sha256::digest(content)),
+                encryption: None,
+            },
+        }
+    }
+
+    fn metadata(name: &str, client_file_id: Option<&str>) -> FileMetadata {
+        serde_json::from_value(json!({
+            "original_name": name,
+            "mime_type": "text/plain",
+            "size_bytes": 0,
+            "client_encryption_algo": "AES-GCM-256",
+            "client_file_id": client_file_id,
+        }))
+        .unwrap()
+    }
+
+    async fn upload(pool: &DbPool, storage: &LocalStorage, content: &'static [u8]) -> File {
+        let staged = stage(storage, &Uuid::new_v4().to_string(), content).await;
+        match record_upload(pool, storage, USER_ID, metadata("existing.txt", None), staged, None).await {
+            Ok(Recorded::Created { file, .. }) => file,
+            _ => panic!("upload wasn't recorded"),
+        }
+    }
+
+    /// The blob's reference count, or None when it has no `blobs` row
+    async fn ref_count(pool: &DbPool, storage_path: &str) -> Option<i64> {
+        sqlx::query_scalar("SELECT ref_count FROM blobs WHERE storage_path = $1")
+            .bind(storage_path)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_upload_insert_discards_new_blob() {
+        let (_dir, pool, storage) = setup().await;
+        let existing = upload(&pool, &storage, b"first").await;
+
+        // Reusing the id makes the row insert fail after the blob row was written
+        let staged = stage(&storage, &existing.id, b"second").await;
+        let storage_path = staged.storage_path.clone();
+        let result = record_upload(&pool, &storage, USER_ID, metadata("second.txt", None), staged, None).await;
+
+        assert!(matches!(result, Err(FileError::DatabaseError(_))));
+        assert!(!storage.exists(&storage_path).await.unwrap());
+        assert_eq!(ref_count(&pool, &storage_path).await, None);
+    }
+
+    #[tokio::test]
+    async fn duplicate_client_file_id_discards_new_blob() {
+        let (_dir, pool, storage) = setup().await;
+        let first = stage(&storage, &Uuid::new_v4().to_string(), b"first").await;
+        let Ok(Recorded::Created { file: existing, .. }) =
+            record_upload(&pool, &storage, USER_ID, metadata("a.txt", Some("retry")), first, None).await
+        else {
+            panic!("upload wasn't recorded");
+        };
+
+        let staged = stage(&storage, &Uuid::new_v4().to_string(), b"second").await;
+        let storage_path = staged.storage_path.clone();
+        let result = record_upload(&pool, &storage, USER_ID, metadata("b.txt", Some("retry")), staged, None).await;
+
+        assert!(matches!(result, Ok(Recorded::Existing(file)) if file.id == existing.id));
+        assert!(!storage.exists(&storage_path).await.unwrap());
+        assert_eq!(ref_count(&pool, &storage_path).await, None);
+    }
+
+    #[tokio::test]
+    async fn failed_upload_of_identical_content_drops_its_reference() {
+        let (_dir, pool, storage) = setup().await;
+        let existing = upload(&pool, &storage, b"same").await;
+
+        // The reused blob gains a reference before the insert fails
+        let staged = stage(&storage, &existing.id, b"same").await;
+        let storage_path = staged.storage_path.clone();
+        let result = record_upload(&pool, &storage, USER_ID, metadata("copy.txt", None), staged, None).await;
+
+        assert!(result.is_err());
+        assert!(!storage.exists(&storage_path).await.unwrap());
+        assert_eq!(ref_count(&pool, &existing.storage_path).await, Some(1));
+        assert!(storage.exists(&existing.storage_path).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_content_update_discards_new_blob() {
+        let (_dir, pool, storage) = setup().await;
+        let existing = upload(&pool, &storage, b"old").await;
+        sqlx::query("CREATE TRIGGER fail_file_updates BEFORE UPDATE ON files BEGIN SELECT RAISE(ABORT, 'injected'); END")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let staged = stage(&storage, &existing.id, b"new").await;
+        let result =
+            record_content(&pool, &storage, &existing.id, USER_ID, &staged.storage_path, staged.stored, None).await;
+
+        assert!(matches!(result, Err(FileError::DatabaseError(_))));
+        assert!(!storage.exists(&staged.storage_path).await.unwrap());
+        assert_eq!(ref_count(&pool, &staged.storage_path).await, None);
+        assert_eq!(ref_count(&pool, &existing.storage_path).await, Some(1));
+        assert!(storage.exists(&existing.storage_path).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn content_update_of_missing_file_discards_new_blob() {
+        let (_dir, pool, storage) = setup().await;
+        let missing_id = Uuid::new_v4().to_string();
+
+        let staged = stage(&storage, &missing_id, b"new").await;
+        let result =
+            record_content(&pool, &storage, &missing_id, USER_ID, &staged.storage_path, staged.stored, None).await;
+
+        assert!(matches!(result, Ok(None)));
+        assert!(!storage.exists(&staged.storage_path).await.unwrap());
+        assert_eq!(ref_count(&pool, &staged.storage_path).await, None);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_file_whose_blob_is_missing_succeeds() {
+        let (_dir, pool, storage) = setup().await;
+        let file = upload(&pool, &storage, b"gone").await;
+        storage.delete(&file.storage_path).await.unwrap();
+        // Deleting an object that is already gone is not an error
+        assert!(storage.delete(&file.storage_path).await.is_ok());
+
+        let file_repo = FileRepository::new(pool.clone());
+        let storage_path = file_repo.delete_file(&file.id, USER_ID).await.unwrap();
+        assert_eq!(storage_path.as_deref(), Some(file.storage_path.as_str()));
+        BlobRepository::new(pool.clone())
+            .release(&storage, &file.storage_path)
+            .await
+            .unwrap();
+
+        assert!(file_repo.get_file(&file.id, USER_ID).await.unwrap().is_none());
+        assert_eq!(ref_count(&pool, &file.storage_path).await, None);
+    }
 }
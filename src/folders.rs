@@ -0,0 +1,200 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::db::DbPool;
+use crate::auth::Claims;
+use crate::filemanager::FileError;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Folder {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FolderResponse {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub created_at: String,
+}
+
+impl From<Folder> for FolderResponse {
+    fn from(folder: Folder) -> Self {
+        Self {
+            id: folder.id,
+            name: folder.name,
+            parent_id: folder.parent_id,
+            created_at: folder.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateFolderRequest {
+    pub name: String,
+    /// Parent folder (omit for root)
+    pub parent_id: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct FolderQuery {
+    /// List children of this folder (omit for root)
+    pub parent_id: Option<String>,
+}
+
+pub struct FolderRepository {
+    pool: DbPool,
+}
+
+impl FolderRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create_folder(
+        &self,
+        user_id: &str,
+        name: &str,
+        parent_id: Option<&str>,
+    ) -> Result<Folder, FileError> {
+        let name = name.trim();
+        if name.is_empty() || name.len() > 255 || name.contains('/') {
+            return Err(FileError::InvalidFolderName);
+        }
+
+        if let Some(parent_id) = parent_id {
+            self.get_folder(parent_id, user_id)
+                .await?
+                .ok_or(FileError::FolderNotFound)?;
+        }
+
+        let folder = Folder {
+            id: Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            name: name.to_string(),
+            parent_id: parent_id.map(str::to_string),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+
+        sqlx::query(
+            "INSERT INTO folders (id, user_id, name, parent_id, created_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&folder.id)
+        .bind(&folder.user_id)
+        .bind(&folder.name)
+        .bind(&folder.parent_id)
+        .bind(&folder.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        Ok(folder)
+    }
+
+    pub async fn get_folder(&self, id: &str, user_id: &str) -> Result<Option<Folder>, FileError> {
+        sqlx::query_as::<_, Folder>("SELECT * FROM folders WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(FileError::DatabaseError)
+    }
+
+    pub async fn list_folders(
+        &self,
+        user_id: &str,
+        parent_id: Option<&str>,
+    ) -> Result<Vec<Folder>, FileError> {
+        sqlx::query_as::<_, Folder>(
+            "SELECT * FROM folders WHERE user_id = $1 AND parent_id IS NOT DISTINCT FROM $2 ORDER BY name ASC",
+        )
+        .bind(user_id)
+        .bind(parent_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)
+    }
+
+    /// Find a root-level folder by name, creating it if it doesn't exist yet
+    pub async fn find_or_create_root_folder(
+        &self,
+        user_id: &str,
+        name: &str,
+    ) -> Result<Folder, FileError> {
+        let existing = sqlx::query_as::<_, Folder>(
+            "SELECT * FROM folders WHERE user_id = $1 AND parent_id IS NULL AND name = $2",
+        )
+        .bind(user_id)
+        .bind(name.trim())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        match existing {
+            Some(folder) => Ok(folder),
+            None => self.create_folder(user_id, name, None).await,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/folders",
+    tag = "files",
+    request_body = CreateFolderRequest,
+    responses(
+        (status = 201, description = "Folder created", body = FolderResponse),
+        (status = 400, description = "Invalid folder name"),
+        (status = 404, description = "Parent folder not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn create_folder(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateFolderRequest>,
+) -> Result<(StatusCode, Json<FolderResponse>), FileError> {
+    let folder = FolderRepository::new(state.db_pool.clone())
+        .create_folder(&claims.user_id, &payload.name, payload.parent_id.as_deref())
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(folder.into())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/folders",
+    tag = "files",
+    params(FolderQuery),
+    responses(
+        (status = 200, description = "Folders in the given parent", body = Vec<FolderResponse>)
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_folders(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<FolderQuery>,
+) -> Result<Json<Vec<FolderResponse>>, FileError> {
+    let folders = FolderRepository::new(state.db_pool.clone())
+        .list_folders(&claims.user_id, query.parent_id.as_deref())
+        .await?;
+
+    Ok(Json(folders.into_iter().map(|f| f.into()).collect()))
+}
@@ -0,0 +1,73 @@
+use axum::{Json, body::Bytes, extract::State, http::StatusCode};
+use futures_util::StreamExt;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::AppState;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthStatus {
+    /// `ok` or `unavailable`
+    pub status: String,
+    /// Database answered a trivial query
+    pub db: bool,
+    /// Every storage volume accepts writes (in maintenance mode: answers a lookup)
+    pub storage: bool,
+}
+
+/// Liveness/readiness probe (unauthenticated)
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses(
+        (status = 200, description = "Service healthy", body = HealthStatus),
+        (status = 503, description = "Database or storage unavailable", body = HealthStatus)
+    )
+)]
+pub async fn health(State(state): State<AppState>) -> (StatusCode, Json<HealthStatus>) {
+    let db = sqlx::query("SELECT 1").execute(&state.db_pool).await.is_ok();
+    let storage = storage_writable(&state).await;
+
+    let (status_code, status) = if db && storage {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+
+    (
+        status_code,
+        Json(HealthStatus {
+            status: status.to_string(),
+            db,
+            storage,
+        }),
+    )
+}
+
+/// Write and remove a small probe object on every volume; in maintenance mode, which
+/// writes nothing, only look one up. Each probe gets its own key so concurrent probes
+/// can't delete each other's object mid-check.
+async fn storage_writable(state: &AppState) -> bool {
+    let probe = format!(".health-probe-{}", Uuid::new_v4());
+
+    if state.maintenance.is_enabled() {
+        for volume in state.storage.iter() {
+            if volume.exists(&probe).await.is_err() {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    for volume in state.storage.iter() {
+        let data = futures_util::stream::iter([Ok(Bytes::from_static(b"ok"))]).boxed();
+        let writable = volume.put(&probe, data).await.is_ok();
+        let _ = volume.delete(&probe).await;
+        if !writable {
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,123 @@
+use std::net::SocketAddr;
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_server::tls_rustls::RustlsConfig;
+use serde_json::json;
+
+/// Paths that are always served over whatever scheme the request arrived on
+const EXEMPT_PATHS: &[&str] = &["/health"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct HttpsPolicy {
+    /// Redirect/reject plain-HTTP requests (`FORCE_HTTPS`)
+    pub force: bool,
+    /// Trust `X-Forwarded-Proto` from a reverse proxy (`TRUST_FORWARDED_PROTO`); only
+    /// honored when the peer is listed in `TRUSTED_PROXY`
+    pub trust_forwarded_proto: bool,
+    /// The server terminates TLS itself, so every request arrived over HTTPS
+    pub terminates_tls: bool,
+}
+
+impl HttpsPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            force: crate::env_flag("FORCE_HTTPS"),
+            trust_forwarded_proto: crate::env_flag("TRUST_FORWARDED_PROTO"),
+            terminates_tls: tls_paths().is_some(),
+        }
+    }
+
+    /// Whether the request reached us over HTTPS, either directly or via a trusted proxy
+    fn is_https(&self, req: &Request) -> bool {
+        if self.terminates_tls || req.uri().scheme_str() == Some("https") {
+            return true;
+        }
+
+        if !self.trust_forwarded_proto {
+            return false;
+        }
+        // Like X-Forwarded-For, the header is only as trustworthy as whoever sent it
+        let from_trusted_proxy = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .is_some_and(|ConnectInfo(peer)| crate::client_ip::is_trusted(peer.ip()));
+        if !from_trusted_proxy {
+            return false;
+        }
+
+        // Anything before the last entry may have been sent by the client itself; the
+        // last one was set by the proxy that connected to us. Repeated headers form one list
+        req.headers()
+            .get_all("x-forwarded-proto")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .last()
+            .is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https"))
+    }
+}
+
+/// `TLS_CERT_PATH` and `TLS_KEY_PATH`, when the server should terminate TLS itself.
+///
+/// Panics if only one of them is set, since serving plain HTTP then is surely a mistake.
+pub fn tls_paths() -> Option<(String, String)> {
+    let cert = std::env::var("TLS_CERT_PATH").ok().filter(|v| !v.is_empty());
+    let key = std::env::var("TLS_KEY_PATH").ok().filter(|v| !v.is_empty());
+
+    match (cert, key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+    }
+}
+
+/// Load the PEM certificate chain and private key named by `TLS_CERT_PATH`/`TLS_KEY_PATH`
+pub async fn rustls_config() -> Option<RustlsConfig> {
+    let (cert, key) = tls_paths()?;
+
+    let config = RustlsConfig::from_pem_file(&cert, &key)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to load TLS certificate {} / key {}: {}", cert, key, e));
+    Some(config)
+}
+
+/// Redirect safe requests to HTTPS and reject mutating requests made over plain HTTP.
+pub async fn enforce_https(
+    State(policy): State<HttpsPolicy>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !policy.force || EXEMPT_PATHS.contains(&req.uri().path()) || policy.is_https(&req) {
+        return next.run(req).await;
+    }
+
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok());
+
+    match (req.method(), host) {
+        (&Method::GET | &Method::HEAD, Some(host)) => {
+            let path_and_query = req
+                .uri()
+                .path_and_query()
+                .map(|pq| pq.as_str())
+                .unwrap_or("/");
+            Redirect::permanent(&format!("https://{}{}", host, path_and_query)).into_response()
+        }
+        _ => (
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "error": "HTTPS is required for this request",
+                "request_id": crate::request_id::current(),
+            })),
+        )
+            .into_response(),
+    }
+}
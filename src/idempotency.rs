@@ -0,0 +1,284 @@
+//! Safe retries of creating requests through the `Idempotency-Key` header.
+//!
+//! The first successful response to a request carrying a key is kept for
+//! `IDEMPOTENCY_KEY_TTL_SECS` (default 86400), and a request repeating the key within
+//! that time gets it back, marked `Idempotent-Replayed: true`, instead of running
+//! again. Keys are scoped to the method and path, and to the caller: the user for
+//! authenticated routes, the exact request body for anonymous ones, so a key alone
+//! never replays someone else's response. Failed requests aren't kept and can be
+//! retried with the same key; a repeat while the first is still running gets `409`.
+//!
+//! Tokens in a response aren't stored with it, so a replayed signup comes without its
+//! `access_token` and the client logs in instead.
+
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{FromRequestParts, Request, State},
+    http::{HeaderName, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::AppState;
+use crate::auth::Claims;
+use crate::db::DbPool;
+
+pub static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+/// Set on responses replayed from an earlier request with the same key
+pub static IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Longest accepted key; UUIDs and similar random tokens fit easily
+const MAX_KEY_LEN: usize = 255;
+
+/// Response fields left out of stored responses, since anyone reading the database or
+/// a backup could use them
+const UNSTORED_FIELDS: &[&str] = &["access_token"];
+
+static KEY_TTL: LazyLock<chrono::Duration> = LazyLock::new(|| {
+    let secs = std::env::var("IDEMPOTENCY_KEY_TTL_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(86400);
+    chrono::Duration::seconds(secs)
+});
+
+/// Who a key belongs to on the routes a layer wraps
+#[derive(Debug, Clone, Copy)]
+pub enum Caller {
+    /// The authenticated user
+    User,
+    /// Nobody in particular; the request body stands in for the caller
+    Anonymous,
+}
+
+/// A response kept for replay
+#[derive(Debug, sqlx::FromRow)]
+struct StoredResponse {
+    status: Option<i64>,
+    content_type: Option<String>,
+    body: Option<String>,
+}
+
+enum Claim {
+    /// The key is new (or expired) and now reserved for this request
+    New,
+    /// A request with the key is still running
+    InProgress,
+    Replay(StoredResponse),
+}
+
+/// Reserve `key` in `scope`, or find what an earlier request with it left
+async fn claim(pool: &DbPool, scope: &str, key: &str) -> Result<Claim, sqlx::Error> {
+    let now = chrono::Utc::now();
+
+    sqlx::query("DELETE FROM idempotency_keys WHERE scope = $1 AND idempotency_key = $2 AND expires_at <= $3")
+        .bind(scope)
+        .bind(key)
+        .bind(now.to_rfc3339())
+        .execute(pool)
+        .await?;
+
+    let inserted = sqlx::query(
+        "INSERT INTO idempotency_keys (scope, idempotency_key, created_at, expires_at)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(scope)
+    .bind(key)
+    .bind(now.to_rfc3339())
+    .bind((now + *KEY_TTL).to_rfc3339())
+    .execute(pool)
+    .await?;
+    if inserted.rows_affected() > 0 {
+        return Ok(Claim::New);
+    }
+
+    let stored = sqlx::query_as::<_, StoredResponse>(
+        "SELECT status, content_type, body FROM idempotency_keys WHERE scope = $1 AND idempotency_key = $2",
+    )
+    .bind(scope)
+    .bind(key)
+    .fetch_optional(pool)
+    .await?;
+    Ok(match stored {
+        Some(stored) if stored.status.is_some() => Claim::Replay(stored),
+        // Still running, or released just now by a failed first attempt; either way the
+        // client should try again shortly
+        _ => Claim::InProgress,
+    })
+}
+
+/// A reserved key, released again unless `keep` stores a response under it. Dropping
+/// it covers requests that are cancelled or time out as well as ones that fail.
+struct Reservation {
+    pool: DbPool,
+    scope: String,
+    key: String,
+    kept: bool,
+}
+
+impl Reservation {
+    async fn keep(mut self, status: StatusCode, content_type: Option<&str>, body: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE idempotency_keys SET status = $1, content_type = $2, body = $3
+             WHERE scope = $4 AND idempotency_key = $5",
+        )
+        .bind(status.as_u16() as i64)
+        .bind(content_type)
+        .bind(body)
+        .bind(&self.scope)
+        .bind(&self.key)
+        .execute(&self.pool)
+        .await?;
+        self.kept = true;
+        Ok(())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+        let (pool, scope, key) = (self.pool.clone(), std::mem::take(&mut self.scope), std::mem::take(&mut self.key));
+        tokio::spawn(async move {
+            let released = sqlx::query(
+                "DELETE FROM idempotency_keys WHERE scope = $1 AND idempotency_key = $2 AND status IS NULL",
+            )
+            .bind(&scope)
+            .bind(&key)
+            .execute(&pool)
+            .await;
+            if let Err(e) = released {
+                tracing::warn!(error = ?e, scope = %scope, "Failed to release idempotency key");
+            }
+        });
+    }
+}
+
+fn rejection(status: StatusCode, message: &str) -> Response {
+    (
+        status,
+        Json(json!({
+            "error": message,
+            "request_id": crate::request_id::current(),
+        })),
+    )
+        .into_response()
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let status = stored
+        .status
+        .and_then(|status| StatusCode::from_u16(status as u16).ok())
+        .unwrap_or(StatusCode::OK);
+    let mut response = (status, stored.body.unwrap_or_default()).into_response();
+    let headers = response.headers_mut();
+    if let Some(content_type) = stored.content_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    headers.insert(IDEMPOTENT_REPLAYED.clone(), HeaderValue::from_static("true"));
+    response
+}
+
+/// `body` without `UNSTORED_FIELDS`, for keeping
+fn storable(body: &str) -> Cow<'_, str> {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(mut object))
+            if UNSTORED_FIELDS.iter().any(|field| object.contains_key(*field)) =>
+        {
+            for field in UNSTORED_FIELDS {
+                object.remove(*field);
+            }
+            Cow::Owned(serde_json::Value::Object(object).to_string())
+        }
+        _ => Cow::Borrowed(body),
+    }
+}
+
+/// Middleware for routes whose keys belong to `caller`; requests without the header
+/// pass straight through
+pub async fn idempotent(
+    State((state, caller)): State<(AppState, Caller)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(key) = req.headers().get(&IDEMPOTENCY_KEY) else {
+        return next.run(req).await;
+    };
+    let key = match key.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        _ => {
+            return rejection(
+                StatusCode::BAD_REQUEST,
+                "Invalid Idempotency-Key (1-255 visible ASCII characters)",
+            );
+        }
+    };
+
+    let (mut parts, body) = req.into_parts();
+    let mut scope = format!("{} {}", parts.method, parts.uri.path());
+    let body = match caller {
+        Caller::User => {
+            let claims = match Claims::from_request_parts(&mut parts, &state).await {
+                Ok(claims) => claims,
+                Err(e) => return e.into_response(),
+            };
+            scope = format!("{} {}", scope, claims.user_id);
+            body
+        }
+        Caller::Anonymous => {
+            // Anonymous routes take small bodies, capped by the body limit outside
+            let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+                return rejection(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large");
+            };
+            scope = format!("{} {}", scope, hex::encode(Sha256::digest(&bytes)));
+            Body::from(bytes)
+        }
+    };
+
+    let pool = state.db_pool.clone();
+    match claim(&pool, &scope, &key).await {
+        Ok(Claim::New) => {}
+        Ok(Claim::Replay(stored)) => return replay(stored),
+        Ok(Claim::InProgress) => {
+            return rejection(
+                StatusCode::CONFLICT,
+                "A request with this Idempotency-Key is still in progress",
+            );
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, "Failed to look up idempotency key");
+            return rejection(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+        }
+    }
+    let reservation = Reservation { pool, scope, key, kept: false };
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if !response.status().is_success() {
+        return response;
+    }
+
+    // The wrapped handlers answer with small JSON documents
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return rejection(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+    };
+    let content_type = parts.headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    match std::str::from_utf8(&bytes) {
+        Ok(text) => {
+            if let Err(e) = reservation.keep(parts.status, content_type, &storable(text)).await {
+                tracing::warn!(error = ?e, "Failed to store idempotent response");
+            }
+        }
+        Err(_) => tracing::warn!("Not storing a non-text idempotent response"),
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
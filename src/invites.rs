@@ -0,0 +1,154 @@
+use std::sync::LazyLock;
+
+use axum::{Json, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::audit;
+use crate::auth::{AdminUser, AuthError};
+use crate::db::DbPool;
+use crate::user::{generate_token, hash_token};
+
+/// Who may create an account, from `SIGNUP_MODE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignupMode {
+    /// Anyone (default)
+    Open,
+    /// Only holders of an unused invite code
+    Invite,
+    /// Nobody; existing accounts keep working
+    Closed,
+}
+
+pub static SIGNUP_MODE: LazyLock<SignupMode> = LazyLock::new(|| {
+    match std::env::var("SIGNUP_MODE").unwrap_or_default().trim().to_ascii_lowercase().as_str() {
+        "" | "open" => SignupMode::Open,
+        "invite" => SignupMode::Invite,
+        "closed" => SignupMode::Closed,
+        other => panic!("SIGNUP_MODE must be open, invite or closed (got {:?})", other),
+    }
+});
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CreateInviteRequest {
+    /// Seconds until the code stops working (omit for no expiry)
+    pub expires_in_seconds: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InviteResponse {
+    /// Pass as `code` to signup; shown only in this response
+    pub code: String,
+    pub expires_at: Option<String>,
+}
+
+pub struct InviteRepository {
+    pool: DbPool,
+}
+
+impl InviteRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Store a new code, returning it in plaintext
+    pub async fn create(&self, created_by: &str, expires_at: Option<&str>) -> Result<String, AuthError> {
+        let code = generate_token();
+
+        sqlx::query(
+            "INSERT INTO invite_codes (code_hash, created_by, created_at, expires_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(hash_token(&code))
+        .bind(created_by)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+        Ok(code)
+    }
+
+    /// Mark a live code used. A single `UPDATE`, so two signups racing for the same
+    /// code can't both succeed. Returns false for unknown, used or expired codes.
+    pub async fn consume(&self, code: &str) -> Result<bool, AuthError> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let result = sqlx::query(
+            "UPDATE invite_codes SET used_at = $1
+             WHERE code_hash = $2 AND used_at IS NULL AND (expires_at IS NULL OR expires_at > $3)",
+        )
+        .bind(&now)
+        .bind(hash_token(code))
+        .bind(&now)
+        .execute(&self.pool)
+        .await
+        .map_err(|_| AuthError::InternalError)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Record the account a consumed code created
+    pub async fn set_used_by(&self, code: &str, user_id: &str) -> Result<(), AuthError> {
+        sqlx::query("UPDATE invite_codes SET used_by = $1 WHERE code_hash = $2")
+            .bind(user_id)
+            .bind(hash_token(code))
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::InternalError)?;
+
+        Ok(())
+    }
+
+    /// Hand a consumed code back after the signup it was spent on failed
+    pub async fn release(&self, code: &str) -> Result<(), AuthError> {
+        sqlx::query("UPDATE invite_codes SET used_at = NULL WHERE code_hash = $1 AND used_by IS NULL")
+            .bind(hash_token(code))
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::InternalError)?;
+
+        Ok(())
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/invites",
+    tag = "admin",
+    request_body = CreateInviteRequest,
+    responses(
+        (status = 201, description = "Invite code created; `code` is not shown again", body = InviteResponse),
+        (status = 400, description = "Invalid expiry"),
+        (status = 403, description = "Admin privileges required")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn create_invite(
+    AdminUser(claims): AdminUser,
+    State(state): State<AppState>,
+    payload: Option<Json<CreateInviteRequest>>,
+) -> Result<(StatusCode, Json<InviteResponse>), AuthError> {
+    let Json(payload) = payload.unwrap_or_default();
+
+    let expires_at = match payload.expires_in_seconds {
+        Some(seconds) => {
+            let expiry = chrono::TimeDelta::try_seconds(seconds)
+                .filter(|_| seconds > 0)
+                .and_then(|delta| chrono::Utc::now().checked_add_signed(delta))
+                .ok_or(AuthError::InvalidInviteExpiry)?;
+            Some(expiry.to_rfc3339())
+        }
+        None => None,
+    };
+
+    let code = InviteRepository::new(state.db_pool.clone())
+        .create(&claims.user_id, expires_at.as_deref())
+        .await?;
+    let expiry = expires_at.as_deref();
+    audit::record(&state.db_pool, Some(&claims.user_id), audit::INVITE_CREATED, None, expiry);
+
+    Ok((StatusCode::CREATED, Json(InviteResponse { code, expires_at })))
+}
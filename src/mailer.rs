@@ -0,0 +1,14 @@
+//! Outgoing email.
+//!
+//! No delivery provider is wired up yet: messages are written to the log so links can
+//! be followed in development. A real transport only needs to replace these functions.
+
+/// Send the link that confirms `to` belongs to the account holder
+pub async fn send_verification_email(to: &str, link: &str) {
+    tracing::info!(to, link, "Email verification link (delivery not configured)");
+}
+
+/// Send a password reset token, to be redeemed at `POST /api/auth/password-reset/confirm`
+pub async fn send_password_reset_email(to: &str, token: &str) {
+    tracing::info!(to, token, "Password reset token (delivery not configured)");
+}
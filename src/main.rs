@@ -1,34 +1,120 @@
+mod admin;
+mod api_keys;
+mod audit;
 mod auth;
+mod blobs;
+mod client_ip;
+mod data_dir;
+mod db;
+mod encryption;
+mod export;
+mod file_index;
 mod filemanager;
+mod folders;
+mod health;
+mod https;
+mod idempotency;
+mod invites;
+mod mailer;
+mod maintenance;
+mod maintenance_mode;
+mod monitoring;
+mod preferences;
+mod rate_limit;
+mod receipt;
+mod request_id;
+mod shares;
 mod static_files;
 mod stats;
+mod storage;
+mod storage_check;
+mod thumbnails;
+mod upload_policy;
+mod usage;
 mod user;
+mod username_policy;
 
-use std::path::PathBuf;
+use std::net::SocketAddr;
 use std::sync::LazyLock;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use axum::Router;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
-use tower_http::cors::{Any, CorsLayer};
-use tower_governor::{
-    governor::GovernorConfigBuilder, 
-    GovernorLayer,
+use axum::{
+    Router,
+    extract::DefaultBodyLimit,
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware,
+    routing::get,
 };
+use tokio::sync::Semaphore;
+use tower_http::{
+    LatencyUnit,
+    compression::{
+        CompressionLayer,
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+    },
+    cors::{AllowHeaders, AllowOrigin, Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
+    timeout::TimeoutLayer,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
 use utoipa::OpenApi;
 use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_swagger_ui::SwaggerUi;
 
 static KEYS: LazyLock<auth::Keys> = LazyLock::new(|| {
     let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    auth::Keys::new(secret.as_bytes())
+    let algorithm = auth::algorithm_from_env();
+    let min_len = auth::Keys::min_secret_len(algorithm);
+    assert!(
+        secret.len() >= min_len,
+        "JWT_SECRET must be at least {} bytes with {:?}",
+        min_len,
+        algorithm
+    );
+    auth::Keys::new(secret.as_bytes(), algorithm)
 });
 
+/// Body size cap for non-upload routes, from `MAX_REQUEST_BODY_KB` (default 1024)
+fn max_request_body_size() -> usize {
+    std::env::var("MAX_REQUEST_BODY_KB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1024)
+        * 1024
+}
+
+/// Time a request gets to produce its response, from `name` in seconds, before it is
+/// answered with `408`. Streamed response bodies (downloads, exports) run past it.
+fn request_timeout(name: &str, default_secs: u64) -> TimeoutLayer {
+    let secs = std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(default_secs);
+    TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, Duration::from_secs(secs))
+}
+
+/// Read a boolean flag from the environment (`1`/`true`/`yes`, case-insensitive).
+pub fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
 #[derive(Clone)]
 pub struct AppState {
-    pub db_pool: SqlitePool,
-    pub storage_root: PathBuf,
+    pub db_pool: db::DbPool,
+    pub storage: storage::Volumes,
     pub stats_cache: Arc<Mutex<stats::StatsCache>>,
+    /// When the server started, for the uptime in stats
+    pub start_time: Instant,
+    /// Limits how many uploads stream their body at once (`MAX_CONCURRENT_UPLOADS`)
+    pub upload_slots: Arc<Semaphore>,
+    /// Refuses writes while on (`MAINTENANCE`, `PUT /api/admin/maintenance`)
+    pub maintenance: maintenance_mode::MaintenanceMode,
 }
 
 #[derive(OpenApi)]
@@ -37,29 +123,147 @@ pub struct AppState {
         auth::signup,
         auth::login,
         auth::me,
+        auth::verify_token,
+        auth::logout_all,
+        auth::update_profile,
+        auth::send_email_verification,
+        auth::confirm_email_verification,
+        auth::request_password_reset,
+        auth::confirm_password_reset,
+        api_keys::create_api_key,
+        api_keys::list_api_keys,
+        api_keys::revoke_api_key,
+        export::export_data,
+        preferences::get_preferences,
+        preferences::update_preferences,
         filemanager::get_files_handler,
+        filemanager::stream_files_handler,
+        file_index::export_file_index,
+        filemanager::file_changes,
+        filemanager::recent_files,
+        filemanager::largest_files,
         filemanager::upload_file,
+        filemanager::upload_files,
+        filemanager::batch_update_files,
+        filemanager::get_file_metadata,
         filemanager::download_file,
+        filemanager::download_file_head,
         filemanager::delete_file,
-        stats::get_stats
+        filemanager::copy_file,
+        filemanager::move_file,
+        filemanager::add_file_tag,
+        filemanager::remove_file_tag,
+        filemanager::replace_file_content,
+        thumbnails::get_thumbnail,
+        receipt::get_receipt,
+        receipt::verify_receipt,
+        shares::create_share,
+        shares::revoke_share,
+        shares::download_shared_file,
+        shares::download_shared_file_form,
+        folders::create_folder,
+        folders::list_folders,
+        usage::get_usage_delta,
+        usage::get_usage_summary,
+        stats::get_stats,
+        stats::stats_ws,
+        stats::update_stats_config,
+        stats::get_user_storage_stats,
+        admin::list_users,
+        admin::delete_user,
+        admin::set_user_role,
+        admin::suspend_user,
+        admin::reactivate_user,
+        admin::list_files,
+        admin::get_file,
+        invites::create_invite,
+        maintenance_mode::get_maintenance,
+        maintenance_mode::set_maintenance,
+        audit::list_audit_events,
+        health::health,
+        monitoring::metrics_handler
     ),
     components(
         schemas(
             auth::Claims,
             auth::AuthBody,
+            auth::TokenStatus,
             auth::LoginRequest,
             user::CreateUserRequest,
+            user::UpdateProfileRequest,
+            auth::VerifyEmailQuery,
+            user::PasswordResetRequest,
+            user::PasswordResetConfirm,
+            api_keys::ApiKey,
+            api_keys::CreateApiKeyRequest,
+            api_keys::CreatedApiKey,
             user::UserResponse,
+            filemanager::File,
             filemanager::FileQuery,
+            file_index::IndexFormat,
+            filemanager::ChangesQuery,
+            filemanager::ChangesResponse,
+            filemanager::DeletedFile,
+            filemanager::FileListResponse,
+            filemanager::QuickListQuery,
             filemanager::FileResponse,
             filemanager::FileMetadata,
-            stats::SystemStats
+            filemanager::UploadForm,
+            filemanager::MultiUploadForm,
+            filemanager::ContentForm,
+            filemanager::UploadResponse,
+            filemanager::CopyFileRequest,
+            filemanager::MoveFileRequest,
+            filemanager::AddTagRequest,
+            filemanager::BatchRequest,
+            filemanager::BatchStatus,
+            filemanager::BatchResult,
+            filemanager::BatchResponse,
+            thumbnails::ThumbnailQuery,
+            shares::CreateShareRequest,
+            shares::ShareResponse,
+            shares::SharePassword,
+            folders::FolderResponse,
+            folders::CreateFolderRequest,
+            folders::FolderQuery,
+            preferences::UserPreferences,
+            preferences::AutoFolderRule,
+            usage::UsageDeltaQuery,
+            usage::UsageDelta,
+            usage::UsageSummary,
+            receipt::ReceiptClaims,
+            receipt::UploadReceipt,
+            receipt::VerifyReceiptRequest,
+            receipt::ReceiptVerification,
+            stats::SystemStats,
+            stats::DiskStat,
+            stats::LoadAverage,
+            stats::StatsConfig,
+            stats::UserStorageStats,
+            stats::UserStorageReport,
+            stats::UserStorageQuery,
+            admin::UserListQuery,
+            admin::AdminUserResponse,
+            admin::UserListResponse,
+            admin::AdminFileQuery,
+            admin::AdminFileListResponse,
+            admin::SetRoleRequest,
+            invites::CreateInviteRequest,
+            maintenance_mode::MaintenanceStatus,
+            invites::InviteResponse,
+            audit::AuditEvent,
+            audit::AuditQuery,
+            audit::AuditLogResponse,
+            health::HealthStatus
         )
     ),
     tags(
         (name = "auth", description = "Authentication endpoints"),
         (name = "files", description = "File management endpoints"),
-        (name = "stats", description = "System statistics endpoints")
+        (name = "stats", description = "System statistics endpoints"),
+        (name = "admin", description = "User management endpoints (admin only)"),
+        (name = "health", description = "Health check endpoints"),
+        (name = "metrics", description = "Prometheus metrics")
     ),
     modifiers(&SecurityAddon)
 )]
@@ -83,78 +287,354 @@ impl utoipa::Modify for SecurityAddon {
     }
 }
 
+/// Build the CORS layer from `CORS_ALLOWED_ORIGINS` (comma-separated).
+///
+/// With an explicit allowlist, credentials are allowed so cookie auth works; this
+/// requires echoing concrete methods/headers since browsers reject wildcards there.
+/// When unset, any origin is allowed (without credentials).
+fn cors_layer() -> CorsLayer {
+    let origins: Vec<HeaderValue> = std::env::var("CORS_ALLOWED_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| {
+            origin
+                .parse::<HeaderValue>()
+                .unwrap_or_else(|_| panic!("Invalid origin in CORS_ALLOWED_ORIGINS: {}", origin))
+        })
+        .collect();
+
+    if origins.is_empty() {
+        tracing::warn!("CORS_ALLOWED_ORIGINS is not set; allowing requests from any origin");
+        return CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(Any)
+            .allow_headers(Any)
+            .expose_headers([
+                request_id::X_REQUEST_ID.clone(),
+                filemanager::X_TOTAL_COUNT.clone(),
+                header::LINK,
+                header::ETAG,
+                auth::X_REFRESHED_TOKEN.clone(),
+                idempotency::IDEMPOTENT_REPLAYED.clone(),
+                filemanager::X_CONTENT_HASH.clone(),
+            ]);
+    }
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([
+            Method::GET,
+            Method::HEAD,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers(AllowHeaders::mirror_request())
+        .expose_headers([
+            request_id::X_REQUEST_ID.clone(),
+            filemanager::X_TOTAL_COUNT.clone(),
+            header::LINK,
+            header::ETAG,
+            auth::X_REFRESHED_TOKEN.clone(),
+            idempotency::IDEMPOTENT_REPLAYED.clone(),
+            filemanager::X_CONTENT_HASH.clone(),
+        ])
+        .allow_credentials(true)
+}
+
+/// gzip/brotli response compression negotiated via `Accept-Encoding`.
+///
+/// File downloads are client-side encrypted `application/octet-stream` blobs that won't
+/// shrink, so they are skipped along with what `DefaultPredicate` already excludes
+/// (images, event streams, tiny bodies).
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(
+        DefaultPredicate::new()
+            .and(NotForContentType::const_new("application/octet-stream"))
+            .and(NotForContentType::const_new("application/zip")),
+    )
+}
+
+/// Initialize logging from `RUST_LOG` (full filter syntax) or `LOG_LEVEL` (e.g. `debug`),
+/// defaulting to `info`.
+fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(std::env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string()))
+    });
+
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+}
+
 #[tokio::main]
 async fn main() {
+    let start_time = Instant::now();
     dotenvy::dotenv().ok();
+    init_tracing();
 
+    // Fail fast on a missing or weak JWT_SECRET rather than on the first login
+    LazyLock::force(&KEYS);
+    LazyLock::force(&invites::SIGNUP_MODE);
+    LazyLock::force(&client_ip::TRUSTED_PROXIES);
+    LazyLock::force(&upload_policy::UPLOAD_POLICY);
+    LazyLock::force(&username_policy::USERNAME_POLICY);
+
+    let data_dir = data_dir::from_env();
+    #[cfg(not(feature = "postgres"))]
+    let database_url = std::env::var("DATABASE_URL")
+        .ok()
+        .or_else(|| data_dir.as_deref().map(data_dir::database_url))
+        .expect("DATABASE_URL or DATA_DIR must be set");
+    #[cfg(feature = "postgres")]
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let storage_root = std::env::var("STORAGE_ROOT").unwrap_or_else(|_| "./storage".to_string());
     let port = std::env::var("PORT")
         .unwrap_or_else(|_| "3000".to_string())
         .parse::<u16>()
         .expect("PORT must be a valid number");
 
-    // Configure SQLite connection to create database if missing
-    let connect_options = database_url
-        .parse::<SqliteConnectOptions>()
-        .expect("Invalid DATABASE_URL")
-        .create_if_missing(true);
-
-    let db_pool = SqlitePool::connect_with(connect_options)
+    // Connect (creating the SQLite database if missing)
+    let db_pool = db::connect(&database_url, &db::PoolSettings::from_env())
         .await
         .expect("Failed to connect to database");
 
     // Run migrations to set up the schema
-    sqlx::migrate!("./migrations")
+    db::MIGRATOR
         .run(&db_pool)
         .await
         .expect("Failed to run migrations");
+    // Migrations keep tables that already existed, so a stale or foreign database would
+    // otherwise only fail once a query touches the missing columns
+    let missing = db::missing_columns(&db_pool)
+        .await
+        .expect("Failed to inspect the database schema");
+    assert!(
+        missing.is_empty(),
+        "Database schema is missing {}. DATABASE_URL probably points at a database that \
+         another application (or a hand-edited copy) created; point it at an empty database \
+         or one created by this server.",
+        missing.join(", ")
+    );
+    db::detect_filename_fts(&db_pool).await;
+
+    // Grant the admin role to any accounts listed in ADMIN_USERNAMES
+    let admin_usernames: Vec<String> = std::env::var("ADMIN_USERNAMES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    user::UserRepository::new(db_pool.clone())
+        .promote_admins(&admin_usernames)
+        .await
+        .expect("Failed to promote admin users");
 
-    tokio::fs::create_dir_all(&storage_root)
+    if encryption::enabled() {
+        tracing::info!("At-rest encryption enabled for new uploads ({})", encryption::ALGORITHM);
+    }
+
+    let storage = storage::from_env(&data_dir::storage_root(data_dir.as_deref())).await;
+
+    // Volumes can be added to STORAGE_ROOTS, but not dropped while users are assigned to them
+    let highest_volume: Option<i64> = sqlx::query_scalar("SELECT MAX(storage_root) FROM users")
+        .fetch_one(&db_pool)
         .await
-        .expect("Failed to create storage root directory");
+        .expect("Failed to read storage volume assignments");
+    if let Some(highest) = highest_volume
+        && storage.get(highest).is_err()
+    {
+        panic!(
+            "Users are assigned to storage volume {} but only {} root(s) are configured; roots in STORAGE_ROOTS can be added but not removed or reordered",
+            highest,
+            storage.len()
+        );
+    }
+
+    if storage_check::requested() {
+        storage_check::run(&db_pool, &storage, storage_check::cleanup_requested())
+            .await
+            .expect("Storage check failed");
+    }
+
+    let maintenance_mode = maintenance_mode::MaintenanceMode::from_env();
+    if let Some(interval) = maintenance::interval_from_env() {
+        maintenance::spawn(db_pool.clone(), interval, maintenance_mode.clone());
+    }
+
+    let stats_cache = Arc::new(Mutex::new(stats::StatsCache::new()));
+
+    let metrics_state = monitoring::MetricsState {
+        handle: monitoring::install_recorder(),
+        token: std::env::var("METRICS_TOKEN").ok().filter(|t| !t.is_empty()),
+        stats_cache: stats_cache.clone(),
+    };
 
     let state = AppState {
         db_pool,
-        storage_root: PathBuf::from(storage_root),
-        stats_cache: Arc::new(Mutex::new(stats::StatsCache::new())),
+        storage,
+        stats_cache,
+        start_time,
+        upload_slots: filemanager::upload_slots_from_env(),
+        maintenance: maintenance_mode,
     };
 
     let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
-        .routes(routes!(auth::signup))
+        // Retried signups get the first attempt's response (see `idempotency`)
+        .merge(
+            OpenApiRouter::new()
+                .routes(routes!(auth::signup))
+                .layer(middleware::from_fn_with_state(
+                    (state.clone(), idempotency::Caller::Anonymous),
+                    idempotency::idempotent,
+                )),
+        )
         .routes(routes!(auth::login))
         .routes(routes!(auth::me))
+        .routes(routes!(auth::verify_token))
+        .routes(routes!(auth::logout_all))
+        .routes(routes!(auth::update_profile))
+        .routes(routes!(export::export_data))
+        .routes(routes!(auth::send_email_verification))
+        .routes(routes!(auth::confirm_email_verification))
+        .routes(routes!(auth::request_password_reset))
+        .routes(routes!(auth::confirm_password_reset))
+        .routes(routes!(api_keys::create_api_key, api_keys::list_api_keys))
+        .routes(routes!(api_keys::revoke_api_key))
         .routes(routes!(filemanager::get_files_handler))
-        .routes(routes!(filemanager::upload_file))
-        .routes(routes!(filemanager::download_file))
-        .routes(routes!(filemanager::delete_file))
+        .routes(routes!(filemanager::stream_files_handler))
+        .routes(routes!(file_index::export_file_index))
+        .routes(routes!(filemanager::file_changes))
+        .routes(routes!(filemanager::recent_files))
+        .routes(routes!(filemanager::largest_files))
+        .routes(routes!(filemanager::download_file, filemanager::download_file_head))
+        .routes(routes!(thumbnails::get_thumbnail))
+        .routes(routes!(filemanager::get_file_metadata, filemanager::delete_file))
+        .routes(routes!(filemanager::copy_file))
+        .routes(routes!(filemanager::move_file))
+        .routes(routes!(filemanager::add_file_tag))
+        .routes(routes!(filemanager::remove_file_tag))
+        .routes(routes!(filemanager::batch_update_files))
+        .routes(routes!(receipt::get_receipt))
+        .routes(routes!(receipt::verify_receipt))
+        .routes(routes!(shares::create_share, shares::revoke_share))
+        .routes(routes!(shares::download_shared_file, shares::download_shared_file_form))
+        .routes(routes!(folders::create_folder, folders::list_folders))
+        .routes(routes!(preferences::get_preferences, preferences::update_preferences))
+        .routes(routes!(usage::get_usage_delta))
+        .routes(routes!(usage::get_usage_summary))
         .routes(routes!(stats::get_stats))
-        .with_state(state)
+        .routes(routes!(stats::update_stats_config))
+        .routes(routes!(stats::stats_ws))
+        .routes(routes!(stats::get_user_storage_stats))
+        .routes(routes!(admin::list_users))
+        .routes(routes!(admin::delete_user))
+        .routes(routes!(admin::set_user_role))
+        .routes(routes!(admin::suspend_user))
+        .routes(routes!(admin::reactivate_user))
+        .routes(routes!(admin::list_files))
+        .routes(routes!(admin::get_file))
+        .routes(routes!(invites::create_invite))
+        .routes(routes!(maintenance_mode::get_maintenance, maintenance_mode::set_maintenance))
+        .routes(routes!(audit::list_audit_events))
+        // Everything above takes small JSON/form bodies and answers promptly, so a stuck
+        // query or a client trickling its body can't hold a connection for long
+        .layer(RequestBodyLimitLayer::new(max_request_body_size()))
+        .layer(request_timeout("REQUEST_TIMEOUT_SECS", 30))
+        // Uploads stream their body to disk and enforce the file size limit themselves
+        .merge(
+            OpenApiRouter::new()
+                .merge(
+                    OpenApiRouter::new()
+                        .routes(routes!(filemanager::upload_file))
+                        .routes(routes!(filemanager::upload_files))
+                        .layer(middleware::from_fn_with_state(
+                            (state.clone(), idempotency::Caller::User),
+                            idempotency::idempotent,
+                        )),
+                )
+                .routes(routes!(filemanager::replace_file_content))
+                .layer(DefaultBodyLimit::max(filemanager::MAX_UPLOAD_BODY_SIZE))
+                // Large files take a while, but a stalled upload mustn't keep its slot
+                .layer(request_timeout("UPLOAD_TIMEOUT_SECS", 3600)),
+        )
+        .with_state(state.clone())
         .split_for_parts();
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    let cors = cors_layer();
 
-    // Rate limiting: 2 requests per second per IP, burst of 20
-    let governor_conf = GovernorConfigBuilder::default()
-        .per_second(2)  // 2 requests per second
-        .burst_size(20) // Allow bursts up to 20 requests
-        .finish()
-        .unwrap();
+    // Built from the same spec the JSON route serves, once rather than per request
+    let openapi_yaml = serde_yaml::to_string(&api).expect("Failed to serialize the OpenAPI spec to YAML");
 
-    let app = Router::new()
+    let routes = Router::new()
         .merge(router)
+        .route(
+            "/api/openapi.yaml",
+            get(move || async move { ([(header::CONTENT_TYPE, "text/yaml")], openapi_yaml) }),
+        )
         .merge(SwaggerUi::new("/swagger-ui").url("/api/openapi.json", api))
+        .merge(
+            Router::new()
+                .route("/metrics", get(monitoring::metrics_handler))
+                .with_state(metrics_state),
+        )
+        // Health probes and the frontend only read, so they are left outside
+        .layer(middleware::from_fn_with_state(
+            state.maintenance.clone(),
+            maintenance_mode::reject_writes,
+        ));
+    // Innermost, so rejections still pass through CORS, tracing and metrics. Health
+    // probes and the static fallback are added afterwards, so neither is limited.
+    let routes = match rate_limit::RateLimit::from_env() {
+        Some(limit) => limit.apply(routes),
+        None => routes,
+    };
+
+    let app = routes
+        .route("/health", get(health::health).with_state(state))
+        // Registered before the layers below so the frontend and the JSON 404 for unknown
+        // API paths get CORS, request ids and the HTTPS policy like everything else
+        .fallback(static_files::handler)
+        .layer(middleware::from_fn(auth::attach_refreshed_token))
+        .layer(middleware::from_fn(monitoring::track_requests))
+        .layer(compression_layer())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_id::make_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO).latency_unit(LatencyUnit::Millis)),
+        )
         .layer(cors)
-        .layer(GovernorLayer::new(Arc::new(governor_conf)))
-        .fallback(static_files::handler);
+        .layer(middleware::from_fn_with_state(
+            https::HttpsPolicy::from_env(),
+            https::enforce_https,
+        ))
+        // Outside the rate limiter, which keys on the resolved address
+        .layer(middleware::from_fn(client_ip::resolve_client_ip))
+        // Outermost so every response, including rejections from the layers above, carries the id
+        .layer(middleware::from_fn(request_id::propagate_request_id));
 
     let addr = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    println!("Server running on http://localhost:{}", port);
-    println!("OpenAPI spec: http://localhost:{}/api/openapi.json", port);
-    println!("Swagger UI: http://localhost:{}/swagger-ui", port);
-    axum::serve(listener, app).await.unwrap();
+    let tls_config = https::rustls_config().await;
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
+    tracing::info!("Server running on {}://localhost:{}", scheme, port);
+    tracing::info!("OpenAPI spec: {}://localhost:{}/api/openapi.json (or .yaml)", scheme, port);
+    tracing::info!("Metrics: {}://localhost:{}/metrics", scheme, port);
+    tracing::info!("Swagger UI: {}://localhost:{}/swagger-ui", scheme, port);
+
+    match tls_config {
+        Some(tls_config) => {
+            let addr = addr.parse().expect("Invalid listen address");
+            axum_server::bind_rustls(addr, tls_config)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        }
+        None => {
+            let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        }
+    }
 }
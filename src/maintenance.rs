@@ -0,0 +1,71 @@
+//! Periodic cleanup of rows that have outlived their use.
+//!
+//! A single background task runs every sweep in `SWEEPS` each `MAINTENANCE_INTERVAL_SECS`
+//! (default 3600; 0 disables it), skipping runs while read-only maintenance mode is on.
+//! Sweeps are plain statements against the pool, so the task never holds anything a
+//! request handler could be waiting on between runs.
+
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+
+use crate::db::DbPool;
+use crate::maintenance_mode::MaintenanceMode;
+
+/// Named cleanup statements; `$1` is the current time (RFC 3339)
+const SWEEPS: &[(&str, &str)] = &[
+    ("expired password resets", "DELETE FROM password_resets WHERE expires_at <= $1"),
+    ("expired email verifications", "DELETE FROM email_verifications WHERE expires_at <= $1"),
+    (
+        "expired invite codes",
+        "DELETE FROM invite_codes WHERE used_at IS NULL AND expires_at <= $1",
+    ),
+    (
+        "expired idempotency keys",
+        "DELETE FROM idempotency_keys WHERE expires_at <= $1",
+    ),
+    (
+        "expired shares",
+        "DELETE FROM shares WHERE expires_at <= $1 OR remaining_downloads <= 0",
+    ),
+];
+
+/// Time between runs, from `MAINTENANCE_INTERVAL_SECS`; `None` when disabled
+pub fn interval_from_env() -> Option<Duration> {
+    let secs = std::env::var("MAINTENANCE_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(3600);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Run the sweeps now and then every `interval`, for the life of the process
+pub fn spawn(pool: DbPool, interval: Duration, mode: MaintenanceMode) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // A slow run pushes the next one back instead of triggering a burst
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            if !mode.is_enabled() {
+                run_once(&pool).await;
+            }
+        }
+    });
+}
+
+async fn run_once(pool: &DbPool) {
+    let now = chrono::Utc::now().to_rfc3339();
+
+    for (name, sql) in SWEEPS {
+        match sqlx::query(sql).bind(&now).execute(pool).await {
+            Ok(result) if result.rows_affected() > 0 => {
+                tracing::info!(rows = result.rows_affected(), "Removed {}", name);
+            }
+            Ok(_) => {}
+            // One failing sweep shouldn't stop the others
+            Err(e) => tracing::warn!(error = ?e, "Failed to remove {}", name),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Read-only maintenance mode, for backups and migrations.
+//!
+//! While it is on, requests that write get `503` with `Retry-After` and reads carry on.
+//! Writing is decided by method, with the exceptions in `READ_ONLY_POSTS` and
+//! `WRITING_GETS`; the bookkeeping reads otherwise do (download counts, API key use,
+//! thumbnail caching, hash backfills, background sweeps) is skipped. Logins still record
+//! their attempts and the audit log still grows, so an admin can always sign in and
+//! switch the mode off again through `PUT /api/admin/maintenance`, which is never
+//! blocked. It starts on with `MAINTENANCE=1`; the state isn't persisted, so a restart
+//! goes back to what `MAINTENANCE` says.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::audit;
+use crate::auth::AdminUser;
+
+/// Route of the toggle, exempt from the mode it controls
+const TOGGLE_PATH: &str = "/api/admin/maintenance";
+
+/// POSTs that only read, allowed through; `*` matches one path segment
+const READ_ONLY_POSTS: &[&str] = &["/api/auth/login", "/api/files/verify-receipt", "/api/share/*"];
+
+/// GETs that change state, refused like other writes
+const WRITING_GETS: &[&str] = &["/api/auth/verify-email/confirm"];
+
+/// Suggested wait before retrying a rejected write
+pub const RETRY_AFTER_SECS: u64 = 60;
+
+pub const READ_ONLY_MESSAGE: &str = "The server is in read-only maintenance mode";
+
+/// Shared switch; clones see the same state
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceMode(Arc<AtomicBool>);
+
+impl MaintenanceMode {
+    pub fn from_env() -> Self {
+        let enabled = crate::env_flag("MAINTENANCE");
+        if enabled {
+            tracing::warn!("Starting in maintenance mode; writes are refused until it is switched off");
+        }
+        Self(Arc::new(AtomicBool::new(enabled)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MaintenanceStatus {
+    /// Whether writes are currently refused
+    pub enabled: bool,
+}
+
+/// Whether `path` matches `pattern`, segment by segment
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut segments = path.split('/');
+    pattern
+        .split('/')
+        .all(|expected| segments.next().is_some_and(|segment| expected == "*" || expected == segment))
+        && segments.next().is_none()
+}
+
+/// Whether a request could change anything
+fn writes(method: &Method, path: &str) -> bool {
+    let matches_any = |patterns: &[&str]| patterns.iter().any(|pattern| path_matches(pattern, path));
+    match *method {
+        Method::GET | Method::HEAD => matches_any(WRITING_GETS),
+        Method::OPTIONS => false,
+        Method::POST => path != TOGGLE_PATH && !matches_any(READ_ONLY_POSTS),
+        _ => path != TOGGLE_PATH,
+    }
+}
+
+/// `503` for writes refused while maintenance mode is on
+pub fn unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS))],
+        Json(json!({
+            "error": READ_ONLY_MESSAGE,
+            "request_id": crate::request_id::current(),
+        })),
+    )
+        .into_response()
+}
+
+/// Refuse writes while maintenance mode is on
+pub async fn reject_writes(State(mode): State<MaintenanceMode>, req: Request, next: Next) -> Response {
+    if mode.is_enabled() && writes(req.method(), req.uri().path()) {
+        return unavailable();
+    }
+    next.run(req).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/maintenance",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Whether maintenance mode is on", body = MaintenanceStatus),
+        (status = 403, description = "Admin privileges required")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_maintenance(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+) -> Json<MaintenanceStatus> {
+    Json(MaintenanceStatus { enabled: state.maintenance.is_enabled() })
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/maintenance",
+    tag = "admin",
+    request_body = MaintenanceStatus,
+    responses(
+        (status = 200, description = "Maintenance mode switched", body = MaintenanceStatus),
+        (status = 403, description = "Admin privileges required")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn set_maintenance(
+    AdminUser(claims): AdminUser,
+    State(state): State<AppState>,
+    Json(payload): Json<MaintenanceStatus>,
+) -> Json<MaintenanceStatus> {
+    state.maintenance.set(payload.enabled);
+
+    let detail = Some(if payload.enabled { "on" } else { "off" });
+    audit::record(&state.db_pool, Some(&claims.user_id), audit::MAINTENANCE_CHANGED, None, detail);
+    tracing::warn!(admin_id = %claims.user_id, enabled = payload.enabled, "Maintenance mode switched");
+    Json(MaintenanceStatus { enabled: payload.enabled })
+}
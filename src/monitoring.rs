@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::stats::StatsCache;
+
+pub const HTTP_REQUESTS_TOTAL: &str = "http_requests_total";
+pub const HTTP_REQUEST_DURATION_SECONDS: &str = "http_request_duration_seconds";
+pub const UPLOAD_BYTES_TOTAL: &str = "upload_bytes_total";
+pub const DOWNLOAD_BYTES_TOTAL: &str = "download_bytes_total";
+
+/// Latency buckets (seconds), from fast JSON responses up to long transfers
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(Clone)]
+pub struct MetricsState {
+    pub handle: PrometheusHandle,
+    /// If set, scrapers must send `Authorization: Bearer <METRICS_TOKEN>`
+    pub token: Option<String>,
+    pub stats_cache: Arc<Mutex<StatsCache>>,
+}
+
+/// Install the global Prometheus recorder. Must be called once, at startup.
+pub fn install_recorder() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(HTTP_REQUEST_DURATION_SECONDS.to_string()),
+            LATENCY_BUCKETS,
+        )
+        .expect("Invalid latency buckets")
+        .install_recorder()
+        .expect("Failed to install Prometheus recorder")
+}
+
+/// Count requests and record their latency, labelled by route template rather than
+/// raw path so file ids don't explode label cardinality.
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(req).await;
+
+    let labels = [
+        ("method", method),
+        ("path", path),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    counter!(HTTP_REQUESTS_TOTAL, &labels).increment(1);
+    histogram!(HTTP_REQUEST_DURATION_SECONDS, &labels).record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Prometheus scrape endpoint
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text exposition format", body = String, content_type = "text/plain"),
+        (status = 401, description = "METRICS_TOKEN is set and the bearer token doesn't match")
+    ),
+    security(
+        (),
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn metrics_handler(State(state): State<MetricsState>, headers: HeaderMap) -> Response {
+    if let Some(token) = &state.token {
+        let authorized = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|provided| provided == token);
+        if !authorized {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    record_system_stats(&state.stats_cache);
+    state.handle.run_upkeep();
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.handle.render(),
+    )
+        .into_response()
+}
+
+/// Publish the (throttled) system stats as gauges
+fn record_system_stats(stats_cache: &Mutex<StatsCache>) {
+    let mut cache = stats_cache.lock().unwrap();
+    cache.refresh_if_needed();
+
+    let sys = cache.get_system();
+    gauge!("system_cpu_usage_percent").set(sys.global_cpu_usage() as f64);
+    gauge!("system_memory_used_bytes").set(sys.used_memory() as f64);
+    gauge!("system_memory_total_bytes").set(sys.total_memory() as f64);
+
+    let (disk_used, disk_total) = cache.get_disks().iter().fold((0u64, 0u64), |(used, total), disk| {
+        (used + (disk.total_space() - disk.available_space()), total + disk.total_space())
+    });
+    gauge!("system_disk_used_bytes").set(disk_used as f64);
+    gauge!("system_disk_total_bytes").set(disk_total as f64);
+
+    let (network_rx, network_tx) = cache.get_networks().iter().fold((0u64, 0u64), |(rx, tx), (_name, network)| {
+        (rx + network.total_received(), tx + network.total_transmitted())
+    });
+    gauge!("system_network_received_bytes").set(network_rx as f64);
+    gauge!("system_network_transmitted_bytes").set(network_tx as f64);
+}
@@ -0,0 +1,189 @@
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::db::DbPool;
+use crate::auth::Claims;
+use crate::filemanager::FileError;
+use crate::folders::FolderRepository;
+
+/// Maximum number of auto-foldering rules per user
+const MAX_AUTO_FOLDER_RULES: usize = 32;
+
+/// Route uploads whose MIME type starts with `mime_prefix` into the root folder `folder`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AutoFolderRule {
+    /// e.g. `image/` or `application/pdf`
+    pub mime_prefix: String,
+    /// Name of a root-level folder, created on first use
+    pub folder: String,
+}
+
+/// Per-user settings. Everything is off by default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct UserPreferences {
+    /// Folder for uploads that don't specify one and match no rule
+    pub default_folder_id: Option<String>,
+    /// Rules checked in order; the first matching rule wins
+    #[serde(default)]
+    pub auto_folder_rules: Vec<AutoFolderRule>,
+}
+
+impl UserPreferences {
+    pub fn folder_rule_for(&self, mime_type: &str) -> Option<&AutoFolderRule> {
+        let mime_type = mime_type.to_ascii_lowercase();
+        self.auto_folder_rules
+            .iter()
+            .find(|rule| mime_type.starts_with(&rule.mime_prefix.to_ascii_lowercase()))
+    }
+}
+
+pub struct PreferencesRepository {
+    pool: DbPool,
+}
+
+impl PreferencesRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn get_preferences(&self, user_id: &str) -> Result<UserPreferences, FileError> {
+        let row: Option<(Option<String>, String)> = sqlx::query_as(
+            "SELECT default_folder_id, auto_folder_rules FROM user_preferences WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        match row {
+            Some((default_folder_id, rules)) => Ok(UserPreferences {
+                default_folder_id,
+                auto_folder_rules: serde_json::from_str(&rules)
+                    .map_err(|_| FileError::InternalError)?,
+            }),
+            None => Ok(UserPreferences::default()),
+        }
+    }
+
+    pub async fn set_preferences(
+        &self,
+        user_id: &str,
+        preferences: &UserPreferences,
+    ) -> Result<(), FileError> {
+        let rules = serde_json::to_string(&preferences.auto_folder_rules)
+            .map_err(|_| FileError::InternalError)?;
+
+        sqlx::query(
+            "INSERT INTO user_preferences (user_id, default_folder_id, auto_folder_rules) VALUES ($1, $2, $3)
+             ON CONFLICT(user_id) DO UPDATE SET
+                default_folder_id = excluded.default_folder_id,
+                auto_folder_rules = excluded.auto_folder_rules",
+        )
+        .bind(user_id)
+        .bind(&preferences.default_folder_id)
+        .bind(&rules)
+        .execute(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        Ok(())
+    }
+}
+
+/// Pick the folder for a new upload: the explicit folder if given (must be owned),
+/// else the first matching auto-folder rule, else the user's default folder, else root.
+pub async fn resolve_upload_folder(
+    pool: &DbPool,
+    user_id: &str,
+    requested: Option<&str>,
+    mime_type: &str,
+) -> Result<Option<String>, FileError> {
+    let folder_repo = FolderRepository::new(pool.clone());
+
+    if let Some(folder_id) = requested {
+        let folder = folder_repo
+            .get_folder(folder_id, user_id)
+            .await?
+            .ok_or(FileError::FolderNotFound)?;
+        return Ok(Some(folder.id));
+    }
+
+    let preferences = PreferencesRepository::new(pool.clone())
+        .get_preferences(user_id)
+        .await?;
+
+    if let Some(rule) = preferences.folder_rule_for(mime_type) {
+        let folder = folder_repo
+            .find_or_create_root_folder(user_id, &rule.folder)
+            .await?;
+        return Ok(Some(folder.id));
+    }
+
+    Ok(preferences.default_folder_id)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/me/preferences",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Current user's preferences", body = UserPreferences)
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_preferences(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<Json<UserPreferences>, FileError> {
+    let preferences = PreferencesRepository::new(state.db_pool.clone())
+        .get_preferences(&claims.user_id)
+        .await?;
+
+    Ok(Json(preferences))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/me/preferences",
+    tag = "auth",
+    request_body = UserPreferences,
+    responses(
+        (status = 200, description = "Preferences updated", body = UserPreferences),
+        (status = 400, description = "Invalid rule"),
+        (status = 404, description = "Default folder not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn update_preferences(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(preferences): Json<UserPreferences>,
+) -> Result<Json<UserPreferences>, FileError> {
+    if preferences.auto_folder_rules.len() > MAX_AUTO_FOLDER_RULES
+        || preferences.auto_folder_rules.iter().any(|rule| {
+            let folder = rule.folder.trim();
+            rule.mime_prefix.is_empty() || folder.is_empty() || folder.len() > 255 || folder.contains('/')
+        })
+    {
+        return Err(FileError::InvalidMetadata);
+    }
+
+    if let Some(folder_id) = &preferences.default_folder_id {
+        FolderRepository::new(state.db_pool.clone())
+            .get_folder(folder_id, &claims.user_id)
+            .await?
+            .ok_or(FileError::FolderNotFound)?;
+    }
+
+    PreferencesRepository::new(state.db_pool.clone())
+        .set_preferences(&claims.user_id, &preferences)
+        .await?;
+
+    Ok(Json(preferences))
+}
@@ -0,0 +1,81 @@
+//! Per-client request rate limiting for the API.
+//!
+//! Each client address (see `client_ip`) may make `RATE_LIMIT_BURST` requests at once,
+//! refilled at `RATE_LIMIT_PER_SECOND`; beyond that requests get `429` with `Retry-After`.
+//! `RATE_LIMIT_PER_SECOND=0` turns the limiter off, e.g. behind a proxy that limits already.
+
+use std::time::Duration;
+
+use axum::{
+    Json, Router,
+    body::Body,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use tower_governor::{GovernorError, GovernorLayer, governor::GovernorConfigBuilder};
+
+use crate::client_ip;
+
+const DEFAULT_PER_SECOND: u32 = 2;
+const DEFAULT_BURST: u32 = 20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Requests a client regains per second
+    pub per_second: u32,
+    /// Requests a client may make back to back
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// `None` when `RATE_LIMIT_PER_SECOND` is 0
+    pub fn from_env() -> Option<Self> {
+        let per_second = env_u32("RATE_LIMIT_PER_SECOND").unwrap_or(DEFAULT_PER_SECOND);
+        let burst = env_u32("RATE_LIMIT_BURST").unwrap_or(DEFAULT_BURST).max(1);
+        (per_second > 0).then_some(Self { per_second, burst })
+    }
+
+    /// Limit every route in `router`; routes merged in afterwards are not limited
+    pub fn apply(self, router: Router) -> Router {
+        let config = GovernorConfigBuilder::default()
+            .period(Duration::from_secs(1) / self.per_second)
+            .burst_size(self.burst)
+            .key_extractor(client_ip::ClientIpKeyExtractor)
+            .finish()
+            .expect("rate limit period and burst are non-zero");
+
+        router.layer(GovernorLayer::new(config).error_handler(rejection))
+    }
+}
+
+fn env_u32(name: &str) -> Option<u32> {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+}
+
+/// JSON error body like the rest of the API
+fn rejection(error: GovernorError) -> Response<Body> {
+    let (status, message, headers) = match error {
+        GovernorError::TooManyRequests { wait_time, .. } => {
+            // `wait_time` is rounded down, and `Retry-After: 0` would invite an immediate retry
+            let headers = HeaderMap::from_iter([(header::RETRY_AFTER, HeaderValue::from(wait_time + 1))]);
+            (StatusCode::TOO_MANY_REQUESTS, "Too many requests", headers)
+        }
+        GovernorError::UnableToExtractKey => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not determine client address",
+            HeaderMap::new(),
+        ),
+        GovernorError::Other { code, headers, .. } => (code, "Request rejected", headers.unwrap_or_default()),
+    };
+
+    (
+        status,
+        headers,
+        Json(json!({
+            "error": message,
+            "request_id": crate::request_id::current(),
+        })),
+    )
+        .into_response()
+}
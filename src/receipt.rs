@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use jsonwebtoken::{decode, encode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::auth::Claims;
+use crate::filemanager::{File, FileError, FileRepository, ensure_content_hash};
+
+/// The facts a receipt attests to
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReceiptClaims {
+    pub file_id: String,
+    /// SHA-256 (hex) of the stored blob
+    pub sha256: String,
+    pub size_bytes: i64,
+    pub user_id: String,
+    /// When the file was stored (RFC 3339)
+    pub stored_at: String,
+}
+
+/// Tamper-evident proof that a file with the given hash was stored at a given time.
+///
+/// `signature` is a compact JWS over the receipt fields, signed with the server key.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UploadReceipt {
+    #[serde(flatten)]
+    pub claims: ReceiptClaims,
+    pub signature: String,
+}
+
+impl UploadReceipt {
+    pub fn issue(file: &File) -> Result<Self, FileError> {
+        let claims = ReceiptClaims {
+            file_id: file.id.clone(),
+            sha256: file.content_hash.clone().ok_or(FileError::InternalError)?,
+            size_bytes: file.size_bytes,
+            user_id: file.user_id.clone(),
+            stored_at: file.created_at.clone(),
+        };
+
+        let signature = encode(&crate::KEYS.header(), &claims, &crate::KEYS.encoding)
+            .map_err(|_| FileError::InternalError)?;
+
+        Ok(Self { claims, signature })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyReceiptRequest {
+    /// The `signature` field of a receipt
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReceiptVerification {
+    /// Signature is valid and the stored file still matches the receipt
+    pub valid: bool,
+    /// Receipt was signed by this server and has not been altered
+    pub signature_valid: bool,
+    /// The file still exists with the hash and size in the receipt
+    pub matches_stored_file: bool,
+    /// Decoded receipt contents, when the signature is valid
+    pub receipt: Option<ReceiptClaims>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{id}/receipt",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    responses(
+        (status = 200, description = "Signed receipt for the stored file", body = UploadReceipt),
+        (status = 404, description = "File not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_receipt(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UploadReceipt>, FileError> {
+    let file_repo = FileRepository::new(state.db_pool.clone());
+
+    let mut file = file_repo
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
+
+    // Files uploaded before hashing existed get their hash computed on first request
+    ensure_content_hash(&state, &mut file).await?;
+
+    Ok(Json(UploadReceipt::issue(&file)?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/verify-receipt",
+    tag = "files",
+    request_body = VerifyReceiptRequest,
+    responses(
+        (status = 200, description = "Verification result", body = ReceiptVerification)
+    )
+)]
+pub async fn verify_receipt(
+    State(state): State<AppState>,
+    Json(payload): Json<VerifyReceiptRequest>,
+) -> Result<Json<ReceiptVerification>, FileError> {
+    let mut validation = crate::KEYS.validation();
+    // Receipts are permanent records, so there is no expiry to check
+    validation.validate_exp = false;
+    validation.required_spec_claims = HashSet::new();
+
+    let receipt = match decode::<ReceiptClaims>(&payload.signature, &crate::KEYS.decoding, &validation) {
+        Ok(data) => data.claims,
+        Err(_) => {
+            return Ok(Json(ReceiptVerification {
+                valid: false,
+                signature_valid: false,
+                matches_stored_file: false,
+                receipt: None,
+            }));
+        }
+    };
+
+    let file = FileRepository::new(state.db_pool.clone())
+        .get_file(&receipt.file_id, &receipt.user_id)
+        .await?;
+
+    let matches_stored_file = file.is_some_and(|file| {
+        file.size_bytes == receipt.size_bytes
+            && file.content_hash.as_deref() == Some(receipt.sha256.as_str())
+    });
+
+    Ok(Json(ReceiptVerification {
+        valid: matches_stored_file,
+        signature_valid: true,
+        matches_stored_file,
+        receipt: Some(receipt),
+    }))
+}
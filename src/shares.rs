@@ -0,0 +1,374 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{
+    Json,
+    extract::{Form, Path, Query, State},
+    http::StatusCode,
+    response::Response,
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::audit;
+use crate::auth::Claims;
+use crate::db::DbPool;
+use crate::filemanager::{FileError, FileRepository, stream_file};
+use crate::user::{hash_password, verify_password};
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Share {
+    pub token: String,
+    pub file_id: String,
+    pub user_id: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub remaining_downloads: Option<i64>,
+    pub password_hash: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CreateShareRequest {
+    /// Seconds until the link stops working (omit for no expiry)
+    pub expires_in_seconds: Option<i64>,
+    /// Number of downloads allowed before the link is revoked (omit for unlimited)
+    pub max_downloads: Option<i64>,
+    /// Require this password to download (omit for an open link)
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ShareResponse {
+    pub token: String,
+    /// Public download URL, absolute when `PUBLIC_URL` is set
+    pub url: String,
+    pub expires_at: Option<String>,
+    pub remaining_downloads: Option<i64>,
+    pub password_protected: bool,
+}
+
+/// Password for a protected share, as `?password=` or a form field
+#[derive(Debug, Default, Deserialize, ToSchema, IntoParams)]
+pub struct SharePassword {
+    pub password: Option<String>,
+}
+
+impl From<Share> for ShareResponse {
+    fn from(share: Share) -> Self {
+        let base = std::env::var("PUBLIC_URL").unwrap_or_default();
+        Self {
+            url: format!("{}/api/share/{}", base.trim_end_matches('/'), share.token),
+            token: share.token,
+            expires_at: share.expires_at,
+            remaining_downloads: share.remaining_downloads,
+            password_protected: share.password_hash.is_some(),
+        }
+    }
+}
+
+/// 256-bit random, URL-safe share token
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub struct ShareRepository {
+    pool: DbPool,
+}
+
+impl ShareRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Create a share for a file, replacing any existing link for it
+    pub async fn create_share(
+        &self,
+        file_id: &str,
+        user_id: &str,
+        expires_at: Option<String>,
+        remaining_downloads: Option<i64>,
+        password_hash: Option<String>,
+    ) -> Result<Share, FileError> {
+        let share = Share {
+            token: generate_token(),
+            file_id: file_id.to_string(),
+            user_id: user_id.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            expires_at,
+            remaining_downloads,
+            password_hash,
+        };
+
+        let mut tx = self.pool.begin().await.map_err(FileError::DatabaseError)?;
+
+        sqlx::query("DELETE FROM shares WHERE file_id = $1")
+            .bind(&share.file_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(FileError::DatabaseError)?;
+
+        sqlx::query(
+            "INSERT INTO shares (token, file_id, user_id, created_at, expires_at, remaining_downloads, password_hash)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&share.token)
+        .bind(&share.file_id)
+        .bind(&share.user_id)
+        .bind(&share.created_at)
+        .bind(&share.expires_at)
+        .bind(share.remaining_downloads)
+        .bind(&share.password_hash)
+        .execute(&mut *tx)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        tx.commit().await.map_err(FileError::DatabaseError)?;
+
+        Ok(share)
+    }
+
+    /// Revoke the share for a file; returns whether one existed
+    pub async fn delete_share(&self, file_id: &str, user_id: &str) -> Result<bool, FileError> {
+        let result = sqlx::query("DELETE FROM shares WHERE file_id = $1 AND user_id = $2")
+            .bind(file_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(FileError::DatabaseError)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Look up a share that hasn't expired or run out of downloads
+    pub async fn get_live_share(&self, token: &str) -> Result<Option<Share>, FileError> {
+        sqlx::query_as::<_, Share>(
+            "SELECT * FROM shares
+             WHERE token = $1
+               AND (remaining_downloads IS NULL OR remaining_downloads > 0)
+               AND (expires_at IS NULL OR expires_at > $2)",
+        )
+        .bind(token)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)
+    }
+
+    /// Use up one download of a live share.
+    ///
+    /// The check and decrement are a single statement so concurrent requests can't
+    /// exceed the download limit. Expired or exhausted shares are removed.
+    pub async fn consume_share(&self, token: &str) -> Result<Option<Share>, FileError> {
+        let now = chrono::Utc::now().to_rfc3339();
+
+        let share = sqlx::query_as::<_, Share>(
+            "UPDATE shares SET remaining_downloads = remaining_downloads - 1
+             WHERE token = $1
+               AND (remaining_downloads IS NULL OR remaining_downloads > 0)
+               AND (expires_at IS NULL OR expires_at > $2)
+             RETURNING *",
+        )
+        .bind(token)
+        .bind(&now)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+        let exhausted = share
+            .as_ref()
+            .is_none_or(|share| share.remaining_downloads == Some(0));
+        if exhausted {
+            sqlx::query("DELETE FROM shares WHERE token = $1")
+                .bind(token)
+                .execute(&self.pool)
+                .await
+                .map_err(FileError::DatabaseError)?;
+        }
+
+        Ok(share)
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/{id}/share",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    request_body = CreateShareRequest,
+    responses(
+        (status = 201, description = "Share link created (replaces any existing link)", body = ShareResponse),
+        (status = 400, description = "Invalid share settings"),
+        (status = 404, description = "File not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn create_share(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    payload: Option<Json<CreateShareRequest>>,
+) -> Result<(StatusCode, Json<ShareResponse>), FileError> {
+    let Json(payload) = payload.unwrap_or_default();
+
+    if payload.expires_in_seconds.is_some_and(|s| s <= 0)
+        || payload.max_downloads.is_some_and(|n| n <= 0)
+        || payload.password.as_deref().is_some_and(str::is_empty)
+    {
+        return Err(FileError::InvalidShareSettings);
+    }
+
+    let expires_at = match payload.expires_in_seconds {
+        Some(seconds) => {
+            let expiry = chrono::TimeDelta::try_seconds(seconds)
+                .and_then(|delta| chrono::Utc::now().checked_add_signed(delta))
+                .ok_or(FileError::InvalidShareSettings)?;
+            Some(expiry.to_rfc3339())
+        }
+        None => None,
+    };
+
+    FileRepository::new(state.db_pool.clone())
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
+
+    let password_hash = payload
+        .password
+        .as_deref()
+        .map(hash_password)
+        .transpose()
+        .map_err(|_| FileError::InternalError)?;
+
+    let share = ShareRepository::new(state.db_pool.clone())
+        .create_share(&id, &claims.user_id, expires_at, payload.max_downloads, password_hash)
+        .await?;
+    audit::record(&state.db_pool, Some(&claims.user_id), audit::SHARE_CREATED, Some(&id), None);
+
+    Ok((StatusCode::CREATED, Json(share.into())))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/files/{id}/share",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID")
+    ),
+    responses(
+        (status = 204, description = "Share link revoked"),
+        (status = 404, description = "File is not shared")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn revoke_share(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, FileError> {
+    let revoked = ShareRepository::new(state.db_pool.clone())
+        .delete_share(&id, &claims.user_id)
+        .await?;
+
+    if revoked {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(FileError::ShareNotFound)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/share/{token}",
+    tag = "files",
+    params(
+        ("token" = String, Path, description = "Share token"),
+        SharePassword
+    ),
+    responses(
+        (status = 200, description = "Shared file download", content_type = "application/octet-stream"),
+        (status = 401, description = "Missing or incorrect share password"),
+        (status = 404, description = "Share link not found or expired")
+    )
+)]
+pub async fn download_shared_file(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Query(query): Query<SharePassword>,
+) -> Result<Response, FileError> {
+    serve_share(&state, &token, query.password.as_deref()).await
+}
+
+/// Form-post variant of `download_shared_file`, keeping the password out of the URL
+#[utoipa::path(
+    post,
+    path = "/api/share/{token}",
+    tag = "files",
+    params(
+        ("token" = String, Path, description = "Share token")
+    ),
+    request_body(content = SharePassword, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Shared file download", content_type = "application/octet-stream"),
+        (status = 401, description = "Missing or incorrect share password"),
+        (status = 404, description = "Share link not found or expired")
+    )
+)]
+pub async fn download_shared_file_form(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Form(form): Form<SharePassword>,
+) -> Result<Response, FileError> {
+    serve_share(&state, &token, form.password.as_deref()).await
+}
+
+async fn serve_share(
+    state: &AppState,
+    token: &str,
+    password: Option<&str>,
+) -> Result<Response, FileError> {
+    let share_repo = ShareRepository::new(state.db_pool.clone());
+
+    // Check the password before spending one of the share's downloads
+    let share = share_repo
+        .get_live_share(token)
+        .await?
+        .ok_or(FileError::ShareNotFound)?;
+
+    if let Some(password_hash) = &share.password_hash {
+        let password = password.ok_or(FileError::SharePasswordRequired)?;
+        let valid =
+            verify_password(password, password_hash).map_err(|_| FileError::InternalError)?;
+        if !valid {
+            return Err(FileError::SharePasswordRequired);
+        }
+    }
+
+    // A limited share can't be counted down in maintenance mode, so it waits; others
+    // are served without touching the row
+    let share = if !state.maintenance.is_enabled() {
+        share_repo
+            .consume_share(token)
+            .await?
+            .ok_or(FileError::ShareNotFound)?
+    } else if share.remaining_downloads.is_some() {
+        return Err(FileError::ReadOnly);
+    } else {
+        share
+    };
+
+    let file = FileRepository::new(state.db_pool.clone())
+        .get_file(&share.file_id, &share.user_id)
+        .await?
+        .ok_or(FileError::ShareNotFound)?;
+
+    stream_file(state, &file).await
+}
@@ -1,32 +1,111 @@
 use axum::{
+    Json,
     body::Body,
-    http::{StatusCode, Uri, header},
-    response::IntoResponse,
+    http::{HeaderMap, HeaderValue, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
 };
 use rust_embed::Embed;
+use serde_json::json;
 
 #[derive(Embed)]
 #[folder = "frontend/dist"]
 pub struct Asset;
 
-pub async fn handler(uri: Uri) -> impl IntoResponse {
+/// Precompressed sibling suffixes, in order of preference, with their `Content-Encoding`
+const PRECOMPRESSED: &[(&str, &str)] = &[("br", "br"), ("gz", "gzip")];
+
+pub async fn handler(uri: Uri, headers: HeaderMap) -> impl IntoResponse {
     let path = uri.path().trim_start_matches('/');
 
-    if let Some(content) = Asset::get(path) {
-        let mime = mime_guess::from_path(path).first_or_octet_stream();
+    // Unknown API routes get a JSON 404 rather than the SPA shell
+    if path == "api" || path.starts_with("api/") {
         return (
-            [(header::CONTENT_TYPE, mime.as_ref())],
-            Body::from(content.data),
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": "Not found",
+                "request_id": crate::request_id::current(),
+            })),
         )
             .into_response();
     }
 
-    match Asset::get("index.html") {
-        Some(content) => (
-            [(header::CONTENT_TYPE, "text/html")],
-            Body::from(content.data),
-        )
-            .into_response(),
-        None => (StatusCode::NOT_FOUND, "404 Not Found").into_response(),
+    if let Some(response) = serve_asset(path, &headers) {
+        return response;
     }
+
+    serve_asset("index.html", &headers)
+        .unwrap_or_else(|| (StatusCode::NOT_FOUND, "404 Not Found").into_response())
+}
+
+/// Serve an embedded asset, preferring a `.br`/`.gz` sibling the client accepts
+fn serve_asset(path: &str, headers: &HeaderMap) -> Option<Response> {
+    let (content, encoding) = PRECOMPRESSED
+        .iter()
+        .filter(|(_, encoding)| accepts_encoding(headers, encoding))
+        .find_map(|(suffix, encoding)| {
+            Asset::get(&format!("{}.{}", path, suffix)).map(|content| (content, Some(*encoding)))
+        })
+        .or_else(|| Asset::get(path).map(|content| (content, None)))?;
+
+    // Each encoded variant has its own hash, so the tag stays distinct per representation
+    let etag = format!("\"{}\"", hex::encode(content.metadata.sha256_hash()));
+    // Vite content-hashes everything but the entry page, so only index.html must revalidate
+    let cache_control = if path == "index.html" {
+        "no-cache"
+    } else {
+        "public, max-age=31536000, immutable"
+    };
+
+    let mut response = if if_none_match(headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        let mut response =
+            ([(header::CONTENT_TYPE, mime.as_ref())], Body::from(content.data)).into_response();
+        if let Some(encoding) = encoding {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        response
+    };
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    Some(response)
+}
+
+/// Whether `If-None-Match` matches `etag` (weak comparison, `*` matches anything)
+pub(crate) fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Whether `Accept-Encoding` lists `encoding` without `q=0`
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            name.eq_ignore_ascii_case(encoding) && !rejected
+        })
 }
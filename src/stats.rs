@@ -1,42 +1,191 @@
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::{
+        Query, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    http::StatusCode,
+    response::Response,
+    Json,
+};
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use sysinfo::{System, Disks, Networks};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
+use std::collections::HashSet;
 use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
 
-use crate::{AppState, auth::Claims};
+use crate::{AppState, auth::{AdminUser, BEARER_SUBPROTOCOL, Claims}, db::DbPool};
+
+/// Default stats refresh rate (every 500ms)
+pub const DEFAULT_UPDATE_RATE_HZ: u32 = 2;
+/// Bounds for the configurable refresh rate, to keep refreshes from becoming a DoS vector
+pub const MIN_UPDATE_RATE_HZ: u32 = 1;
+pub const MAX_UPDATE_RATE_HZ: u32 = 20;
+
+/// Cumulative I/O byte counters at one refresh
+#[derive(Clone, Copy, Default)]
+struct IoCounters {
+    network_rx: u64,
+    network_tx: u64,
+    disk_read: u64,
+    disk_written: u64,
+}
+
+/// I/O throughput in bytes per second between the last two refreshes
+#[derive(Clone, Copy, Default)]
+pub struct IoRates {
+    pub network_rx: u64,
+    pub network_tx: u64,
+    pub disk_read: u64,
+    pub disk_write: u64,
+}
 
 pub struct StatsCache {
     sys: System,
+    disks: Disks,
+    networks: Networks,
     last_refresh: Instant,
     cache_duration: Duration,
+    update_rate_hz: u32,
+    /// Counters from the previous refresh; rates are zero until there is one
+    previous_io: Option<(Instant, IoCounters)>,
+    io_rates: IoRates,
 }
 
 impl StatsCache {
     pub fn new() -> Self {
+        // CPU usage is a delta between two samples, so take a second one now
+        // to avoid the first reading being zero
+        let mut sys = System::new_all();
+        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        sys.refresh_cpu_usage();
+
         Self {
-            sys: System::new_all(),
+            sys,
+            disks: Disks::new_with_refreshed_list(),
+            networks: Networks::new_with_refreshed_list(),
             last_refresh: Instant::now() - Duration::from_secs(10), // Force initial refresh
-            cache_duration: Duration::from_millis(500), // Refresh at most every 500ms (2Hz)
+            cache_duration: Duration::from_millis(1000 / DEFAULT_UPDATE_RATE_HZ as u64),
+            update_rate_hz: DEFAULT_UPDATE_RATE_HZ,
+            previous_io: None,
+            io_rates: IoRates::default(),
         }
     }
 
+    pub fn update_rate_hz(&self) -> u32 {
+        self.update_rate_hz
+    }
+
+    /// Set the refresh rate, clamped to the supported range. Returns the applied rate.
+    pub fn set_update_rate_hz(&mut self, hz: u32) -> u32 {
+        let hz = hz.clamp(MIN_UPDATE_RATE_HZ, MAX_UPDATE_RATE_HZ);
+        self.update_rate_hz = hz;
+        self.cache_duration = Duration::from_millis(1000 / hz as u64);
+        hz
+    }
+
     pub fn refresh_if_needed(&mut self) {
         if self.last_refresh.elapsed() >= self.cache_duration {
             self.sys.refresh_all();
+            // Also picks up newly mounted disks and drops unmounted ones
+            self.disks.refresh(true);
+            self.networks.refresh(true);
             self.last_refresh = Instant::now();
+            self.update_io_rates();
+        }
+    }
+
+    fn io_counters(&self) -> IoCounters {
+        let (network_rx, network_tx) = self.networks.iter().fold((0u64, 0u64), |(rx, tx), (_name, network)| {
+            (rx + network.total_received(), tx + network.total_transmitted())
+        });
+
+        // A device mounted in several places would otherwise be counted once per mount
+        let mut devices = HashSet::new();
+        let (disk_read, disk_written) = self
+            .disks
+            .iter()
+            .filter(|disk| devices.insert(disk.name().to_owned()))
+            .fold((0u64, 0u64), |(read, written), disk| {
+                let usage = disk.usage();
+                (read + usage.total_read_bytes, written + usage.total_written_bytes)
+            });
+
+        IoCounters { network_rx, network_tx, disk_read, disk_written }
+    }
+
+    fn update_io_rates(&mut self) {
+        let now = self.last_refresh;
+        let counters = self.io_counters();
+
+        if let Some((at, previous)) = self.previous_io {
+            let seconds = now.duration_since(at).as_secs_f64();
+            // Counters drop when an interface or disk disappears; report no traffic then
+            let rate = |current: u64, previous: u64| {
+                if seconds > 0.0 {
+                    (current.saturating_sub(previous) as f64 / seconds) as u64
+                } else {
+                    0
+                }
+            };
+            self.io_rates = IoRates {
+                network_rx: rate(counters.network_rx, previous.network_rx),
+                network_tx: rate(counters.network_tx, previous.network_tx),
+                disk_read: rate(counters.disk_read, previous.disk_read),
+                disk_write: rate(counters.disk_written, previous.disk_written),
+            };
         }
+        self.previous_io = Some((now, counters));
     }
 
     pub fn get_system(&self) -> &System {
         &self.sys
     }
+
+    pub fn get_disks(&self) -> &Disks {
+        &self.disks
+    }
+
+    pub fn get_networks(&self) -> &Networks {
+        &self.networks
+    }
+
+    pub fn io_rates(&self) -> IoRates {
+        self.io_rates
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DiskStat {
+    /// Mount point of the disk
+    pub mount_point: String,
+    /// Total disk space in bytes
+    pub total: u64,
+    /// Available disk space in bytes
+    pub available: u64,
+    /// Filesystem type (e.g. ext4, apfs)
+    pub file_system: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct LoadAverage {
+    /// Load average over the last minute
+    pub one: f64,
+    /// Load average over the last 5 minutes
+    pub five: f64,
+    /// Load average over the last 15 minutes
+    pub fifteen: f64,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct SystemStats {
-    /// CPU usage percentage
+    /// CPU usage percentage (average across cores)
     pub cpu_usage: f32,
+    /// CPU usage percentage of each core
+    pub per_core: Vec<f32>,
+    /// System load average (all zeros on platforms without one, e.g. Windows)
+    pub load_average: LoadAverage,
     /// Memory used in bytes
     pub memory_used: u64,
     /// Total memory in bytes
@@ -49,23 +198,65 @@ pub struct SystemStats {
     pub disk_total: u64,
     /// Disk usage percentage
     pub disk_percent: f32,
+    /// Per-disk breakdown
+    pub disks: Vec<DiskStat>,
     /// Network received bytes
     pub network_rx: u64,
     /// Network transmitted bytes
     pub network_tx: u64,
+    /// Network receive rate in bytes per second (0 on the first sample)
+    pub network_rx_rate: u64,
+    /// Network transmit rate in bytes per second (0 on the first sample)
+    pub network_tx_rate: u64,
+    /// Disk read rate in bytes per second (0 on the first sample)
+    pub disk_read_rate: u64,
+    /// Disk write rate in bytes per second (0 on the first sample)
+    pub disk_write_rate: u64,
     /// Total files stored
     pub total_files: i64,
     /// Total storage used by files
     pub total_file_size: i64,
-    /// Uptime in seconds
+    /// Seconds since this server process started
     pub uptime: u64,
+    /// Seconds since the host booted
+    pub host_uptime: u64,
     /// Update rate in Hz
     pub update_rate_hz: u32,
 }
 
+#[derive(Serialize, Deserialize, ToSchema, FromRow)]
+pub struct UserStorageStats {
+    pub user_id: String,
+    pub username: String,
+    /// Number of files owned by the user
+    pub file_count: i64,
+    /// Total bytes stored by the user
+    pub total_bytes: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UserStorageReport {
+    /// Users on this page, largest consumers first
+    pub users: Vec<UserStorageStats>,
+    /// Files stored across all users
+    pub total_files: i64,
+    /// Bytes stored across all users
+    pub total_bytes: i64,
+    pub total_users: i64,
+    pub page: i64,
+    pub page_size: i64,
+    pub total_pages: i64,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct UserStorageQuery {
+    pub page: Option<i64>,
+    pub page_size: Option<i64>,
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct StatsConfig {
-    /// Update rate in Hz (default: 50)
+    /// Update rate in Hz (default: 2, clamped to 1-20)
     pub update_rate_hz: u32,
 }
 
@@ -73,6 +264,7 @@ pub struct StatsConfig {
 #[utoipa::path(
     get,
     path = "/api/stats",
+    tag = "stats",
     responses(
         (status = 200, description = "System statistics", body = SystemStats),
         (status = 401, description = "Unauthorized")
@@ -85,38 +277,155 @@ pub async fn get_stats(
     claims: Claims,
     State(state): State<AppState>,
 ) -> Result<Json<SystemStats>, StatusCode> {
+    Ok(Json(collect_stats(&state, &claims.user_id).await))
+}
+
+/// Stream system statistics over a WebSocket
+///
+/// Browsers can't set an `Authorization` header on WebSocket upgrades, so the token
+/// may instead be passed as `?access_token=` or as the `bearer, <token>` subprotocol.
+#[utoipa::path(
+    get,
+    path = "/api/stats/ws",
+    tag = "stats",
+    params(
+        ("access_token" = Option<String>, Query, description = "JWT, if not sent via header or subprotocol")
+    ),
+    responses(
+        (status = 101, description = "Switching to a WebSocket streaming SystemStats frames"),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn stats_ws(
+    claims: Claims,
+    State(state): State<AppState>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.protocols([BEARER_SUBPROTOCOL])
+        .on_upgrade(move |socket| stream_stats(socket, state, claims))
+}
+
+/// How often an open stream re-checks the account it was opened for
+const REVALIDATE_INTERVAL: Duration = Duration::from_secs(10);
+
+async fn stream_stats(mut socket: WebSocket, state: AppState, claims: Claims) {
+    // Ticks once right away; the configured period takes over after the first frame.
+    // Client messages never tick it, so they can't speed the stream up.
+    let mut period = Duration::ZERO;
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    // A slow send pushes the next frame back instead of triggering a burst
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // Claims are only checked on upgrade, so suspension, sign-out everywhere and expiry
+    // would otherwise never reach a stream that is already open
+    let mut recheck = tokio::time::interval_at(
+        tokio::time::Instant::now() + REVALIDATE_INTERVAL,
+        REVALIDATE_INTERVAL,
+    );
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = recheck.tick() => {
+                if let Err(e) = claims.revalidate(&state.db_pool).await {
+                    tracing::info!(user_id = %claims.user_id, error = ?e, "Closing stats stream");
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::POLICY,
+                            reason: "Credentials no longer valid".into(),
+                        })))
+                        .await;
+                    break;
+                }
+                continue;
+            }
+            msg = socket.recv() => match msg {
+                // Ignore anything the client sends; only a close or error ends the stream
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        }
+
+        let stats = collect_stats(&state, &claims.user_id).await;
+        let frame = match serde_json::to_string(&stats) {
+            Ok(frame) => frame,
+            Err(_) => break,
+        };
+        if socket.send(Message::Text(frame.into())).await.is_err() {
+            break;
+        }
+
+        // Re-read the rate each frame so config changes apply to open streams
+        let rate = Duration::from_millis(1000 / stats.update_rate_hz.max(1) as u64);
+        if rate != period {
+            period = rate;
+            ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        }
+    }
+}
+
+/// Number of files a user has and their total size in bytes
+pub(crate) async fn user_file_totals(pool: &DbPool, user_id: &str) -> Result<(i64, i64), sqlx::Error> {
+    sqlx::query_as("SELECT COUNT(*), CAST(COALESCE(SUM(size_bytes), 0) AS BIGINT) FROM files WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+}
+
+/// Collect a stats snapshot, with file totals scoped to the given user
+async fn collect_stats(state: &AppState, user_id: &str) -> SystemStats {
     // Refresh stats cache (throttled to prevent DoS) and collect stats
     // We need to drop the lock before any await points
-    let (cpu_usage, memory_used, memory_total, disk_used, disk_total, network_rx, network_tx, uptime) = {
+    let (cpu_usage, per_core, load_average, memory_used, memory_total, disk_used, disk_total, disks, network_rx, network_tx, io_rates, host_uptime, update_rate_hz) = {
         let mut cache = state.stats_cache.lock().unwrap();
         cache.refresh_if_needed();
+        let update_rate_hz = cache.update_rate_hz();
+        let io_rates = cache.io_rates();
         let sys = cache.get_system();
 
         // Get CPU usage
         let cpu_usage = sys.global_cpu_usage();
+        let per_core: Vec<f32> = sys.cpus().iter().map(|cpu| cpu.cpu_usage()).collect();
+        let load = System::load_average();
+        let load_average = LoadAverage {
+            one: load.one,
+            five: load.five,
+            fifteen: load.fifteen,
+        };
 
         // Get memory stats
         let memory_used = sys.used_memory();
         let memory_total = sys.total_memory();
 
         // Get disk stats
-        let disks = Disks::new_with_refreshed_list();
+        let disks: Vec<DiskStat> = cache
+            .get_disks()
+            .iter()
+            .map(|disk| DiskStat {
+                mount_point: disk.mount_point().to_string_lossy().into_owned(),
+                total: disk.total_space(),
+                available: disk.available_space(),
+                file_system: disk.file_system().to_string_lossy().into_owned(),
+            })
+            .collect();
         let (disk_used, disk_total) = disks.iter().fold((0u64, 0u64), |(used, total), disk| {
-            (used + (disk.total_space() - disk.available_space()), total + disk.total_space())
+            (used + (disk.total - disk.available), total + disk.total)
         });
 
         // Get network stats
-        let networks = Networks::new_with_refreshed_list();
-        let (network_rx, network_tx) = networks.iter().fold((0u64, 0u64), |(rx, tx), (_name, network)| {
+        let (network_rx, network_tx) = cache.get_networks().iter().fold((0u64, 0u64), |(rx, tx), (_name, network)| {
             (rx + network.total_received(), tx + network.total_transmitted())
         });
 
-        let uptime = System::uptime();
+        let host_uptime = System::uptime();
 
         // Drop the lock before the database query
         drop(cache);
 
-        (cpu_usage, memory_used, memory_total, disk_used, disk_total, network_rx, network_tx, uptime)
+        (cpu_usage, per_core, load_average, memory_used, memory_total, disk_used, disk_total, disks, network_rx, network_tx, io_rates, host_uptime, update_rate_hz)
     };
 
     // Calculate percentages
@@ -133,27 +442,118 @@ pub async fn get_stats(
     };
 
     // Get file stats from database - SCOPED TO CURRENT USER
-    let file_stats: (i64, i64) = sqlx::query_as(
-        "SELECT COUNT(*), COALESCE(SUM(size_bytes), 0) FROM files WHERE user_id = ?"
-    )
-    .bind(&claims.user_id)
-    .fetch_one(&state.db_pool)
-    .await
-    .unwrap_or((0, 0));
+    let file_stats = user_file_totals(&state.db_pool, user_id).await.unwrap_or((0, 0));
 
-    Ok(Json(SystemStats {
+    SystemStats {
         cpu_usage,
+        per_core,
+        load_average,
         memory_used,
         memory_total,
         memory_percent,
         disk_used,
         disk_total,
         disk_percent,
+        disks,
         network_rx,
         network_tx,
+        network_rx_rate: io_rates.network_rx,
+        network_tx_rate: io_rates.network_tx,
+        disk_read_rate: io_rates.disk_read,
+        disk_write_rate: io_rates.disk_write,
         total_files: file_stats.0,
         total_file_size: file_stats.1,
-        uptime,
-        update_rate_hz: 2, // Actual refresh rate is 2Hz (every 500ms)
+        uptime: state.start_time.elapsed().as_secs(),
+        host_uptime,
+        update_rate_hz,
+    }
+}
+
+/// Set the system statistics refresh rate (admin only)
+#[utoipa::path(
+    post,
+    path = "/api/stats/config",
+    tag = "stats",
+    request_body = StatsConfig,
+    responses(
+        (status = 200, description = "Applied stats configuration", body = StatsConfig),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Admin privileges required")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn update_stats_config(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Json(config): Json<StatsConfig>,
+) -> Json<StatsConfig> {
+    let update_rate_hz = state
+        .stats_cache
+        .lock()
+        .unwrap()
+        .set_update_rate_hz(config.update_rate_hz);
+
+    Json(StatsConfig { update_rate_hz })
+}
+
+/// Per-user storage breakdown across all users (admin only)
+#[utoipa::path(
+    get,
+    path = "/api/stats/users",
+    tag = "stats",
+    params(UserStorageQuery),
+    responses(
+        (status = 200, description = "Per-user storage usage", body = UserStorageReport),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Admin privileges required")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_user_storage_stats(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<UserStorageQuery>,
+) -> Result<Json<UserStorageReport>, StatusCode> {
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(50).clamp(1, 500);
+
+    let users = sqlx::query_as::<_, UserStorageStats>(
+        "SELECT u.id AS user_id, u.username AS username,
+                COUNT(f.id) AS file_count, CAST(COALESCE(SUM(f.size_bytes), 0) AS BIGINT) AS total_bytes
+         FROM users u
+         LEFT JOIN files f ON f.user_id = u.id
+         GROUP BY u.id, u.username
+         ORDER BY total_bytes DESC, u.username ASC
+         LIMIT $1 OFFSET $2",
+    )
+    .bind(page_size)
+    .bind((page - 1) * page_size)
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let (total_files, total_bytes): (i64, i64) =
+        sqlx::query_as("SELECT COUNT(*), CAST(COALESCE(SUM(size_bytes), 0) AS BIGINT) FROM files")
+            .fetch_one(&state.db_pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let total_users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(UserStorageReport {
+        users,
+        total_files,
+        total_bytes,
+        total_users,
+        page,
+        page_size,
+        total_pages: (total_users as f64 / page_size as f64).ceil() as i64,
     }))
 }
@@ -0,0 +1,525 @@
+//! Where blobs and cached thumbnails live.
+//!
+//! Everything is addressed by a relative, `/`-separated key such as
+//! `<user_id>/ab/cd/<id>.bin`. `STORAGE_BACKEND` selects the implementation: `local`
+//! (default, files under `STORAGE_ROOT`) or `s3` (objects in `S3_BUCKET`, only when
+//! built with the `s3` feature).
+//!
+//! The local backend can spread users over several volumes listed in `STORAGE_ROOTS`.
+//! Each user is assigned one at signup and keeps it, recorded in `users.storage_root`.
+
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+use crate::db::DbPool;
+
+pub type ByteStream<'a> = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send + 'a>>;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Store `data` under `key`, replacing any existing object. Readers never see a
+    /// partial object, and nothing is left behind if `data` fails.
+    async fn put(&self, key: &str, data: ByteStream<'_>) -> io::Result<()>;
+
+    /// Stream an object's bytes; `NotFound` if there is none.
+    /// Opened eagerly, so a missing object is reported before anything is streamed.
+    async fn get(&self, key: &str) -> io::Result<ByteStream<'static>>;
+
+    /// Remove an object; removing one that doesn't exist is not an error
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.size(key).await?.is_some())
+    }
+
+    /// Size of an object in bytes, or `None` if there is none
+    async fn size(&self, key: &str) -> io::Result<Option<u64>>;
+
+    /// Remove every object under `prefix/`
+    async fn delete_prefix(&self, prefix: &str) -> io::Result<()>;
+
+    /// Every stored key, except those with a dot-prefixed segment (caches, probes and
+    /// in-progress writes)
+    async fn list(&self) -> io::Result<Vec<String>>;
+
+    /// Bytes that can still be stored, or `None` when the backend has no practical limit
+    async fn available_space(&self) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+/// Refuse keys that could escape the storage root or alias another key: empty
+/// segments, `.`/`..`, absolute paths, backslashes and NUL bytes
+pub fn validate_key(key: &str) -> io::Result<()> {
+    let contained = !key.is_empty()
+        && !key.contains(['\0', '\\'])
+        && key.split('/').all(|segment| {
+            let mut components = Path::new(segment).components();
+            matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+        });
+
+    if !contained {
+        tracing::error!(key = %key, "Refusing a storage key outside the storage root");
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsafe storage key"));
+    }
+    Ok(())
+}
+
+/// Every configured storage volume, indexed by `users.storage_root`
+#[derive(Clone)]
+pub struct Volumes(Arc<[Arc<dyn Storage>]>);
+
+impl Volumes {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Storage> {
+        self.0.iter().map(|volume| volume.as_ref())
+    }
+
+    pub fn get(&self, index: i64) -> io::Result<&dyn Storage> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.0.get(index))
+            .map(|volume| volume.as_ref())
+            .ok_or_else(|| io::Error::other(format!("storage volume {} is not configured", index)))
+    }
+
+    /// The volume holding a user's blobs and thumbnails; `NotFound` for unknown users
+    pub async fn for_user(&self, pool: &DbPool, user_id: &str) -> io::Result<&dyn Storage> {
+        let index: i64 = sqlx::query_scalar("SELECT storage_root FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(io::Error::other)?
+            .ok_or(io::ErrorKind::NotFound)?;
+        self.get(index)
+    }
+}
+
+/// Volume for a new user out of `count`: a hash of their id, so users spread evenly
+pub fn assign_volume(user_id: &str, count: usize) -> i64 {
+    let digest = Sha256::digest(user_id.as_bytes());
+    let hash = u64::from_be_bytes(digest[..8].try_into().expect("SHA-256 is 32 bytes"));
+    (hash % count.max(1) as u64) as i64
+}
+
+/// The volumes chosen by `STORAGE_BACKEND`, with the local backend falling back to
+/// `default_root`. Panics on a misconfiguration, since nothing works without storage.
+pub async fn from_env(default_root: &Path) -> Volumes {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or_default();
+
+    let volumes: Vec<Arc<dyn Storage>> = match backend.trim().to_ascii_lowercase().as_str() {
+        "" | "local" => {
+            // Existing users are on volume 0, so the original root must stay first
+            let roots = std::env::var("STORAGE_ROOTS")
+                .or_else(|_| std::env::var("STORAGE_ROOT"))
+                .unwrap_or_else(|_| default_root.to_string_lossy().into_owned());
+
+            let mut volumes: Vec<Arc<dyn Storage>> = Vec::new();
+            for root in roots.split(',').map(str::trim).filter(|root| !root.is_empty()) {
+                let storage = LocalStorage::new(root)
+                    .await
+                    .unwrap_or_else(|e| panic!("Failed to create storage root {:?}: {}", root, e));
+                volumes.push(Arc::new(storage));
+            }
+            if volumes.is_empty() {
+                panic!("STORAGE_ROOTS must list at least one directory");
+            }
+            volumes
+        }
+        #[cfg(feature = "s3")]
+        "s3" => vec![Arc::new(s3::S3Storage::from_env().await)],
+        #[cfg(not(feature = "s3"))]
+        "s3" => panic!("STORAGE_BACKEND=s3 needs a build with `--features s3`"),
+        other => panic!("STORAGE_BACKEND must be local or s3 (got {:?})", other),
+    };
+
+    Volumes(volumes.into())
+}
+
+/// Files under a root directory, one per key
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub async fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        tokio::fs::create_dir_all(&root).await?;
+        Ok(Self { root })
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+
+    async fn write(path: &Path, mut data: ByteStream<'_>) -> io::Result<()> {
+        let mut file = tokio::fs::File::create(path).await?;
+        while let Some(chunk) = data.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: ByteStream<'_>) -> io::Result<()> {
+        let path = self.path(key)?;
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsafe storage key"));
+        };
+        tokio::fs::create_dir_all(dir).await?;
+
+        // Written beside the target and renamed over it; the leading dot keeps it out of `list`
+        let temp = dir.join(format!(".{}.{}.tmp", name.to_string_lossy(), uuid::Uuid::new_v4()));
+        let written = match Self::write(&temp, data).await {
+            Ok(()) => tokio::fs::rename(&temp, &path).await,
+            Err(e) => Err(e),
+        };
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&temp).await;
+        }
+        written
+    }
+
+    async fn get(&self, key: &str) -> io::Result<ByteStream<'static>> {
+        let file = tokio::fs::File::open(self.path(key)?).await?;
+        Ok(ReaderStream::new(file).boxed())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    async fn size(&self, key: &str) -> io::Result<Option<u64>> {
+        match tokio::fs::metadata(self.path(key)?).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> io::Result<()> {
+        match tokio::fs::remove_dir_all(self.path(prefix)?).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut pending = vec![(self.root.clone(), String::new())];
+
+        while let Some((dir, prefix)) = pending.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                if name.starts_with('.') {
+                    continue;
+                }
+
+                let key = format!("{}{}", prefix, name);
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    pending.push((entry.path(), format!("{}/", key)));
+                } else if file_type.is_file() {
+                    keys.push(key);
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Free space on the volume holding the root, i.e. the disk with the longest
+    /// mount point the root lies under. sysinfo skips tmpfs and network mounts, so a
+    /// root on one of those is measured by the mount enclosing it.
+    async fn available_space(&self) -> io::Result<Option<u64>> {
+        let root = tokio::fs::canonicalize(&self.root).await?;
+
+        // Reads the mount table and statvfs's every disk
+        let disks = tokio::task::spawn_blocking(sysinfo::Disks::new_with_refreshed_list)
+            .await
+            .map_err(io::Error::other)?;
+
+        let disk = disks
+            .list()
+            .iter()
+            .filter(|disk| root.starts_with(disk.mount_point()))
+            .max_by_key(|disk| disk.mount_point().as_os_str().len());
+        Ok(disk.map(|disk| disk.available_space()))
+    }
+}
+
+#[cfg(feature = "s3")]
+mod s3 {
+    //! Objects in an S3 (or S3-compatible) bucket. Credentials and region come from the
+    //! usual AWS sources (`AWS_ACCESS_KEY_ID`, `AWS_REGION`, profiles, instance roles).
+
+    use std::io;
+
+    use async_trait::async_trait;
+    use aws_sdk_s3::{
+        Client,
+        config::BehaviorVersion,
+        error::DisplayErrorContext,
+        primitives::ByteStream as S3Body,
+        types::{CompletedMultipartUpload, CompletedPart},
+    };
+    use futures_util::StreamExt;
+    use tokio_util::io::ReaderStream;
+
+    use super::{ByteStream, Storage, validate_key};
+
+    /// Bytes per multipart part; S3 requires at least 5 MiB for all but the last
+    const PART_SIZE: usize = 8 * 1024 * 1024;
+
+    fn s3_error(e: impl std::error::Error) -> io::Error {
+        io::Error::other(DisplayErrorContext(e).to_string())
+    }
+
+    pub struct S3Storage {
+        client: Client,
+        bucket: String,
+        /// Prepended to every key (`S3_PREFIX`, e.g. `trusty/`)
+        prefix: String,
+    }
+
+    impl S3Storage {
+        /// `S3_BUCKET` (required), `S3_PREFIX`, and `S3_ENDPOINT` for S3-compatible
+        /// services such as MinIO (which also switches to path-style addressing)
+        pub async fn from_env() -> Self {
+            let bucket = std::env::var("S3_BUCKET")
+                .ok()
+                .filter(|bucket| !bucket.is_empty())
+                .expect("S3_BUCKET must be set when STORAGE_BACKEND=s3");
+            let prefix = std::env::var("S3_PREFIX").unwrap_or_default();
+            let prefix = match prefix.trim_matches('/') {
+                "" => String::new(),
+                prefix => format!("{}/", prefix),
+            };
+
+            let shared = aws_config::load_defaults(BehaviorVersion::latest()).await;
+            let mut config = aws_sdk_s3::config::Builder::from(&shared);
+            if let Some(endpoint) = std::env::var("S3_ENDPOINT").ok().filter(|e| !e.is_empty()) {
+                config = config.endpoint_url(endpoint).force_path_style(true);
+            }
+
+            Self {
+                client: Client::from_conf(config.build()),
+                bucket,
+                prefix,
+            }
+        }
+
+        fn object_key(&self, key: &str) -> io::Result<String> {
+            validate_key(key)?;
+            Ok(format!("{}{}", self.prefix, key))
+        }
+
+        /// Upload `first` and the rest of `data` as a multipart upload
+        async fn put_multipart(&self, key: &str, first: Vec<u8>, mut data: ByteStream<'_>) -> io::Result<()> {
+            let upload = self
+                .client
+                .create_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(s3_error)?;
+            let upload_id = upload
+                .upload_id()
+                .ok_or_else(|| io::Error::other("S3 returned no upload id"))?
+                .to_string();
+
+            let result: io::Result<()> = async {
+                let mut parts = Vec::new();
+                let mut buffer = first;
+                let mut finished = false;
+
+                while !finished {
+                    while buffer.len() < PART_SIZE {
+                        match data.next().await {
+                            Some(chunk) => buffer.extend_from_slice(&chunk?),
+                            None => {
+                                finished = true;
+                                break;
+                            }
+                        }
+                    }
+                    if buffer.is_empty() {
+                        break;
+                    }
+
+                    let part_number = parts.len() as i32 + 1;
+                    let part = self
+                        .client
+                        .upload_part()
+                        .bucket(&self.bucket)
+                        .key(key)
+                        .upload_id(&upload_id)
+                        .part_number(part_number)
+                        .body(S3Body::from(std::mem::take(&mut buffer)))
+                        .send()
+                        .await
+                        .map_err(s3_error)?;
+                    parts.push(
+                        CompletedPart::builder()
+                            .set_e_tag(part.e_tag().map(str::to_string))
+                            .part_number(part_number)
+                            .build(),
+                    );
+                }
+
+                self.client
+                    .complete_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(&upload_id)
+                    .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+                    .send()
+                    .await
+                    .map_err(s3_error)?;
+                Ok(())
+            }
+            .await;
+
+            if result.is_err() {
+                let _ = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(&upload_id)
+                    .send()
+                    .await;
+            }
+            result
+        }
+
+        /// Every object key under `prefix`, without `self.prefix`
+        async fn keys_under(&self, prefix: &str) -> io::Result<Vec<String>> {
+            let mut keys = Vec::new();
+            let mut pages = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(format!("{}{}", self.prefix, prefix))
+                .into_paginator()
+                .send();
+
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(s3_error)?;
+                for object in page.contents() {
+                    if let Some(key) = object.key().and_then(|key| key.strip_prefix(&self.prefix)) {
+                        keys.push(key.to_string());
+                    }
+                }
+            }
+            Ok(keys)
+        }
+    }
+
+    #[async_trait]
+    impl Storage for S3Storage {
+        async fn put(&self, key: &str, mut data: ByteStream<'_>) -> io::Result<()> {
+            let key = self.object_key(key)?;
+
+            // Small objects go up in one request; larger ones stream as a multipart upload
+            let mut buffer = Vec::new();
+            while buffer.len() < PART_SIZE {
+                match data.next().await {
+                    Some(chunk) => buffer.extend_from_slice(&chunk?),
+                    None => {
+                        self.client
+                            .put_object()
+                            .bucket(&self.bucket)
+                            .key(&key)
+                            .body(S3Body::from(buffer))
+                            .send()
+                            .await
+                            .map_err(s3_error)?;
+                        return Ok(());
+                    }
+                }
+            }
+
+            self.put_multipart(&key, buffer, data).await
+        }
+
+        async fn get(&self, key: &str) -> io::Result<ByteStream<'static>> {
+            let object = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(self.object_key(key)?)
+                .send()
+                .await
+                .map_err(|e| match e.as_service_error() {
+                    Some(service) if service.is_no_such_key() => io::Error::from(io::ErrorKind::NotFound),
+                    _ => s3_error(e),
+                })?;
+
+            Ok(ReaderStream::new(object.body.into_async_read()).boxed())
+        }
+
+        async fn delete(&self, key: &str) -> io::Result<()> {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(self.object_key(key)?)
+                .send()
+                .await
+                .map_err(s3_error)?;
+            Ok(())
+        }
+
+        async fn size(&self, key: &str) -> io::Result<Option<u64>> {
+            let head = self
+                .client
+                .head_object()
+                .bucket(&self.bucket)
+                .key(self.object_key(key)?)
+                .send()
+                .await;
+
+            match head {
+                Ok(head) => Ok(head.content_length().map(|len| len as u64)),
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+                Err(e) => Err(s3_error(e)),
+            }
+        }
+
+        async fn delete_prefix(&self, prefix: &str) -> io::Result<()> {
+            validate_key(prefix)?;
+            for key in self.keys_under(&format!("{}/", prefix)).await? {
+                self.delete(&key).await?;
+            }
+            Ok(())
+        }
+
+        async fn list(&self) -> io::Result<Vec<String>> {
+            let mut keys = self.keys_under("").await?;
+            keys.retain(|key| !key.split('/').any(|segment| segment.starts_with('.')));
+            Ok(keys)
+        }
+    }
+}
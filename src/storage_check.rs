@@ -0,0 +1,147 @@
+//! Startup reconciliation of the `files` and `blobs` tables with blob storage.
+//!
+//! Enabled with `--check-storage` or `STORAGE_CHECK=1`; discrepancies are only logged
+//! unless `--clean-storage` or `STORAGE_CHECK_CLEANUP=1` is given as well.
+
+use std::collections::HashSet;
+
+use sqlx::FromRow;
+
+use crate::db::DbPool;
+use crate::filemanager;
+use crate::storage::Volumes;
+use crate::thumbnails;
+
+#[derive(Debug, FromRow)]
+struct FileRow {
+    id: String,
+    user_id: String,
+    storage_path: String,
+    /// Owner's volume
+    storage_root: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct BlobRow {
+    storage_path: String,
+    storage_root: i64,
+}
+
+/// Counts of each kind of discrepancy found
+#[derive(Debug, Default)]
+pub struct StorageCheckSummary {
+    /// Stored `.bin` objects that no row points at
+    pub orphaned_blobs: usize,
+    /// `files` rows whose blob is missing from storage
+    pub dangling_files: usize,
+    /// `blobs` rows whose object is missing from storage
+    pub dangling_blobs: usize,
+    /// Discrepancies removed (only when cleaning up)
+    pub cleaned: usize,
+}
+
+/// Whether the check was requested on the command line or in the environment
+pub fn requested() -> bool {
+    std::env::args().any(|arg| arg == "--check-storage" || arg == "--clean-storage")
+        || crate::env_flag("STORAGE_CHECK")
+        || cleanup_requested()
+}
+
+/// Whether discrepancies should be removed rather than just reported
+pub fn cleanup_requested() -> bool {
+    std::env::args().any(|arg| arg == "--clean-storage") || crate::env_flag("STORAGE_CHECK_CLEANUP")
+}
+
+/// Every stored `.bin` object, with the index of the volume it is on. `list` already
+/// leaves out dot-prefixed keys (the thumbnail cache, health probes), which are not blobs.
+async fn stored_blobs(volumes: &Volumes) -> std::io::Result<HashSet<(i64, String)>> {
+    let mut blobs = HashSet::new();
+    for (index, volume) in volumes.iter().enumerate() {
+        let keys = volume.list().await?;
+        blobs.extend(keys.into_iter().filter(|key| key.ends_with(".bin")).map(|key| (index as i64, key)));
+    }
+    Ok(blobs)
+}
+
+/// Compare the database against storage, logging each discrepancy and removing it
+/// when `cleanup` is set. Orphans are deleted from storage; dangling rows are deleted
+/// from the database, since their content is already gone. A blob only counts as
+/// present on its owner's volume.
+pub async fn run(
+    pool: &DbPool,
+    volumes: &Volumes,
+    cleanup: bool,
+) -> Result<StorageCheckSummary, Box<dyn std::error::Error>> {
+    let stored = stored_blobs(volumes).await?;
+
+    let files = sqlx::query_as::<_, FileRow>(
+        "SELECT f.id, f.user_id, f.storage_path, u.storage_root FROM files f JOIN users u ON u.id = f.user_id",
+    )
+    .fetch_all(pool)
+    .await?;
+    let blobs = sqlx::query_as::<_, BlobRow>(
+        "SELECT b.storage_path, u.storage_root FROM blobs b JOIN users u ON u.id = b.user_id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut summary = StorageCheckSummary::default();
+
+    let referenced: HashSet<(i64, &str)> = files
+        .iter()
+        .map(|file| (file.storage_root, file.storage_path.as_str()))
+        .chain(blobs.iter().map(|blob| (blob.storage_root, blob.storage_path.as_str())))
+        .collect();
+    let is_stored = |volume: i64, path: &str| stored.contains(&(volume, path.to_string()));
+
+    for (volume, path) in stored.iter().filter(|(volume, path)| !referenced.contains(&(*volume, path.as_str()))) {
+        summary.orphaned_blobs += 1;
+        tracing::warn!(volume, storage_path = %path, "Orphaned blob in storage with no file row");
+
+        if cleanup {
+            match volumes.get(*volume)?.delete(path).await {
+                Ok(()) => summary.cleaned += 1,
+                Err(e) => tracing::warn!(error = ?e, storage_path = %path, "Failed to remove orphaned blob"),
+            }
+        }
+    }
+
+    for file in files.iter().filter(|file| !is_stored(file.storage_root, &file.storage_path)) {
+        summary.dangling_files += 1;
+        tracing::warn!(
+            file_id = %file.id,
+            user_id = %file.user_id,
+            storage_path = %file.storage_path,
+            "File row points at a missing blob"
+        );
+
+        if cleanup {
+            filemanager::delete_file_row(pool, &file.id, &file.user_id).await?;
+            thumbnails::invalidate(volumes.get(file.storage_root)?, &file.id).await;
+            summary.cleaned += 1;
+        }
+    }
+
+    for blob in blobs.iter().filter(|blob| !is_stored(blob.storage_root, &blob.storage_path)) {
+        summary.dangling_blobs += 1;
+        tracing::warn!(storage_path = %blob.storage_path, "Blob row points at a missing file");
+
+        if cleanup {
+            sqlx::query("DELETE FROM blobs WHERE storage_path = $1")
+                .bind(&blob.storage_path)
+                .execute(pool)
+                .await?;
+            summary.cleaned += 1;
+        }
+    }
+
+    tracing::info!(
+        orphaned_blobs = summary.orphaned_blobs,
+        dangling_files = summary.dangling_files,
+        dangling_blobs = summary.dangling_blobs,
+        cleaned = summary.cleaned,
+        "Storage check complete"
+    );
+
+    Ok(summary)
+}
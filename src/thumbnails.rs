@@ -0,0 +1,164 @@
+use std::io::Cursor;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures_util::{StreamExt, TryStreamExt};
+use image::{DynamicImage, ImageFormat};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::auth::Claims;
+use crate::db::DbPool;
+use crate::filemanager::{File, FileError, FileRepository, open_stored_file, user_storage};
+use crate::storage::Storage;
+
+const DEFAULT_SIZE: u32 = 256;
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 1024;
+
+/// Cache prefix in storage; thumbnails are kept as `<prefix>/<file_id>/<size>.jpg`
+const THUMBNAIL_DIR: &str = ".thumbnails";
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+pub struct ThumbnailQuery {
+    /// Longest edge in pixels (default 256, clamped to 16–1024)
+    pub size: Option<u32>,
+}
+
+fn thumbnail_prefix(file_id: &str) -> String {
+    format!("{}/{}", THUMBNAIL_DIR, file_id)
+}
+
+/// Whether a thumbnail of `file` can be rendered, as far as is known without trying:
+/// it must have an image type, and its current content mustn't have failed before
+pub fn available(file: &File) -> bool {
+    file.mime_type.starts_with("image/") && !file.thumbnail_failed
+}
+
+/// Remember that `file`'s content can't be rendered, unless it has been replaced since
+async fn record_failure(pool: &DbPool, file: &File) {
+    let result = sqlx::query("UPDATE files SET thumbnail_failed = TRUE WHERE id = $1 AND storage_path = $2")
+        .bind(&file.id)
+        .bind(&file.storage_path)
+        .execute(pool)
+        .await;
+    if let Err(e) = result {
+        tracing::warn!(error = ?e, file_id = %file.id, "Failed to record thumbnail failure");
+    }
+}
+
+/// Drop cached thumbnails for a file whose content changed or was deleted
+pub async fn invalidate(storage: &dyn Storage, file_id: &str) {
+    let _ = storage.delete_prefix(&thumbnail_prefix(file_id)).await;
+}
+
+/// Fit the image within `size`×`size` as a JPEG, never enlarging it
+fn render_thumbnail(data: &[u8], size: u32) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(data).ok()?;
+
+    let image = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+
+    // JPEG has no alpha channel
+    let image = DynamicImage::ImageRgb8(image.to_rgb8());
+
+    let mut jpeg = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+        .ok()?;
+    Some(jpeg)
+}
+
+fn jpeg_response(jpeg: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "image/jpeg"),
+            (header::CACHE_CONTROL, "private, max-age=3600"),
+        ],
+        jpeg,
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{id}/thumbnail",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID"),
+        ThumbnailQuery
+    ),
+    responses(
+        (status = 200, description = "JPEG thumbnail", content_type = "image/jpeg"),
+        (status = 404, description = "File not found"),
+        (status = 415, description = "File is not a supported image")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_thumbnail(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<ThumbnailQuery>,
+) -> Result<Response, FileError> {
+    let file = FileRepository::new(state.db_pool.clone())
+        .get_file(&id, &claims.user_id)
+        .await?
+        .ok_or(FileError::NotFound)?;
+
+    if !available(&file) {
+        return Err(FileError::NotAnImage);
+    }
+
+    let size = query.size.unwrap_or(DEFAULT_SIZE).clamp(MIN_SIZE, MAX_SIZE);
+    let storage = user_storage(&state, &file.user_id).await?;
+    let cache_key = format!("{}/{}.jpg", thumbnail_prefix(&file.id), size);
+
+    if let Ok(cached) = storage.get(&cache_key).await
+        && let Ok(jpeg) = cached.map_ok(|chunk| chunk.to_vec()).try_concat().await
+    {
+        return Ok(jpeg_response(jpeg));
+    }
+
+    let data: Vec<u8> = open_stored_file(&state, &file)
+        .await
+        .map_err(|_| FileError::StorageError)?
+        .try_fold(Vec::with_capacity(file.size_bytes as usize), |mut data, chunk| async move {
+            data.extend_from_slice(&chunk);
+            Ok(data)
+        })
+        .await
+        .map_err(|_| FileError::StorageError)?;
+
+    // Decoding and resizing are CPU-bound
+    let Some(jpeg) = tokio::task::spawn_blocking(move || render_thumbnail(&data, size))
+        .await
+        .map_err(|_| FileError::InternalError)?
+    else {
+        // Saves clients the round trip and the server the decoding next time
+        if !state.maintenance.is_enabled() {
+            record_failure(&state.db_pool, &file).await;
+        }
+        return Err(FileError::NotAnImage);
+    };
+
+    // Maintenance mode writes nothing, so the thumbnail is rendered again next time
+    if !state.maintenance.is_enabled() {
+        let data = futures_util::stream::iter([Ok(Bytes::copy_from_slice(&jpeg))]).boxed();
+        if let Err(e) = storage.put(&cache_key, data).await {
+            tracing::warn!(error = ?e, file_id = %file.id, "Failed to cache thumbnail");
+        }
+    }
+
+    Ok(jpeg_response(jpeg))
+}
@@ -0,0 +1,67 @@
+//! Which kinds of file may be uploaded, from `ALLOWED_MIME_TYPES` and `ALLOWED_EXTENSIONS`.
+//!
+//! Both are comma-separated and unset by default, which allows everything. MIME types
+//! may end in `/*` to allow a whole family (`image/*`); extensions are matched without
+//! their dot and regardless of case. When both are set an upload must pass both. Files
+//! arrive client-encrypted, so the declared type is all there is to check: the content
+//! can't be sniffed.
+
+use std::sync::LazyLock;
+
+pub static UPLOAD_POLICY: LazyLock<UploadPolicy> = LazyLock::new(UploadPolicy::from_env);
+
+#[derive(Debug)]
+pub struct UploadPolicy {
+    /// Lowercased types, `None` allowing any
+    mime_types: Option<Vec<String>>,
+    /// Lowercased extensions without the dot, `None` allowing any
+    extensions: Option<Vec<String>>,
+}
+
+impl UploadPolicy {
+    fn from_env() -> Self {
+        let policy = Self {
+            mime_types: env_list("ALLOWED_MIME_TYPES", |entry| entry.to_string()),
+            extensions: env_list("ALLOWED_EXTENSIONS", |entry| entry.trim_start_matches('.').to_string()),
+        };
+        if let Some(mime_types) = &policy.mime_types {
+            tracing::info!("Uploads limited to MIME types {}", mime_types.join(", "));
+        }
+        if let Some(extensions) = &policy.extensions {
+            tracing::info!("Uploads limited to extensions {}", extensions.join(", "));
+        }
+        policy
+    }
+
+    /// Whether a file called `name` of (normalized, lowercase) `mime_type` may be uploaded
+    pub fn allows(&self, name: &str, mime_type: &str) -> bool {
+        let type_allowed = self.mime_types.as_ref().is_none_or(|allowed| {
+            allowed.iter().any(|pattern| match pattern.strip_suffix("/*") {
+                Some(family) => mime_type.split_once('/').is_some_and(|(top, _)| top == family),
+                None => pattern == mime_type,
+            })
+        });
+        let extension_allowed = self.extensions.as_ref().is_none_or(|allowed| {
+            extension(name).is_some_and(|extension| allowed.contains(&extension))
+        });
+        type_allowed && extension_allowed
+    }
+}
+
+/// Non-empty, lowercased entries of a comma-separated variable; `None` when unset or empty
+fn env_list(name: &str, normalize: impl Fn(&str) -> String) -> Option<Vec<String>> {
+    let value = std::env::var(name).ok()?;
+    let entries: Vec<String> = value
+        .split(',')
+        .map(|entry| normalize(&entry.trim().to_ascii_lowercase()))
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    (!entries.is_empty()).then_some(entries)
+}
+
+/// Lowercased extension of a file name; a leading dot (".bashrc") isn't one
+fn extension(name: &str) -> Option<String> {
+    name.rfind('.')
+        .filter(|&dot| dot > 0 && dot + 1 < name.len())
+        .map(|dot| name[dot + 1..].to_ascii_lowercase())
+}
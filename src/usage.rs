@@ -0,0 +1,138 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::auth::Claims;
+use crate::filemanager::FileError;
+use crate::stats::user_file_totals;
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct UsageDeltaQuery {
+    /// Start of the period (RFC 3339)
+    pub since: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UsageDelta {
+    /// Start of the period (RFC 3339)
+    pub since: String,
+    /// End of the period, i.e. server time of the request (RFC 3339)
+    pub until: String,
+    pub files_added: i64,
+    pub bytes_added: i64,
+    pub files_removed: i64,
+    pub bytes_removed: i64,
+    /// Always true now that deletions are recorded; kept for clients that check it
+    pub deletions_tracked: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UsageSummary {
+    pub total_files: i64,
+    pub total_bytes: i64,
+    /// Storage the user may use; null while no quota applies
+    pub quota_bytes: Option<i64>,
+    /// `total_bytes` as a percentage of `quota_bytes`; null without a quota
+    pub percent_used: Option<f32>,
+}
+
+/// Parse an RFC 3339 timestamp and normalize it to the format `created_at` is stored in,
+/// so it can be compared directly in SQL.
+pub fn parse_timestamp(value: &str) -> Result<String, FileError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc).to_rfc3339())
+        .map_err(|_| FileError::InvalidTimestamp)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/me/usage-delta",
+    tag = "files",
+    params(UsageDeltaQuery),
+    responses(
+        (status = 200, description = "Storage added/removed since the timestamp", body = UsageDelta),
+        (status = 400, description = "Invalid timestamp")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_usage_delta(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<UsageDeltaQuery>,
+) -> Result<Json<UsageDelta>, FileError> {
+    let since = parse_timestamp(&query.since)?;
+    let until = Utc::now().to_rfc3339();
+
+    // Files uploaded in the period count as added even if they were deleted again
+    let (files_added, bytes_added): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), CAST(COALESCE(SUM(size_bytes), 0) AS BIGINT) FROM (
+             SELECT size_bytes FROM files WHERE user_id = $1 AND created_at >= $2
+             UNION ALL
+             SELECT size_bytes FROM deleted_files WHERE user_id = $1 AND created_at >= $2
+         ) AS added",
+    )
+    .bind(&claims.user_id)
+    .bind(&since)
+    .fetch_one(&state.db_pool)
+    .await
+    .map_err(FileError::DatabaseError)?;
+
+    let (files_removed, bytes_removed): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), CAST(COALESCE(SUM(size_bytes), 0) AS BIGINT) FROM deleted_files WHERE user_id = $1 AND deleted_at >= $2",
+    )
+    .bind(&claims.user_id)
+    .bind(&since)
+    .fetch_one(&state.db_pool)
+    .await
+    .map_err(FileError::DatabaseError)?;
+
+    Ok(Json(UsageDelta {
+        since,
+        until,
+        files_added,
+        bytes_added,
+        files_removed,
+        bytes_removed,
+        deletions_tracked: true,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/usage",
+    tag = "files",
+    responses(
+        (status = 200, description = "The caller's file count and storage used", body = UsageSummary)
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_usage_summary(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<Json<UsageSummary>, FileError> {
+    let (total_files, total_bytes) = user_file_totals(&state.db_pool, &claims.user_id)
+        .await
+        .map_err(FileError::DatabaseError)?;
+
+    // Users have no storage quotas yet
+    let quota_bytes: Option<i64> = None;
+    let percent_used = quota_bytes
+        .filter(|&quota| quota > 0)
+        .map(|quota| (total_bytes as f32 / quota as f32) * 100.0);
+
+    Ok(Json(UsageSummary {
+        total_files,
+        total_bytes,
+        quota_bytes,
+        percent_used,
+    }))
+}
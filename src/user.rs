@@ -1,12 +1,24 @@
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
     Argon2,
 };
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, SqlitePool};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::db::DbPool;
+use crate::storage;
+use crate::username_policy::USERNAME_POLICY;
+
+pub const ROLE_USER: &str = "user";
+pub const ROLE_ADMIN: &str = "admin";
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct User {
     pub id: String,
@@ -14,12 +26,54 @@ pub struct User {
     #[serde(skip_serializing)]
     pub password_hash: String,
     pub created_at: String,
+    pub role: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    /// Must match the `token_version` of a token for it to be accepted
+    #[serde(skip_serializing)]
+    pub token_version: i64,
+    /// Logins are refused until this time (RFC 3339)
+    pub locked_until: Option<String>,
+    /// False while an admin has the account suspended
+    pub is_active: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CreateUserRequest {
     pub username: String,
     pub password: String,
+    /// Optional contact address for account recovery; must be unique
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Invite code; required when the server runs with `SIGNUP_MODE=invite`
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+/// A user together with how much they have stored
+#[derive(Debug, Clone, FromRow)]
+pub struct UserWithFileCount {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub file_count: i64,
+    pub total_bytes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateProfileRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetConfirm {
+    /// Token from the reset email
+    pub token: String,
+    pub new_password: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -27,6 +81,10 @@ pub struct UserResponse {
     pub id: String,
     pub username: String,
     pub created_at: String,
+    pub role: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub is_active: bool,
 }
 
 impl From<User> for UserResponse {
@@ -35,6 +93,10 @@ impl From<User> for UserResponse {
             id: user.id,
             username: user.username,
             created_at: user.created_at,
+            role: user.role,
+            email: user.email,
+            email_verified: user.email_verified,
+            is_active: user.is_active,
         }
     }
 }
@@ -47,6 +109,15 @@ pub enum UserError {
     UserNotFound,
     InvalidPassword,
     InvalidUsername,
+    InvalidUsernameCharacters,
+    UsernameReserved,
+    UsernameBlocked,
+    InvalidEmail,
+    EmailExists,
+    InvalidVerificationToken,
+    InvalidResetToken,
+    /// Every problem found with a signup, as (field, error) in form order
+    InvalidFields(Vec<(&'static str, UserError)>),
 }
 
 impl std::fmt::Display for UserError {
@@ -58,6 +129,17 @@ impl std::fmt::Display for UserError {
             UserError::UserNotFound => write!(f, "User not found"),
             UserError::InvalidPassword => write!(f, "Invalid password"),
             UserError::InvalidUsername => write!(f, "Invalid username"),
+            UserError::InvalidUsernameCharacters => write!(f, "Username contains characters that aren't allowed"),
+            UserError::UsernameReserved => write!(f, "Username is reserved"),
+            UserError::UsernameBlocked => write!(f, "Username is not allowed"),
+            UserError::InvalidEmail => write!(f, "Invalid email"),
+            UserError::EmailExists => write!(f, "Email already in use"),
+            UserError::InvalidVerificationToken => write!(f, "Invalid or expired verification token"),
+            UserError::InvalidResetToken => write!(f, "Invalid or expired reset token"),
+            UserError::InvalidFields(errors) => {
+                let fields: Vec<&str> = errors.iter().map(|(field, _)| *field).collect();
+                write!(f, "Invalid {}", fields.join(", "))
+            }
         }
     }
 }
@@ -65,20 +147,41 @@ impl std::fmt::Display for UserError {
 impl std::error::Error for UserError {}
 
 pub struct UserRepository {
-    pool: SqlitePool,
+    pool: DbPool,
 }
 
 impl UserRepository {
-    pub fn new(pool: SqlitePool) -> Self {
+    pub fn new(pool: DbPool) -> Self {
         Self { pool }
     }
 
-    pub async fn create_user(&self, username: &str, password: &str) -> Result<User, UserError> {
-        if username.len() < 3 || username.len() > 50 {
-            return Err(UserError::InvalidUsername);
+    /// Create an account on one of `storage_volumes` volumes, chosen from its id
+    pub async fn create_user(
+        &self,
+        username: &str,
+        password: &str,
+        email: Option<&str>,
+        storage_volumes: usize,
+    ) -> Result<User, UserError> {
+        // Report every problem at once so a signup form can mark all the bad fields
+        let mut errors = Vec::new();
+        if let Err(e) = USERNAME_POLICY.check(username) {
+            errors.push(("username", e));
+        } else if self.find_by_username(username).await?.is_some() {
+            errors.push(("username", UserError::UsernameExists));
         }
         if password.len() < 6 {
-            return Err(UserError::InvalidPassword);
+            errors.push(("password", UserError::InvalidPassword));
+        }
+        if let Some(email) = email {
+            if !is_valid_email(email) {
+                errors.push(("email", UserError::InvalidEmail));
+            } else if self.find_by_email(email).await?.is_some() {
+                errors.push(("email", UserError::EmailExists));
+            }
+        }
+        if !errors.is_empty() {
+            return Err(UserError::InvalidFields(errors));
         }
 
         let password_hash = hash_password(password)?;
@@ -86,12 +189,15 @@ impl UserRepository {
         let now = chrono::Utc::now().to_rfc3339();
 
         let result = sqlx::query(
-            "INSERT INTO users (id, username, password_hash, created_at) VALUES (?, ?, ?, ?)",
+            "INSERT INTO users (id, username, password_hash, created_at, email, storage_root)
+             VALUES ($1, $2, $3, $4, $5, $6)",
         )
         .bind(&user_id)
         .bind(username)
         .bind(&password_hash)
         .bind(&now)
+        .bind(email)
+        .bind(storage::assign_volume(&user_id, storage_volumes))
         .execute(&self.pool)
         .await;
 
@@ -101,16 +207,26 @@ impl UserRepository {
                 username: username.to_string(),
                 password_hash,
                 created_at: now,
+                role: ROLE_USER.to_string(),
+                email: email.map(str::to_string),
+                email_verified: false,
+                token_version: 0,
+                locked_until: None,
+                is_active: true,
             }),
-            Err(sqlx::Error::Database(ref db_err)) if db_err.message().contains("UNIQUE") => {
-                Err(UserError::UsernameExists)
+            Err(sqlx::Error::Database(ref db_err)) if db_err.is_unique_violation() => {
+                if is_email_conflict(db_err.as_ref()) {
+                    Err(UserError::EmailExists)
+                } else {
+                    Err(UserError::UsernameExists)
+                }
             }
             Err(e) => Err(UserError::DatabaseError(e)),
         }
     }
 
     pub async fn find_by_username(&self, username: &str) -> Result<Option<User>, UserError> {
-        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = ?")
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
             .bind(username)
             .fetch_optional(&self.pool)
             .await
@@ -118,19 +234,356 @@ impl UserRepository {
     }
 
     pub async fn find_by_id(&self, user_id: &str) -> Result<Option<User>, UserError> {
-        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ?")
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
             .bind(user_id)
             .fetch_optional(&self.pool)
             .await
             .map_err(UserError::DatabaseError)
     }
 
+    /// Rename a user, subject to the same rules as signup
+    pub async fn update_username(&self, user_id: &str, username: &str) -> Result<User, UserError> {
+        USERNAME_POLICY.check(username)?;
+
+        let result = sqlx::query_as::<_, User>("UPDATE users SET username = $1 WHERE id = $2 RETURNING *")
+            .bind(username)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await;
+
+        match result {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => Err(UserError::UserNotFound),
+            Err(sqlx::Error::Database(ref db_err)) if db_err.is_unique_violation() => {
+                Err(UserError::UsernameExists)
+            }
+            Err(e) => Err(UserError::DatabaseError(e)),
+        }
+    }
+
+    /// One page of users, oldest first, with their file counts
+    pub async fn list_users(&self, page: i64, page_size: i64) -> Result<Vec<UserWithFileCount>, UserError> {
+        sqlx::query_as::<_, UserWithFileCount>(
+            "SELECT u.*,
+                    (SELECT COUNT(*) FROM files f WHERE f.user_id = u.id) AS file_count,
+                    (SELECT CAST(COALESCE(SUM(f.size_bytes), 0) AS BIGINT) FROM files f WHERE f.user_id = u.id) AS total_bytes
+             FROM users u
+             ORDER BY u.created_at ASC, u.id ASC
+             LIMIT $1 OFFSET $2",
+        )
+        .bind(page_size)
+        .bind((page - 1) * page_size)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(UserError::DatabaseError)
+    }
+
+    pub async fn count_users(&self) -> Result<i64, UserError> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(UserError::DatabaseError)
+    }
+
+    pub async fn set_role(&self, user_id: &str, role: &str) -> Result<User, UserError> {
+        sqlx::query_as::<_, User>("UPDATE users SET role = $1 WHERE id = $2 RETURNING *")
+            .bind(role)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(UserError::DatabaseError)?
+            .ok_or(UserError::UserNotFound)
+    }
+
+    /// Suspend (`false`) or reactivate (`true`) an account
+    pub async fn set_active(&self, user_id: &str, is_active: bool) -> Result<User, UserError> {
+        sqlx::query_as::<_, User>("UPDATE users SET is_active = $1 WHERE id = $2 RETURNING *")
+            .bind(is_active)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(UserError::DatabaseError)?
+            .ok_or(UserError::UserNotFound)
+    }
+
+    /// Revoke every token issued to a user so far; false if there is no such user
+    pub async fn revoke_tokens(&self, user_id: &str) -> Result<bool, UserError> {
+        let result = sqlx::query("UPDATE users SET token_version = token_version + 1 WHERE id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(UserError::DatabaseError)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revoke every token issued to a user so far and delete their API keys; false if
+    /// there is no such user
+    pub async fn revoke_credentials(&self, user_id: &str) -> Result<bool, UserError> {
+        let mut tx = self.pool.begin().await.map_err(UserError::DatabaseError)?;
+        let result = sqlx::query("UPDATE users SET token_version = token_version + 1 WHERE id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(UserError::DatabaseError)?;
+        sqlx::query("DELETE FROM api_keys WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(UserError::DatabaseError)?;
+        tx.commit().await.map_err(UserError::DatabaseError)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete a user; their files, folders, shares and blobs go with them via `ON DELETE CASCADE`
+    pub async fn delete_user(&self, user_id: &str) -> Result<bool, UserError> {
+        let result = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(UserError::DatabaseError)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn find_by_email(&self, email: &str) -> Result<Option<User>, UserError> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+            .bind(email)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(UserError::DatabaseError)
+    }
+
+    /// Count a failed login, locking the account until `lock_until` once `max_failures`
+    /// consecutive failures are reached. Returns the lock expiry if this failure locked it.
+    pub async fn record_failed_login(
+        &self,
+        user_id: &str,
+        max_failures: i64,
+        lock_until: &str,
+    ) -> Result<Option<String>, UserError> {
+        // One statement, so concurrent attempts can't both slip under the threshold
+        sqlx::query_scalar::<_, Option<String>>(
+            "UPDATE users
+             SET failed_login_attempts = CASE WHEN failed_login_attempts + 1 >= $2 THEN 0 ELSE failed_login_attempts + 1 END,
+                 locked_until = CASE WHEN failed_login_attempts + 1 >= $2 THEN $3 ELSE locked_until END
+             WHERE id = $1
+             RETURNING CASE WHEN locked_until = $3 THEN locked_until END",
+        )
+        .bind(user_id)
+        .bind(max_failures)
+        .bind(lock_until)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(UserError::DatabaseError)
+        .map(Option::flatten)
+    }
+
+    /// Reset the failure counter after a successful login
+    pub async fn clear_failed_logins(&self, user_id: &str) -> Result<(), UserError> {
+        sqlx::query(
+            "UPDATE users SET failed_login_attempts = 0, locked_until = NULL
+             WHERE id = $1 AND (failed_login_attempts > 0 OR locked_until IS NOT NULL)",
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(UserError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Issue a password reset token. Only its hash is stored, so the returned
+    /// token can't be recovered from the database.
+    pub async fn create_password_reset(
+        &self,
+        user_id: &str,
+        expires_at: &str,
+    ) -> Result<String, UserError> {
+        let token = generate_token();
+
+        sqlx::query(
+            "INSERT INTO password_resets (token_hash, user_id, created_at, expires_at)
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(hash_token(&token))
+        .bind(user_id)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .map_err(UserError::DatabaseError)?;
+
+        Ok(token)
+    }
+
+    /// Redeem a reset token: set the new password, spend every outstanding reset
+    /// token for the user, invalidate all tokens issued so far and delete their API keys
+    pub async fn reset_password(&self, token: &str, new_password: &str) -> Result<User, UserError> {
+        if new_password.len() < 6 {
+            return Err(UserError::InvalidPassword);
+        }
+        let password_hash = hash_password(new_password)?;
+
+        let mut tx = self.pool.begin().await.map_err(UserError::DatabaseError)?;
+
+        let user_id = sqlx::query_scalar::<_, String>(
+            "DELETE FROM password_resets WHERE token_hash = $1 AND expires_at > $2 RETURNING user_id",
+        )
+        .bind(hash_token(token))
+        .bind(chrono::Utc::now().to_rfc3339())
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(UserError::DatabaseError)?
+        .ok_or(UserError::InvalidResetToken)?;
+
+        let user = sqlx::query_as::<_, User>(
+            "UPDATE users SET password_hash = $1, token_version = token_version + 1
+             WHERE id = $2 RETURNING *",
+        )
+        .bind(&password_hash)
+        .bind(&user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(UserError::DatabaseError)?;
+
+        sqlx::query("DELETE FROM password_resets WHERE user_id = $1")
+            .bind(&user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(UserError::DatabaseError)?;
+        // A reset may follow a compromise, and keys never expire on their own
+        sqlx::query("DELETE FROM api_keys WHERE user_id = $1")
+            .bind(&user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(UserError::DatabaseError)?;
+
+        tx.commit().await.map_err(UserError::DatabaseError)?;
+        Ok(user)
+    }
+
+    /// Issue a verification token for the user's current email. Only its hash is
+    /// stored, so the returned token can't be recovered from the database.
+    pub async fn create_email_verification(
+        &self,
+        user: &User,
+        expires_at: &str,
+    ) -> Result<String, UserError> {
+        let email = user.email.as_deref().ok_or(UserError::InvalidEmail)?;
+        let token = generate_token();
+
+        sqlx::query(
+            "INSERT INTO email_verifications (token_hash, user_id, email, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(hash_token(&token))
+        .bind(&user.id)
+        .bind(email)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .map_err(UserError::DatabaseError)?;
+
+        Ok(token)
+    }
+
+    /// Redeem a verification token, marking the email it was issued for as verified.
+    ///
+    /// The token is spent even if the user has since changed their email, in which
+    /// case nothing is verified.
+    pub async fn confirm_email_verification(&self, token: &str) -> Result<User, UserError> {
+        let mut tx = self.pool.begin().await.map_err(UserError::DatabaseError)?;
+
+        let verification = sqlx::query_as::<_, (String, String)>(
+            "DELETE FROM email_verifications WHERE token_hash = $1 AND expires_at > $2 RETURNING user_id, email",
+        )
+        .bind(hash_token(token))
+        .bind(chrono::Utc::now().to_rfc3339())
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(UserError::DatabaseError)?;
+        let (user_id, email) = verification.ok_or(UserError::InvalidVerificationToken)?;
+
+        let user = sqlx::query_as::<_, User>(
+            "UPDATE users SET email_verified = TRUE WHERE id = $1 AND email = $2 RETURNING *",
+        )
+        .bind(&user_id)
+        .bind(&email)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(UserError::DatabaseError)?;
+        let Some(user) = user else {
+            tx.commit().await.map_err(UserError::DatabaseError)?;
+            return Err(UserError::InvalidVerificationToken);
+        };
+
+        // Any other outstanding links for this user are now pointless
+        sqlx::query("DELETE FROM email_verifications WHERE user_id = $1")
+            .bind(&user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(UserError::DatabaseError)?;
+
+        tx.commit().await.map_err(UserError::DatabaseError)?;
+        Ok(user)
+    }
+
+    /// Grant the admin role to every listed username that exists.
+    pub async fn promote_admins(&self, usernames: &[String]) -> Result<u64, UserError> {
+        let mut promoted = 0;
+        for username in usernames {
+            let result = sqlx::query("UPDATE users SET role = $1 WHERE username = $2 AND role != $1")
+                .bind(ROLE_ADMIN)
+                .bind(username)
+                .execute(&self.pool)
+                .await
+                .map_err(UserError::DatabaseError)?;
+            promoted += result.rows_affected();
+        }
+        Ok(promoted)
+    }
+
     pub fn verify_password(&self, user: &User, password: &str) -> Result<bool, UserError> {
         verify_password(password, &user.password_hash)
     }
 }
 
-fn hash_password(password: &str) -> Result<String, UserError> {
+/// Minimal shape check (`local@domain.tld`); deliverability is proven by verification
+fn is_valid_email(email: &str) -> bool {
+    if email.len() > 254 || email.chars().any(char::is_whitespace) {
+        return false;
+    }
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain
+                    .split_once('.')
+                    .is_some_and(|(host, tld)| !host.is_empty() && !tld.is_empty())
+        }
+        None => false,
+    }
+}
+
+/// Whether a unique violation came from `users.email` rather than `users.username`
+fn is_email_conflict(error: &dyn sqlx::error::DatabaseError) -> bool {
+    error.constraint().is_some_and(|c| c.contains("email")) || error.message().contains("email")
+}
+
+/// SHA-256 (hex) of a random token, as stored
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// 256-bit random, URL-safe token for verification links, resets and API keys
+pub(crate) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub(crate) fn hash_password(password: &str) -> Result<String, UserError> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
 
@@ -140,7 +593,7 @@ fn hash_password(password: &str) -> Result<String, UserError> {
         .map_err(|_| UserError::PasswordHashError)
 }
 
-fn verify_password(password: &str, password_hash: &str) -> Result<bool, UserError> {
+pub(crate) fn verify_password(password: &str, password_hash: &str) -> Result<bool, UserError> {
     let parsed_hash = PasswordHash::new(password_hash).map_err(|_| UserError::InvalidPassword)?;
 
     Ok(Argon2::default()
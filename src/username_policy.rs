@@ -0,0 +1,80 @@
+//! Which usernames may be taken, at signup and on rename.
+//!
+//! Besides being 3-50 characters long, a username must match `USERNAME_PATTERN`, a
+//! regular expression that by default allows letters, digits, `_` and `-`. It mustn't be
+//! one of `RESERVED_USERNAMES`, which could pass for the server or its staff, nor contain
+//! any entry of the comma-separated `USERNAME_BLOCKLIST` (unset by default). Both are
+//! compared regardless of case. Existing accounts keep their names until renamed.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::user::UserError;
+
+pub static USERNAME_POLICY: LazyLock<UsernamePolicy> = LazyLock::new(UsernamePolicy::from_env);
+
+/// Names nobody may sign up as
+const RESERVED_USERNAMES: &[&str] = &[
+    "admin",
+    "administrator",
+    "api",
+    "moderator",
+    "root",
+    "security",
+    "support",
+    "system",
+    "trusty",
+];
+
+const DEFAULT_PATTERN: &str = "^[A-Za-z0-9_-]+$";
+
+#[derive(Debug)]
+pub struct UsernamePolicy {
+    pattern: Regex,
+    /// Lowercased words no username may contain
+    blocklist: Vec<String>,
+}
+
+impl UsernamePolicy {
+    /// Panics on an invalid `USERNAME_PATTERN`, like other malformed settings do
+    fn from_env() -> Self {
+        let pattern = std::env::var("USERNAME_PATTERN")
+            .ok()
+            .filter(|pattern| !pattern.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_PATTERN.to_string());
+        let pattern = Regex::new(&pattern)
+            .unwrap_or_else(|e| panic!("USERNAME_PATTERN is not a valid regular expression: {}", e));
+
+        let blocklist: Vec<String> = std::env::var("USERNAME_BLOCKLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|entry| entry.trim().to_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        if !blocklist.is_empty() {
+            tracing::info!("{} blocked username words", blocklist.len());
+        }
+
+        Self { pattern, blocklist }
+    }
+
+    /// Whether `username` may be taken, and if not, the first rule it breaks
+    pub fn check(&self, username: &str) -> Result<(), UserError> {
+        if username.len() < 3 || username.len() > 50 {
+            return Err(UserError::InvalidUsername);
+        }
+        if !self.pattern.is_match(username) {
+            return Err(UserError::InvalidUsernameCharacters);
+        }
+
+        let lowercase = username.to_lowercase();
+        if RESERVED_USERNAMES.contains(&lowercase.as_str()) {
+            return Err(UserError::UsernameReserved);
+        }
+        if self.blocklist.iter().any(|word| lowercase.contains(word.as_str())) {
+            return Err(UserError::UsernameBlocked);
+        }
+        Ok(())
+    }
+}
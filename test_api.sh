@@ -1,8 +1,13 @@
 #!/bin/bash
 
 # Test script for Trusty encrypted file storage
+#
+# The default rate limit throttles this script; run the server with one it stays under
+# that the last test can still exceed, e.g. RATE_LIMIT_PER_SECOND=20 RATE_LIMIT_BURST=100
 
 API_BASE="http://localhost:3000"
+# Optional: an admin's token, for the tests that need the admin role
+ADMIN_TOKEN="${ADMIN_TOKEN:-}"
 TEST_USERNAME="testuser_$(date +%s)"
 TEST_PASSWORD="testpass123"
 
@@ -13,7 +18,7 @@ echo ""
 echo "1. Testing signup..."
 SIGNUP_RESPONSE=$(curl -s -X POST "$API_BASE/api/auth/signup" \
   -H "Content-Type: application/json" \
-  -d "{\"username\":\"$TEST_USERNAME\",\"password\":\"$TEST_PASSWORD\"}")
+  -d "{\"username\":\"$TEST_USERNAME\",\"password\":\"$TEST_PASSWORD\",\"email\":\"$TEST_USERNAME@example.com\"}")
 
 TOKEN=$(echo $SIGNUP_RESPONSE | grep -o '"access_token":"[^"]*' | cut -d'"' -f4)
 
@@ -138,9 +143,1206 @@ else
   echo "❌ File download failed (HTTP $HTTP_CODE)"
 fi
 
-# Test 9: Delete file
+# Test 9: Streamed listing
+echo ""
+echo "9. Testing streamed file listing..."
+# Enough files that the array is streamed as several elements
+STREAM_PREFIX="streamed_$(date +%s)"
+STREAM_IDS=()
+for i in 1 2 3 4 5; do
+  STREAM_IDS+=($(curl -s -X POST "$API_BASE/api/files/upload" \
+    -H "Authorization: Bearer $TOKEN" \
+    -F "file=@/tmp/$TEST_FILE" \
+    -F "metadata={\"original_name\":\"${STREAM_PREFIX}_$i.txt\",\"mime_type\":\"text/plain\",\"size_bytes\":100,\"client_encryption_algo\":\"AES-GCM-256\"}" \
+    | grep -o '"id":"[^"]*' | cut -d'"' -f4))
+done
+STREAM_RESPONSE=$(curl -s -X GET "$API_BASE/api/files/stream?q=$STREAM_PREFIX&sort=original_name&direction=desc" \
+  -H "Authorization: Bearer $TOKEN")
+PAGED_RESPONSE=$(curl -s -X GET "$API_BASE/api/files?q=$STREAM_PREFIX&sort=original_name&direction=desc&page_size=100" \
+  -H "Authorization: Bearer $TOKEN")
+
+# Same files, in the same order, as the paginated listing
+if python3 -c "
+import json, sys
+streamed = [f['id'] for f in json.loads(sys.argv[1])]
+paged = [f['id'] for f in json.loads(sys.argv[2])['files']]
+sys.exit(0 if len(streamed) == 5 and streamed == paged else 1)
+" "$STREAM_RESPONSE" "$PAGED_RESPONSE" 2>/dev/null; then
+  echo "✅ Streamed listing is well-formed JSON matching /api/files in count and order"
+else
+  echo "❌ Streamed listing failed"
+  echo "   Streamed: $STREAM_RESPONSE"
+  echo "   Paged: $PAGED_RESPONSE"
+fi
+
+for id in "${STREAM_IDS[@]}"; do
+  curl -s -o /dev/null -X DELETE "$API_BASE/api/files/$id" -H "Authorization: Bearer $TOKEN"
+done
+
+# Test 10: Upload receipt
+echo ""
+echo "10. Testing upload receipt verification..."
+RECEIPT_SIGNATURE=$(curl -s -X GET "$API_BASE/api/files/$FILE_ID/receipt" \
+  -H "Authorization: Bearer $TOKEN" | grep -o '"signature":"[^"]*' | cut -d'"' -f4)
+VERIFY_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/verify-receipt" \
+  -H "Content-Type: application/json" \
+  -d "{\"signature\":\"$RECEIPT_SIGNATURE\"}")
+
+if echo $VERIFY_RESPONSE | grep -q '"valid":true'; then
+  echo "✅ Receipt verifies against the stored file"
+else
+  echo "❌ Receipt verification failed"
+  echo $VERIFY_RESPONSE
+fi
+
+# Test 11: Auto-foldering
+echo ""
+echo "11. Testing auto-foldering of images..."
+curl -s -X PUT "$API_BASE/api/me/preferences" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"auto_folder_rules":[{"mime_prefix":"image/","folder":"images"}]}' > /dev/null
+IMAGE_METADATA='{"original_name":"photo.png","mime_type":"image/png","size_bytes":100,"client_encryption_algo":"AES-GCM-256"}'
+IMAGE_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/$TEST_FILE" \
+  -F "metadata=$IMAGE_METADATA")
+IMAGE_ID=$(echo $IMAGE_RESPONSE | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+IMAGE_PARENT=$(echo $IMAGE_RESPONSE | grep -o '"parent_id":"[^"]*' | cut -d'"' -f4)
+IMAGES_FOLDER=$(curl -s -X GET "$API_BASE/api/folders" \
+  -H "Authorization: Bearer $TOKEN" | grep -o '"id":"[^"]*","name":"images"' | cut -d'"' -f4)
+
+if [ -n "$IMAGES_FOLDER" ] && [ "$IMAGE_PARENT" = "$IMAGES_FOLDER" ]; then
+  echo "✅ Image landed in the images folder"
+else
+  echo "❌ Auto-foldering failed"
+  echo $IMAGE_RESPONSE
+fi
+curl -s -X DELETE "$API_BASE/api/files/$IMAGE_ID" -H "Authorization: Bearer $TOKEN" > /dev/null
+curl -s -X PUT "$API_BASE/api/me/preferences" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"auto_folder_rules":[]}' > /dev/null
+
+# Test 12: Unknown API route
+echo ""
+echo "12. Testing unknown API route returns a JSON 404..."
+UNKNOWN_HEADERS=$(curl -s -D - -o /tmp/unknown_api.json "$API_BASE/api/doesnotexist")
+UNKNOWN_STATUS=$(echo "$UNKNOWN_HEADERS" | head -1 | cut -d' ' -f2)
+
+if [ "$UNKNOWN_STATUS" = "404" ] && grep -q '"request_id":"[^"]' /tmp/unknown_api.json \
+  && echo "$UNKNOWN_HEADERS" | grep -qi '^x-request-id:'; then
+  echo "✅ Unknown API route returns a JSON 404 with its request id"
+else
+  echo "❌ Unknown API route returned $UNKNOWN_STATUS"
+  cat /tmp/unknown_api.json
+fi
+rm -f /tmp/unknown_api.json
+
+# Test 13: Share link
+echo ""
+echo "13. Testing single-use share link..."
+SHARE_URL=$(curl -s -X POST "$API_BASE/api/files/$FILE_ID/share" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"max_downloads":1}' | grep -o '"url":"[^"]*' | cut -d'"' -f4)
+FIRST_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE$SHARE_URL")
+SECOND_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE$SHARE_URL")
+
+if [ "$FIRST_STATUS" = "200" ] && [ "$SECOND_STATUS" = "404" ]; then
+  echo "✅ Share link works once without auth, then expires"
+else
+  echo "❌ Share link returned $FIRST_STATUS then $SECOND_STATUS"
+fi
+
+# Test 14: Password-protected share link
+echo ""
+echo "14. Testing password-protected share link..."
+SHARE_URL=$(curl -s -X POST "$API_BASE/api/files/$FILE_ID/share" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"password":"opensesame"}' | grep -o '"url":"[^"]*' | cut -d'"' -f4)
+NO_PASSWORD_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE$SHARE_URL")
+WRONG_PASSWORD_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE$SHARE_URL?password=wrong")
+FORM_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE$SHARE_URL" -d "password=opensesame")
+
+if [ "$NO_PASSWORD_STATUS" = "401" ] && [ "$WRONG_PASSWORD_STATUS" = "401" ] && [ "$FORM_STATUS" = "200" ]; then
+  echo "✅ Protected share requires the right password"
+else
+  echo "❌ Protected share returned $NO_PASSWORD_STATUS / $WRONG_PASSWORD_STATUS / $FORM_STATUS"
+fi
+curl -s -X DELETE "$API_BASE/api/files/$FILE_ID/share" -H "Authorization: Bearer $TOKEN" > /dev/null
+
+# Test 15: Copy file
+echo ""
+echo "15. Testing file copy..."
+COPY_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/$FILE_ID/copy" \
+  -H "Authorization: Bearer $TOKEN")
+COPY_ID=$(echo $COPY_RESPONSE | grep -o '"id":"[^"]*' | cut -d'"' -f4)
+
+if echo $COPY_RESPONSE | grep -q "\"original_name\":\"Copy of $TEST_FILE\"" && \
+   curl -s "$API_BASE/api/files/$COPY_ID/download" -H "Authorization: Bearer $TOKEN" | cmp -s - /tmp/$TEST_FILE; then
+  echo "✅ Copy has the default name and identical content"
+else
+  echo "❌ File copy failed"
+  echo $COPY_RESPONSE
+fi
+curl -s -X DELETE "$API_BASE/api/files/$COPY_ID" -H "Authorization: Bearer $TOKEN" > /dev/null
+
+# Test 16: Listing filters
+echo ""
+echo "16. Testing listing filters..."
+TEXT_COUNT=$(curl -s -G "$API_BASE/api/files" -H "Authorization: Bearer $TOKEN" \
+  --data-urlencode "mime_prefix=text/" --data-urlencode "min_size=1" \
+  --data-urlencode "created_after=2000-01-01T00:00:00Z" | grep -o '"total":[0-9]*' | cut -d: -f2)
+IMAGE_COUNT=$(curl -s -G "$API_BASE/api/files" -H "Authorization: Bearer $TOKEN" \
+  --data-urlencode "mime_prefix=image/" | grep -o '"total":[0-9]*' | cut -d: -f2)
+
+if [ "$TEXT_COUNT" = "1" ] && [ "$IMAGE_COUNT" = "0" ]; then
+  echo "✅ MIME, size and date filters narrow the listing"
+else
+  echo "❌ Filters returned text=$TEXT_COUNT image=$IMAGE_COUNT"
+fi
+
+# Test 17: Sort orders
+echo ""
+echo "17. Testing every sort/direction combination..."
+SORT_IDS=""
+for SORT_FILE in "a-sort.bin:application/zip:1" "z-sort.bin:audio/mpeg:5000"; do
+  IFS=: read SORT_NAME SORT_MIME SORT_SIZE <<< "$SORT_FILE"
+  head -c $SORT_SIZE /dev/zero > /tmp/$SORT_NAME
+  SORT_ID=$(curl -s -X POST "$API_BASE/api/files/upload" \
+    -H "Authorization: Bearer $TOKEN" \
+    -F "file=@/tmp/$SORT_NAME" \
+    -F "metadata={\"original_name\":\"$SORT_NAME\",\"mime_type\":\"$SORT_MIME\",\"size_bytes\":$SORT_SIZE,\"client_encryption_algo\":\"AES-GCM-256\"}" \
+    | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+  SORT_IDS="$SORT_IDS $SORT_ID"
+  rm -f /tmp/$SORT_NAME
+done
+
+SORT_FAILURES=0
+for SORT in name size date type downloads foo; do
+  for DIRECTION in asc desc; do
+    if ! curl -s "$API_BASE/api/files?sort=$SORT&direction=$DIRECTION" -H "Authorization: Bearer $TOKEN" | \
+      python3 -c "
+import json, sys
+files = json.load(sys.stdin)['files']
+key = {'size': 'size_bytes', 'date': 'created_at', 'type': 'mime_type', 'downloads': 'download_count'}.get('$SORT', 'original_name')
+values = [f[key] for f in files]
+sys.exit(0 if len(values) >= 3 and values == sorted(values, reverse='$DIRECTION' == 'desc') else 1)"; then
+      echo "❌ sort=$SORT direction=$DIRECTION is out of order"
+      SORT_FAILURES=$((SORT_FAILURES + 1))
+    fi
+  done
+done
+
+if [ "$SORT_FAILURES" = "0" ]; then
+  echo "✅ All sort/direction combinations are ordered (unknown sort falls back to name)"
+fi
+for SORT_ID in $SORT_IDS; do
+  curl -s -X DELETE "$API_BASE/api/files/$SORT_ID" -H "Authorization: Bearer $TOKEN" > /dev/null
+done
+
+# Test 18: Multi-keyword search
+echo ""
+echo "18. Testing multi-keyword search..."
+MATCH_TOTAL=$(curl -s -G "$API_BASE/api/files" -H "Authorization: Bearer $TOKEN" \
+  --data-urlencode "q=file test" | grep -o '"total":[0-9]*' | cut -d: -f2)
+NO_MATCH_TOTAL=$(curl -s -G "$API_BASE/api/files" -H "Authorization: Bearer $TOKEN" \
+  --data-urlencode "q=test nonexistent" | grep -o '"total":[0-9]*' | cut -d: -f2)
+
+if [ "$MATCH_TOTAL" = "1" ] && [ "$NO_MATCH_TOTAL" = "0" ]; then
+  echo "✅ Every search term must match"
+else
+  echo "❌ Multi-keyword search returned $MATCH_TOTAL / $NO_MATCH_TOTAL"
+fi
+
+# Test 19: Replace content
+echo ""
+echo "19. Testing in-place content replacement..."
+echo "Replaced content at $(date)" > /tmp/replacement.txt
+REPLACE_RESPONSE=$(curl -s -X PUT "$API_BASE/api/files/$FILE_ID/content" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/replacement.txt")
+
+if echo $REPLACE_RESPONSE | grep -q "\"id\":\"$FILE_ID\"" && \
+   echo $REPLACE_RESPONSE | grep -q '"updated_at":"' && \
+   curl -s "$API_BASE/api/files/$FILE_ID/download" -H "Authorization: Bearer $TOKEN" | cmp -s - /tmp/replacement.txt; then
+  echo "✅ Content replaced under the same id"
+else
+  echo "❌ Content replacement failed"
+  echo $REPLACE_RESPONSE
+fi
+rm -f /tmp/replacement.txt
+
+# The receipt from test 10 was issued for the content just replaced
+STALE_RECEIPT_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/verify-receipt" \
+  -H "Content-Type: application/json" \
+  -d "{\"signature\":\"$RECEIPT_SIGNATURE\"}")
+if echo $STALE_RECEIPT_RESPONSE | grep -q '"signature_valid":true' && \
+   echo $STALE_RECEIPT_RESPONSE | grep -q '"matches_stored_file":false'; then
+  echo "✅ Receipt for the old content no longer matches the stored file"
+else
+  echo "❌ Receipt still matches after replacement"
+  echo $STALE_RECEIPT_RESPONSE
+fi
+
+# Test 20: Download counts
+echo ""
+echo "20. Testing download count tracking..."
+DOWNLOAD_COUNT=$(curl -s -G "$API_BASE/api/files" -H "Authorization: Bearer $TOKEN" \
+  --data-urlencode "q=$TEST_FILE" | grep -o '"download_count":[0-9]*' | head -1 | cut -d: -f2)
+
+if [ -n "$DOWNLOAD_COUNT" ] && [ "$DOWNLOAD_COUNT" -ge 1 ]; then
+  echo "✅ File has been downloaded $DOWNLOAD_COUNT time(s)"
+else
+  echo "❌ Download count not recorded"
+fi
+
+# Test 21: Single-file metadata
+echo ""
+echo "21. Testing single-file metadata..."
+METADATA_RESPONSE=$(curl -s "$API_BASE/api/files/$FILE_ID" -H "Authorization: Bearer $TOKEN")
+MISSING_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files/does-not-exist" -H "Authorization: Bearer $TOKEN")
+
+if echo $METADATA_RESPONSE | grep -q "\"id\":\"$FILE_ID\"" && [ "$MISSING_STATUS" = "404" ]; then
+  echo "✅ Metadata returned for owned file, 404 otherwise"
+else
+  echo "❌ Metadata endpoint failed"
+  echo $METADATA_RESPONSE
+fi
+
+# Test 22: Deduplicated uploads
+echo ""
+echo "22. Testing deduplicated uploads survive deleting one copy..."
+DEDUP_IDS=""
+for i in 1 2; do
+  DEDUP_ID=$(curl -s -X POST "$API_BASE/api/files/upload" \
+    -H "Authorization: Bearer $TOKEN" \
+    -F "file=@/tmp/$TEST_FILE" \
+    -F "metadata=$METADATA" | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+  DEDUP_IDS="$DEDUP_IDS $DEDUP_ID"
+done
+set -- $DEDUP_IDS
+curl -s -X DELETE "$API_BASE/api/files/$1" -H "Authorization: Bearer $TOKEN" > /dev/null
+
+if curl -s "$API_BASE/api/files/$2/download" -H "Authorization: Bearer $TOKEN" | cmp -s - /tmp/$TEST_FILE; then
+  echo "✅ Shared blob kept until its last reference is deleted"
+else
+  echo "❌ Deleting a duplicate broke the remaining copy"
+fi
+curl -s -X DELETE "$API_BASE/api/files/$2" -H "Authorization: Bearer $TOKEN" > /dev/null
+
+# Test 23: Thumbnails
+echo ""
+echo "23. Testing thumbnail rejection for non-images..."
+THUMBNAIL_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files/$FILE_ID/thumbnail?size=64" \
+  -H "Authorization: Bearer $TOKEN")
+
+if [ "$THUMBNAIL_STATUS" = "415" ]; then
+  echo "✅ Text file has no thumbnail (415)"
+else
+  echo "❌ Thumbnail of a text file returned $THUMBNAIL_STATUS"
+fi
+
+# Test 24: Upload integrity check
+echo ""
+echo "24. Testing upload integrity verification..."
+BAD_METADATA='{"original_name":"'$TEST_FILE'","mime_type":"text/plain","size_bytes":100,"expected_sha256":"0000000000000000000000000000000000000000000000000000000000000000"}'
+INTEGRITY_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/$TEST_FILE" \
+  -F "metadata=$BAD_METADATA")
+EXPECTED_SHA256=$(sha256sum /tmp/$TEST_FILE | cut -d' ' -f1)
+GOOD_METADATA='{"original_name":"'$TEST_FILE'","mime_type":"text/plain","size_bytes":100,"expected_sha256":"'$EXPECTED_SHA256'"}'
+INTEGRITY_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/$TEST_FILE" \
+  -F "metadata=$GOOD_METADATA")
+
+if [ "$INTEGRITY_STATUS" = "422" ] && echo "$INTEGRITY_RESPONSE" | grep -q "\"content_hash\":\"$EXPECTED_SHA256\""; then
+  echo "✅ Mismatched hash rejected (422), matching hash echoed back"
+else
+  echo "❌ Integrity check failed: status $INTEGRITY_STATUS, response $INTEGRITY_RESPONSE"
+fi
+INTEGRITY_ID=$(echo "$INTEGRITY_RESPONSE" | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+curl -s -X DELETE "$API_BASE/api/files/$INTEGRITY_ID" -H "Authorization: Bearer $TOKEN" > /dev/null
+
+# Test 25: Change username
+echo ""
+echo "25. Testing username change..."
+NEW_USERNAME="${TEST_USERNAME}_renamed"
+SHORT_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X PATCH "$API_BASE/api/auth/profile" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"username":"ab"}')
+PROFILE_RESPONSE=$(curl -s -X PATCH "$API_BASE/api/auth/profile" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d "{\"username\":\"$NEW_USERNAME\"}")
+PROFILE_TOKEN=$(echo $PROFILE_RESPONSE | grep -o '"access_token":"[^"]*' | cut -d'"' -f4)
+
+if [ "$SHORT_STATUS" = "400" ] && curl -s "$API_BASE/api/auth/me" -H "Authorization: Bearer $PROFILE_TOKEN" | grep -q "\"username\":\"$NEW_USERNAME\""; then
+  echo "✅ Username changed and new token reflects it"
+  TOKEN=$PROFILE_TOKEN
+else
+  echo "❌ Username change failed (short name status $SHORT_STATUS)"
+  echo $PROFILE_RESPONSE
+fi
+
+# Test 26: Admin endpoints
+echo ""
+echo "26. Testing admin user management requires the admin role..."
+ADMIN_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/admin/users" \
+  -H "Authorization: Bearer $TOKEN")
+
+if [ "$ADMIN_STATUS" = "403" ]; then
+  echo "✅ Regular user denied user management (403)"
+elif [ "$ADMIN_STATUS" = "200" ]; then
+  echo "✅ User listing available (test user is an admin)"
+else
+  echo "❌ User listing returned $ADMIN_STATUS"
+fi
+
+# Test 27: Email verification
+echo ""
+echo "27. Testing email verification..."
+BAD_EMAIL_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/signup" \
+  -H "Content-Type: application/json" \
+  -d "{\"username\":\"${TEST_USERNAME}_mail\",\"password\":\"$TEST_PASSWORD\",\"email\":\"not-an-email\"}")
+SEND_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/verify-email/send" \
+  -H "Authorization: Bearer $TOKEN")
+CONFIRM_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/auth/verify-email/confirm?token=bogus")
+
+if [ "$BAD_EMAIL_STATUS" = "422" ] && [ "$SEND_STATUS" = "202" ] && [ "$CONFIRM_STATUS" = "400" ]; then
+  echo "✅ Invalid email rejected, link sent (see server log), bogus token rejected"
+else
+  echo "❌ Email verification: signup $BAD_EMAIL_STATUS, send $SEND_STATUS, confirm $CONFIRM_STATUS"
+fi
+
+# Test 28: Password reset
+echo ""
+echo "28. Testing password reset..."
+RESET_REQUEST_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/password-reset/request" \
+  -H "Content-Type: application/json" \
+  -d '{"email":"nobody@example.com"}')
+RESET_CONFIRM_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/password-reset/confirm" \
+  -H "Content-Type: application/json" \
+  -d '{"token":"bogus","new_password":"newpass123"}')
+
+if [ "$RESET_REQUEST_STATUS" = "200" ] && [ "$RESET_CONFIRM_STATUS" = "400" ]; then
+  echo "✅ Unknown email gets 200, bogus reset token rejected"
+else
+  echo "❌ Password reset: request $RESET_REQUEST_STATUS, confirm $RESET_CONFIRM_STATUS"
+fi
+
+# Test 29: Unauthenticated requests
+echo ""
+echo "29. Testing missing and invalid tokens return 401..."
+NO_TOKEN_HEADERS=$(curl -s -o /dev/null -D - "$API_BASE/api/files")
+BAD_TOKEN_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files" \
+  -H "Authorization: Bearer not-a-token")
+
+if echo "$NO_TOKEN_HEADERS" | head -1 | grep -q " 401" && echo "$NO_TOKEN_HEADERS" | grep -qi "^www-authenticate: Bearer" && [ "$BAD_TOKEN_STATUS" = "401" ]; then
+  echo "✅ 401 with WWW-Authenticate for missing and invalid tokens"
+else
+  echo "❌ Expected 401 challenges, got invalid-token status $BAD_TOKEN_STATUS"
+  echo "$NO_TOKEN_HEADERS"
+fi
+
+# Test 30: Account lockout
+echo ""
+echo "30. Testing account lockout after repeated failed logins..."
+LOCKOUT_USERNAME="${TEST_USERNAME}_lock"
+curl -s -X POST "$API_BASE/api/auth/signup" \
+  -H "Content-Type: application/json" \
+  -d "{\"username\":\"$LOCKOUT_USERNAME\",\"password\":\"$TEST_PASSWORD\"}" > /dev/null
+for i in 1 2 3 4 5; do
+  curl -s -o /dev/null -X POST "$API_BASE/api/auth/login" \
+    -H "Content-Type: application/json" \
+    -d "{\"username\":\"$LOCKOUT_USERNAME\",\"password\":\"wrong-password\"}"
+done
+LOCKED_HEADERS=$(curl -s -o /dev/null -D - -X POST "$API_BASE/api/auth/login" \
+  -H "Content-Type: application/json" \
+  -d "{\"username\":\"$LOCKOUT_USERNAME\",\"password\":\"$TEST_PASSWORD\"}")
+
+if echo "$LOCKED_HEADERS" | head -1 | grep -q " 429" && echo "$LOCKED_HEADERS" | grep -qi "^retry-after:"; then
+  echo "✅ Locked account refuses even the right password (429 with Retry-After)"
+else
+  echo "❌ Account not locked after 5 failures (assumes default LOGIN_MAX_FAILURES)"
+  echo "$LOCKED_HEADERS" | head -1
+fi
+
+# Test 31: API keys
+echo ""
+echo "31. Testing API keys..."
+API_KEY_RESPONSE=$(curl -s -X POST "$API_BASE/api/auth/api-keys" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"name":"ci"}')
+API_KEY=$(echo $API_KEY_RESPONSE | grep -o '"key":"[^"]*' | cut -d'"' -f4)
+API_KEY_ID=$(echo $API_KEY_RESPONSE | grep -o '"id":"[^"]*' | cut -d'"' -f4)
+API_KEY_LIST_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files" \
+  -H "Authorization: Bearer $API_KEY")
+curl -s -X DELETE "$API_BASE/api/auth/api-keys/$API_KEY_ID" -H "Authorization: Bearer $TOKEN" > /dev/null
+REVOKED_KEY_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files" \
+  -H "Authorization: Bearer $API_KEY")
+
+if [ "$API_KEY_LIST_STATUS" = "200" ] && [ "$REVOKED_KEY_STATUS" = "401" ]; then
+  echo "✅ API key authenticates until revoked"
+else
+  echo "❌ API key: with key $API_KEY_LIST_STATUS, after revoke $REVOKED_KEY_STATUS"
+  echo $API_KEY_RESPONSE
+fi
+
+# Test 32: Pagination headers
+echo ""
+echo "32. Testing pagination headers..."
+PAGINATION_HEADERS=$(curl -s -o /dev/null -D - "$API_BASE/api/files?page=1&page_size=1" \
+  -H "Authorization: Bearer $TOKEN")
+
+if echo "$PAGINATION_HEADERS" | grep -qi "^x-total-count: [0-9]" && echo "$PAGINATION_HEADERS" | grep -i "^link:" | grep -q 'rel="first"'; then
+  echo "✅ X-Total-Count and Link headers present"
+else
+  echo "❌ Pagination headers missing"
+  echo "$PAGINATION_HEADERS"
+fi
+
+# Test 33: Cursor pagination
+echo ""
+echo "33. Testing cursor pagination..."
+CURSOR_PAGE=$(curl -s "$API_BASE/api/files?cursor=&page_size=1" \
+  -H "Authorization: Bearer $TOKEN")
+NEXT_CURSOR=$(echo $CURSOR_PAGE | grep -o '"next_cursor":"[^"]*' | cut -d'"' -f4)
+BAD_CURSOR_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files?cursor=!!!" \
+  -H "Authorization: Bearer $TOKEN")
+
+if echo "$CURSOR_PAGE" | grep -q '"files"' && [ "$BAD_CURSOR_STATUS" = "400" ]; then
+  echo "✅ Cursor mode lists files and rejects malformed cursors"
+  if [ -n "$NEXT_CURSOR" ]; then
+    curl -s "$API_BASE/api/files?cursor=$NEXT_CURSOR&page_size=1" -H "Authorization: Bearer $TOKEN" | grep -q '"files"' \
+      && echo "✅ next_cursor fetches the following page"
+  fi
+else
+  echo "❌ Cursor pagination failed (bad cursor status $BAD_CURSOR_STATUS)"
+  echo $CURSOR_PAGE
+fi
+
+# Test 34: Request body limit
+echo ""
+echo "34. Testing oversized JSON bodies are rejected..."
+head -c 2000000 /dev/zero | tr '\0' 'a' > /tmp/trusty_big_body.txt
+BIG_BODY_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/login" \
+  -H "Content-Type: application/json" \
+  --data-binary @/tmp/trusty_big_body.txt)
+rm -f /tmp/trusty_big_body.txt
+
+if [ "$BIG_BODY_STATUS" = "413" ]; then
+  echo "✅ 2MB login body rejected (413)"
+else
+  echo "❌ Oversized body returned $BIG_BODY_STATUS"
+fi
+
+# Test 35: Move file between folders
+echo ""
+echo "35. Testing moving a file between folders..."
+MOVE_FOLDER=$(curl -s -X POST "$API_BASE/api/folders" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"name":"moved"}' | grep -o '"id":"[^"]*' | cut -d'"' -f4)
+MOVE_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/$FILE_ID/move" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d "{\"folder_id\":\"$MOVE_FOLDER\"}")
+MISSING_FOLDER_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/$FILE_ID/move" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"folder_id":"no-such-folder"}')
+ROOT_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/$FILE_ID/move" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"folder_id":null}')
+
+if echo $MOVE_RESPONSE | grep -q "\"parent_id\":\"$MOVE_FOLDER\"" && \
+   [ "$MISSING_FOLDER_STATUS" = "404" ] && \
+   echo $ROOT_RESPONSE | grep -q '"parent_id":null'; then
+  echo "✅ File moved into a folder and back to root"
+else
+  echo "❌ File move failed ($MISSING_FOLDER_STATUS)"
+  echo $MOVE_RESPONSE
+fi
+
+# Test 36: File tags
+echo ""
+echo "36. Testing file tags..."
+TAG_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/$FILE_ID/tags" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"tag":"Invoices"}')
+TAGGED_LIST=$(curl -s -X GET "$API_BASE/api/files?tag=INVOICES" \
+  -H "Authorization: Bearer $TOKEN")
+UNTAG_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE "$API_BASE/api/files/$FILE_ID/tags/invoices" \
+  -H "Authorization: Bearer $TOKEN")
+UNTAGGED_TOTAL=$(curl -s -X GET "$API_BASE/api/files?tag=invoices" \
+  -H "Authorization: Bearer $TOKEN" | grep -o '"total":[0-9]*' | cut -d: -f2)
+
+if echo $TAG_RESPONSE | grep -q '"tags":\["invoices"\]' && \
+   echo $TAGGED_LIST | grep -q "\"id\":\"$FILE_ID\"" && \
+   [ "$UNTAG_STATUS" = "200" ] && [ "$UNTAGGED_TOTAL" = "0" ]; then
+  echo "✅ Tags are added, filtered case-insensitively and removed"
+else
+  echo "❌ File tags failed ($UNTAG_STATUS, $UNTAGGED_TOTAL)"
+  echo $TAG_RESPONSE
+fi
+
+# Test 37: Data export
+echo ""
+echo "37. Testing data export..."
+curl -s -o /tmp/trusty-export.zip "$API_BASE/api/auth/export" \
+  -H "Authorization: Bearer $TOKEN"
+
+if unzip -l /tmp/trusty-export.zip 2>/dev/null | grep -q "metadata.json" && \
+   unzip -p /tmp/trusty-export.zip metadata.json | grep -q "\"id\": \"$FILE_ID\""; then
+  echo "✅ Export is a ZIP with metadata for the uploaded file"
+else
+  echo "❌ Data export failed"
+fi
+rm -f /tmp/trusty-export.zip
+
+# Test 38: MIME type validation
+echo ""
+echo "38. Testing MIME type validation..."
+UPPER_METADATA='{"original_name":"upper.png","mime_type":"IMAGE/PNG","size_bytes":100,"client_encryption_algo":"AES-GCM-256"}'
+UPPER_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/$TEST_FILE" \
+  -F "metadata=$UPPER_METADATA")
+UPPER_ID=$(echo $UPPER_RESPONSE | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+BAD_METADATA='{"original_name":"bad.bin","mime_type":"not a mime type","size_bytes":100,"client_encryption_algo":"AES-GCM-256"}'
+BAD_MIME_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/$TEST_FILE" \
+  -F "metadata=$BAD_METADATA")
+
+if echo $UPPER_RESPONSE | grep -q '"mime_type":"image/png"' && [ "$BAD_MIME_STATUS" = "400" ]; then
+  echo "✅ MIME types are normalized and garbage is rejected"
+else
+  echo "❌ MIME type validation failed ($BAD_MIME_STATUS)"
+  echo $UPPER_RESPONSE
+fi
+curl -s -X DELETE "$API_BASE/api/files/$UPPER_ID" -H "Authorization: Bearer $TOKEN" > /dev/null
+
+# Test 39: HEAD download
+echo ""
+echo "39. Testing HEAD on downloads..."
+HEAD_HEADERS=$(curl -s -I "$API_BASE/api/files/$FILE_ID/download" \
+  -H "Authorization: Bearer $TOKEN")
+EXPECTED_LENGTH=$(wc -c < /tmp/$TEST_FILE | tr -d ' ')
+
+if echo "$HEAD_HEADERS" | grep -qi "^content-length: $EXPECTED_LENGTH" && \
+   echo "$HEAD_HEADERS" | grep -qi "^etag: " && \
+   echo "$HEAD_HEADERS" | grep -qi "^content-disposition: attachment"; then
+  echo "✅ HEAD returns length, ETag and disposition"
+else
+  echo "❌ HEAD download failed"
+  echo "$HEAD_HEADERS"
+fi
+
+# Test 40: Field-level signup errors
+echo ""
+echo "40. Testing that signup reports every invalid field at once..."
+FIELD_ERRORS=$(curl -s -X POST "$API_BASE/api/auth/signup" \
+  -H "Content-Type: application/json" \
+  -d '{"username":"ab","password":"123","email":"nope"}')
+
+if echo "$FIELD_ERRORS" | grep -q '"username"' && echo "$FIELD_ERRORS" | grep -q '"password"' && echo "$FIELD_ERRORS" | grep -q '"email"'; then
+  echo "✅ Username, password and email problems all reported"
+else
+  echo "❌ Field errors: $FIELD_ERRORS"
+fi
+
+# Test 41: Multipart fields in any order
+echo ""
+echo "41. Testing upload with the file field before metadata..."
+REORDERED_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/$TEST_FILE" \
+  -F "metadata=$METADATA")
+REORDERED_ID=$(echo $REORDERED_RESPONSE | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+
+if [ -n "$REORDERED_ID" ]; then
+  echo "✅ File-first upload accepted"
+else
+  echo "❌ File-first upload failed"
+  echo $REORDERED_RESPONSE
+fi
+curl -s -X DELETE "$API_BASE/api/files/$REORDERED_ID" -H "Authorization: Bearer $TOKEN" > /dev/null
+
+# Test 42: Duplicate multipart fields
+echo ""
+echo "42. Testing that duplicate upload fields are rejected..."
+COUNT_BEFORE=$(curl -s -D - -o /dev/null "$API_BASE/api/files" -H "Authorization: Bearer $TOKEN" | grep -i "^x-total-count" | tr -dc '0-9')
+DUP_FILE_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "metadata=$METADATA" \
+  -F "file=@/tmp/$TEST_FILE" \
+  -F "file=@/tmp/$TEST_FILE")
+DUP_METADATA_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/$TEST_FILE" \
+  -F "metadata=$METADATA" \
+  -F "metadata=$METADATA")
+COUNT_AFTER=$(curl -s -D - -o /dev/null "$API_BASE/api/files" -H "Authorization: Bearer $TOKEN" | grep -i "^x-total-count" | tr -dc '0-9')
+
+if [ "$DUP_FILE_STATUS" = "400" ] && [ "$DUP_METADATA_STATUS" = "400" ] && [ "$COUNT_BEFORE" = "$COUNT_AFTER" ]; then
+  echo "✅ Duplicate file and metadata fields rejected without creating files"
+else
+  echo "❌ Duplicate fields: file $DUP_FILE_STATUS, metadata $DUP_METADATA_STATUS, files $COUNT_BEFORE -> $COUNT_AFTER"
+fi
+
+# Test 43: Disk-space preflight
+echo ""
+echo "43. Testing that uploads larger than the free space are refused..."
+HUGE_METADATA='{"original_name":"huge.bin","mime_type":"application/octet-stream","size_bytes":1000000000000000000,"client_encryption_algo":"AES-GCM-256"}'
+HUGE_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "metadata=$HUGE_METADATA" \
+  -F "file=@/tmp/$TEST_FILE")
+
+if [ "$HUGE_STATUS" = "507" ]; then
+  echo "✅ Upload declaring more than the free space rejected with 507"
+else
+  echo "❌ Disk-space preflight failed ($HUGE_STATUS)"
+fi
+
+# Test 44: Admin file inspection
+echo ""
+echo "44. Testing admin file inspection..."
+ADMIN_FILE_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/admin/files/$FILE_ID" \
+  -H "Authorization: Bearer $TOKEN")
+
+if [ "$ADMIN_FILE_STATUS" = "403" ]; then
+  echo "✅ Regular user denied admin file records (403)"
+elif [ "$ADMIN_FILE_STATUS" = "200" ]; then
+  echo "✅ Admin file record available (test user is an admin)"
+else
+  echo "❌ Admin file record returned $ADMIN_FILE_STATUS"
+fi
+
+# Test 45: Batch tagging and moves
+echo ""
+echo "45. Testing batch file updates..."
+BATCH_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/batch" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d "{\"ids\":[\"$FILE_ID\",\"not-a-file\"],\"add_tags\":[\"Batch\"],\"move_to\":null}")
+UNBATCH_RESPONSE=$(curl -s -X POST "$API_BASE/api/files/batch" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d "{\"ids\":[\"$FILE_ID\"],\"remove_tags\":[\"batch\"]}")
+EMPTY_BATCH_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/batch" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d "{\"ids\":[\"$FILE_ID\"]}")
+
+if echo $BATCH_RESPONSE | grep -q '"updated":1,"skipped":1' && \
+   echo $BATCH_RESPONSE | grep -q '"status":"not_found"' && \
+   echo $BATCH_RESPONSE | grep -q '"tags":\["batch"\]' && \
+   echo $UNBATCH_RESPONSE | grep -q '"tags":\[\]' && \
+   [ "$EMPTY_BATCH_STATUS" = "400" ]; then
+  echo "✅ Batch updates apply to owned files and report the rest"
+else
+  echo "❌ Batch updates failed ($EMPTY_BATCH_STATUS)"
+  echo $BATCH_RESPONSE
+fi
+
+# Test 46: Idempotent uploads
+echo ""
+echo "46. Testing idempotent uploads with client_file_id..."
+echo "retry me" > /tmp/trusty-retry.txt
+RETRY_METADATA="{\"original_name\":\"retry.txt\",\"mime_type\":\"text/plain\",\"size_bytes\":9,\"client_encryption_algo\":\"none\",\"client_file_id\":\"retry-$TEST_USERNAME\"}"
+FIRST_UPLOAD=$(curl -s -w "\n%{http_code}" -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "metadata=$RETRY_METADATA;type=application/json" \
+  -F "file=@/tmp/trusty-retry.txt")
+RETRY_UPLOAD=$(curl -s -w "\n%{http_code}" -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "metadata=$RETRY_METADATA;type=application/json" \
+  -F "file=@/tmp/trusty-retry.txt")
+FIRST_ID=$(echo "$FIRST_UPLOAD" | head -1 | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+RETRY_ID=$(echo "$RETRY_UPLOAD" | head -1 | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+
+if [ "$(echo "$FIRST_UPLOAD" | tail -1)" = "201" ] && [ "$(echo "$RETRY_UPLOAD" | tail -1)" = "200" ] && \
+   [ -n "$FIRST_ID" ] && [ "$FIRST_ID" = "$RETRY_ID" ]; then
+  echo "✅ Retried upload returned the existing file"
+else
+  echo "❌ Idempotent upload failed"
+  echo "$RETRY_UPLOAD"
+fi
+if [ -n "$FIRST_ID" ]; then
+  curl -s -o /dev/null -X DELETE "$API_BASE/api/files/$FIRST_ID" -H "Authorization: Bearer $TOKEN"
+fi
+rm -f /tmp/trusty-retry.txt
+
+# Test 47: Recent and largest files
+echo ""
+echo "47. Testing recent and largest file shortcuts..."
+RECENT_RESPONSE=$(curl -s "$API_BASE/api/files/recent?limit=1" \
+  -H "Authorization: Bearer $TOKEN")
+LARGEST_RESPONSE=$(curl -s "$API_BASE/api/files/largest?limit=500" \
+  -H "Authorization: Bearer $TOKEN")
+
+if echo $RECENT_RESPONSE | grep -q '^\[{"id"' && \
+   [ "$(echo $RECENT_RESPONSE | grep -o '"original_name"' | wc -l)" = "1" ] && \
+   echo $LARGEST_RESPONSE | grep -q "\"id\":\"$FILE_ID\""; then
+  echo "✅ Recent and largest files listed"
+else
+  echo "❌ Recent/largest listing failed"
+  echo $RECENT_RESPONSE
+fi
+
+# Test 48: OpenAPI schema references
+echo ""
+echo "48. Testing that every OpenAPI schema reference resolves..."
+if curl -s "$API_BASE/api/openapi.json" | python3 -c "
+import json, re, sys
+doc = json.load(sys.stdin)
+schemas = doc['components']['schemas']
+refs = set(re.findall(r'#/components/schemas/([^\"]+)', json.dumps(doc)))
+missing = sorted(refs - set(schemas))
+listing = doc['paths']['/api/files']['get']['responses']['200']['content']['application/json']['schema']
+sys.exit(0 if not missing and 'FileListResponse' in schemas and 'File' in schemas
+         and listing.get('\$ref') == '#/components/schemas/FileListResponse' else 1)
+"; then
+  echo "✅ OpenAPI document is self-contained (FileListResponse and File registered)"
+else
+  echo "❌ OpenAPI document has unresolved schema references"
+fi
+
+# Test 49: Multi-file upload
+echo ""
+echo "49. Testing multi-file upload..."
+echo "first batch file" > /tmp/multi_a.txt
+echo "second batch file" > /tmp/multi_b.txt
+MULTI_META_A='{"original_name":"multi_a.txt","mime_type":"text/plain","size_bytes":17,"client_encryption_algo":"AES-GCM-256"}'
+MULTI_META_B='{"original_name":"multi_b.txt","mime_type":"text/plain","size_bytes":18,"client_encryption_algo":"AES-GCM-256"}'
+MULTI_RESPONSE=$(curl -s -w "\n%{http_code}" -X POST "$API_BASE/api/files/upload/multiple" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "metadata=$MULTI_META_A" -F "file=@/tmp/multi_a.txt" \
+  -F "metadata=$MULTI_META_B" -F "file=@/tmp/multi_b.txt")
+MULTI_CODE=$(echo "$MULTI_RESPONSE" | tail -1)
+MULTI_NAMES=$(echo "$MULTI_RESPONSE" | sed '$d' | python3 -c "import sys,json; print(','.join(f['original_name'] for f in json.load(sys.stdin)))" 2>/dev/null)
+if [ "$MULTI_CODE" = "201" ] && [ "$MULTI_NAMES" = "multi_a.txt,multi_b.txt" ]; then
+  echo "✅ Both files uploaded in one request, in order"
+else
+  echo "❌ Multi-file upload failed (HTTP $MULTI_CODE): $MULTI_RESPONSE"
+fi
+
+UNPAIRED_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/upload/multiple" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "metadata=$MULTI_META_A" -F "file=@/tmp/multi_a.txt" \
+  -F "file=@/tmp/multi_b.txt")
+if [ "$UNPAIRED_CODE" = "400" ]; then
+  echo "✅ File field without its own metadata rejected"
+else
+  echo "❌ Unpaired file field returned HTTP $UNPAIRED_CODE"
+fi
+
+BAD_META_B='{"original_name":"multi_bad.txt","mime_type":"text/plain","size_bytes":18,"client_encryption_algo":"AES-GCM-256","expected_size":1}'
+ROLLBACK_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/files/upload/multiple" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F 'metadata={"original_name":"multi_kept.txt","mime_type":"text/plain","size_bytes":17,"client_encryption_algo":"AES-GCM-256"}' -F "file=@/tmp/multi_a.txt" \
+  -F "metadata=$BAD_META_B" -F "file=@/tmp/multi_b.txt")
+KEPT=$(curl -s "$API_BASE/api/files?q=multi_kept" -H "Authorization: Bearer $TOKEN" | grep -c "multi_kept")
+if [ "$ROLLBACK_CODE" = "422" ] && [ "$KEPT" = "0" ]; then
+  echo "✅ A failing file rejects the whole upload"
+else
+  echo "❌ Partial upload kept (HTTP $ROLLBACK_CODE, kept $KEPT)"
+fi
+
+for MULTI_ID in $(echo "$MULTI_RESPONSE" | sed '$d' | grep -o '"id":"[^"]*' | cut -d'"' -f4 | sort -u); do
+  curl -s -o /dev/null -X DELETE "$API_BASE/api/files/$MULTI_ID" -H "Authorization: Bearer $TOKEN"
+done
+rm -f /tmp/multi_a.txt /tmp/multi_b.txt
+
+# Test 50: Incremental sync changes
+echo ""
+echo "50. Testing incremental sync changes..."
+SYNC_SINCE=$(date -u +%Y-%m-%dT%H:%M:%SZ)
+sleep 1
+echo "sync test file" > /tmp/sync_test.txt
+SYNC_ID=$(curl -s -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/sync_test.txt" \
+  -F 'metadata={"original_name":"sync_test.txt","mime_type":"text/plain","size_bytes":15,"client_encryption_algo":"AES-GCM-256"}' \
+  | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+CHANGES=$(curl -s "$API_BASE/api/files/changes?since=$SYNC_SINCE" -H "Authorization: Bearer $TOKEN")
+if echo "$CHANGES" | python3 -c "import sys,json; d=json.load(sys.stdin); sys.exit(0 if '$SYNC_ID' in [f['id'] for f in d['created']] and d['until'] else 1)"; then
+  echo "✅ New upload listed as created"
+else
+  echo "❌ Changes missing the new upload: $CHANGES"
+fi
+
+curl -s -o /dev/null -X POST "$API_BASE/api/files/$FILE_ID/tags" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"tag":"synced"}'
+CHANGES=$(curl -s "$API_BASE/api/files/changes?since=$SYNC_SINCE" -H "Authorization: Bearer $TOKEN")
+if echo "$CHANGES" | python3 -c "import sys,json; d=json.load(sys.stdin); sys.exit(0 if '$FILE_ID' in [f['id'] for f in d['updated']] else 1)"; then
+  echo "✅ Tag change on an older file listed as updated"
+else
+  echo "❌ Changes missing the tag change: $CHANGES"
+fi
+curl -s -o /dev/null -X DELETE "$API_BASE/api/files/$FILE_ID/tags/synced" -H "Authorization: Bearer $TOKEN"
+
+curl -s -o /dev/null -X DELETE "$API_BASE/api/files/$SYNC_ID" -H "Authorization: Bearer $TOKEN"
+CHANGES=$(curl -s "$API_BASE/api/files/changes?since=$SYNC_SINCE" -H "Authorization: Bearer $TOKEN")
+if echo "$CHANGES" | python3 -c "import sys,json; d=json.load(sys.stdin); sys.exit(0 if '$SYNC_ID' in [f['id'] for f in d['deleted']] and '$SYNC_ID' not in [f['id'] for f in d['created']] else 1)"; then
+  echo "✅ Deleted file reported as a tombstone"
+else
+  echo "❌ Deletion not reported: $CHANGES"
+fi
+
+INVALID_CODE=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files/changes?since=yesterday" -H "Authorization: Bearer $TOKEN")
+if [ "$INVALID_CODE" = "400" ]; then
+  echo "✅ Invalid since timestamp rejected"
+else
+  echo "❌ Invalid since returned HTTP $INVALID_CODE"
+fi
+rm -f /tmp/sync_test.txt
+
+# Test 51: Optimistic concurrency with If-Match
+echo ""
+echo "51. Testing If-Match on file updates..."
+ETAG=$(curl -s -D - -o /dev/null "$API_BASE/api/files/$FILE_ID" -H "Authorization: Bearer $TOKEN" \
+  | grep -i '^etag:' | cut -d' ' -f2 | tr -d '\r')
+UPDATED_ETAG=$(curl -s -D - -o /dev/null -X POST "$API_BASE/api/files/$FILE_ID/tags" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -H "If-Match: $ETAG" \
+  -d '{"tag":"etag-test"}' | grep -i '^etag:' | cut -d' ' -f2 | tr -d '\r')
+STALE_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE "$API_BASE/api/files/$FILE_ID/tags/etag-test" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "If-Match: $ETAG")
+FRESH_CODE=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE "$API_BASE/api/files/$FILE_ID/tags/etag-test" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "If-Match: $UPDATED_ETAG")
+if [ -n "$ETAG" ] && [ "$UPDATED_ETAG" != "$ETAG" ] && [ "$STALE_CODE" = "412" ] && [ "$FRESH_CODE" = "200" ]; then
+  echo "✅ Stale ETag rejected with 412, current one accepted"
+else
+  echo "❌ If-Match not enforced (etag $ETAG -> $UPDATED_ETAG, stale HTTP $STALE_CODE, fresh HTTP $FRESH_CODE)"
+fi
+
+# Test 52: Storage usage summary
+echo ""
+echo "52. Testing storage usage summary..."
+USAGE_RESPONSE=$(curl -s "$API_BASE/api/files/usage" -H "Authorization: Bearer $TOKEN")
+if echo "$USAGE_RESPONSE" | python3 -c "import sys,json; d=json.load(sys.stdin); sys.exit(0 if d['total_files'] >= 1 and d['total_bytes'] > 0 and 'quota_bytes' in d and 'percent_used' in d else 1)"; then
+  echo "✅ Usage summary counts the user's files"
+else
+  echo "❌ Unexpected usage summary: $USAGE_RESPONSE"
+fi
+
+# Test 53: Usage delta
+echo ""
+echo "53. Testing usage delta..."
+# Whole seconds: wait out the current one so earlier uploads fall before the timestamp
+sleep 1
+DELTA_SINCE=$(date -u +%Y-%m-%dT%H:%M:%SZ)
+sleep 1
+printf 'usage delta test' > /tmp/usage_delta_test.txt
+DELTA_ID=$(curl -s -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F "file=@/tmp/usage_delta_test.txt" \
+  -F 'metadata={"original_name":"usage_delta_test.txt","mime_type":"text/plain","size_bytes":16,"client_encryption_algo":"AES-GCM-256"}' \
+  | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+curl -s -o /dev/null -X DELETE "$API_BASE/api/files/$DELTA_ID" -H "Authorization: Bearer $TOKEN"
+DELTA=$(curl -s "$API_BASE/api/me/usage-delta?since=$DELTA_SINCE" -H "Authorization: Bearer $TOKEN")
+if [ -n "$DELTA_ID" ] && echo "$DELTA" | python3 -c "import sys,json; d=json.load(sys.stdin); sys.exit(0 if (d['files_added'], d['bytes_added'], d['files_removed'], d['bytes_removed']) == (1, 16, 1, 16) else 1)"; then
+  echo "✅ Upload and deletion counted in the usage delta"
+else
+  echo "❌ Unexpected usage delta: $DELTA"
+fi
+rm -f /tmp/usage_delta_test.txt
+
+# Test 54: Upload name conflicts
+echo ""
+echo "54. Testing upload name conflicts..."
+echo "conflict test" > /tmp/conflict.txt
+CONFLICT_NAME="conflict_$TEST_USERNAME.txt"
+upload_conflict() {
+  curl -s -w "\n%{http_code}" -X POST "$API_BASE/api/files/upload" \
+    -H "Authorization: Bearer $TOKEN" \
+    -F "metadata={\"original_name\":\"$CONFLICT_NAME\",\"mime_type\":\"text/plain\",\"size_bytes\":14,\"client_encryption_algo\":\"AES-GCM-256\"$1}" \
+    -F "file=@/tmp/conflict.txt"
+}
+FIRST_ID=$(upload_conflict "" | head -1 | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+RENAMED=$(upload_conflict "" | head -1)
+ERROR_CODE=$(upload_conflict ',"on_conflict":"error"' | tail -1)
+REPLACED=$(upload_conflict ',"on_conflict":"replace"' | head -1)
+FIRST_CODE=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files/$FIRST_ID" -H "Authorization: Bearer $TOKEN")
+if echo "$RENAMED" | grep -q "\"original_name\":\"conflict_$TEST_USERNAME (1).txt\"" && \
+   [ "$ERROR_CODE" = "409" ] && \
+   echo "$REPLACED" | grep -q "\"original_name\":\"$CONFLICT_NAME\"" && \
+   [ "$FIRST_CODE" = "404" ]; then
+  echo "✅ Duplicate name renamed, rejected with 409, or replacing the original"
+else
+  echo "❌ Name conflicts not handled (renamed: $RENAMED, error HTTP $ERROR_CODE, original HTTP $FIRST_CODE)"
+fi
+rm -f /tmp/conflict.txt
+
+# Test 55: Token verification
+echo ""
+echo "55. Testing token verification..."
+VERIFY_RESPONSE=$(curl -s "$API_BASE/api/auth/verify" \
+  -H "Authorization: Bearer $TOKEN")
+EXPIRES_IN=$(echo $VERIFY_RESPONSE | grep -o '"expires_in":[0-9]*' | cut -d':' -f2)
+BAD_VERIFY_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/auth/verify" \
+  -H "Authorization: Bearer not-a-token")
+
+if echo "$VERIFY_RESPONSE" | grep -q '"valid":true' && [ -n "$EXPIRES_IN" ] && [ "$EXPIRES_IN" -gt 0 ] && [ "$BAD_VERIFY_STATUS" = "401" ]; then
+  echo "✅ Valid token reports ${EXPIRES_IN}s left, invalid token gets 401"
+else
+  echo "❌ Token verification failed"
+  echo "$VERIFY_RESPONSE (invalid token: $BAD_VERIFY_STATUS)"
+fi
+
+# Test 56: Sign out everywhere
+echo ""
+echo "56. Testing sign out everywhere..."
+LOGOUT_USERNAME="logout_$TEST_USERNAME"
+LOGOUT_TOKEN=$(curl -s -X POST "$API_BASE/api/auth/signup" \
+  -H "Content-Type: application/json" \
+  -d "{\"username\":\"$LOGOUT_USERNAME\",\"password\":\"$TEST_PASSWORD\"}" | grep -o '"access_token":"[^"]*' | cut -d'"' -f4)
+OTHER_SESSION_TOKEN=$(curl -s -X POST "$API_BASE/api/auth/login" \
+  -H "Content-Type: application/json" \
+  -d "{\"username\":\"$LOGOUT_USERNAME\",\"password\":\"$TEST_PASSWORD\"}" | grep -o '"access_token":"[^"]*' | cut -d'"' -f4)
+LOGOUT_API_KEY=$(curl -s -X POST "$API_BASE/api/auth/api-keys" \
+  -H "Authorization: Bearer $LOGOUT_TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"name":"logout test"}' | grep -o '"key":"[^"]*' | cut -d'"' -f4)
+LOGOUT_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/logout-all" \
+  -H "Authorization: Bearer $LOGOUT_TOKEN")
+OWN_AFTER_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/auth/verify" \
+  -H "Authorization: Bearer $LOGOUT_TOKEN")
+OTHER_AFTER_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/auth/verify" \
+  -H "Authorization: Bearer $OTHER_SESSION_TOKEN")
+API_KEY_AFTER_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/auth/verify" \
+  -H "Authorization: Bearer $LOGOUT_API_KEY")
+FRESH_TOKEN=$(curl -s -X POST "$API_BASE/api/auth/login" \
+  -H "Content-Type: application/json" \
+  -d "{\"username\":\"$LOGOUT_USERNAME\",\"password\":\"$TEST_PASSWORD\"}" | grep -o '"access_token":"[^"]*' | cut -d'"' -f4)
+FRESH_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/auth/verify" \
+  -H "Authorization: Bearer $FRESH_TOKEN")
+
+if [ "$LOGOUT_STATUS" = "204" ] && [ "$OWN_AFTER_STATUS" = "401" ] && [ "$OTHER_AFTER_STATUS" = "401" ] && \
+   [ -n "$LOGOUT_API_KEY" ] && [ "$API_KEY_AFTER_STATUS" = "401" ] && [ "$FRESH_STATUS" = "200" ]; then
+  echo "✅ Every earlier session and API key revoked, new logins still work"
+else
+  echo "❌ Sign out everywhere failed (logout $LOGOUT_STATUS, own $OWN_AFTER_STATUS, other $OTHER_AFTER_STATUS, API key $API_KEY_AFTER_STATUS, fresh $FRESH_STATUS)"
+fi
+
+# Test 57: File index export
+echo ""
+echo "57. Testing file index export..."
+INDEX_JSON=$(curl -s "$API_BASE/api/files/export" \
+  -H "Authorization: Bearer $TOKEN")
+INDEX_CSV_HEADERS=$(curl -s -D - -o /tmp/trusty_index.csv "$API_BASE/api/files/export?format=csv" \
+  -H "Authorization: Bearer $TOKEN")
+INDEX_BAD_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files/export?format=xml" \
+  -H "Authorization: Bearer $TOKEN")
+INDEX_JSON_LINES=$(echo "$INDEX_JSON" | grep -c '^{"id":')
+INDEX_CSV_ROWS=$(($(wc -l < /tmp/trusty_index.csv) - 1))
+
+if [ "$INDEX_JSON_LINES" -gt 0 ] && [ "$INDEX_JSON_LINES" = "$INDEX_CSV_ROWS" ] \
+  && head -1 /tmp/trusty_index.csv | grep -q '^id,original_name,' \
+  && echo "$INDEX_CSV_HEADERS" | grep -qi '^content-disposition: attachment; filename="trusty-files-.*\.csv"' \
+  && [ "$INDEX_BAD_STATUS" = "400" ]; then
+  echo "✅ Index exported as $INDEX_JSON_LINES JSON lines and CSV rows"
+else
+  echo "❌ File index export failed (json $INDEX_JSON_LINES, csv $INDEX_CSV_ROWS, bad format $INDEX_BAD_STATUS)"
+fi
+rm -f /tmp/trusty_index.csv
+
+# Test 58: Thumbnail availability in file records
 echo ""
-echo "9. Testing file deletion..."
+echo "58. Testing thumbnail availability flag..."
+echo "not really a png" > /tmp/trusty_broken.png
+BROKEN_IMAGE_ID=$(curl -s -X POST "$API_BASE/api/files/upload" \
+  -H "Authorization: Bearer $TOKEN" \
+  -F 'metadata={"original_name":"broken.png","mime_type":"image/png","size_bytes":17,"client_encryption_algo":"AES-GCM-256"}' \
+  -F "file=@/tmp/trusty_broken.png" | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4)
+BEFORE_FLAG=$(curl -s "$API_BASE/api/files/$BROKEN_IMAGE_ID" -H "Authorization: Bearer $TOKEN" | grep -o '"has_thumbnail":[a-z]*')
+BROKEN_THUMB_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files/$BROKEN_IMAGE_ID/thumbnail" \
+  -H "Authorization: Bearer $TOKEN")
+AFTER_FLAG=$(curl -s "$API_BASE/api/files/$BROKEN_IMAGE_ID" -H "Authorization: Bearer $TOKEN" | grep -o '"has_thumbnail":[a-z]*')
+
+if [ "$BEFORE_FLAG" = '"has_thumbnail":true' ] && [ "$BROKEN_THUMB_STATUS" = "415" ] && [ "$AFTER_FLAG" = '"has_thumbnail":false' ]; then
+  echo "✅ has_thumbnail turns false once an image fails to render"
+else
+  echo "❌ Thumbnail flag not maintained (before $BEFORE_FLAG, thumbnail $BROKEN_THUMB_STATUS, after $AFTER_FLAG)"
+fi
+curl -s -o /dev/null -X DELETE "$API_BASE/api/files/$BROKEN_IMAGE_ID" -H "Authorization: Bearer $TOKEN"
+rm -f /tmp/trusty_broken.png
+
+# Test 59: Idempotency keys
+echo ""
+echo "59. Testing Idempotency-Key replay..."
+IDEMPOTENCY_KEY="idem-$(date +%s)-$RANDOM"
+echo "idempotent upload" > /tmp/trusty_idem.txt
+idempotent_upload() {
+  curl -s -D /tmp/trusty_idem_headers -X POST "$API_BASE/api/files/upload" \
+    -H "Authorization: Bearer $TOKEN" \
+    -H "Idempotency-Key: $IDEMPOTENCY_KEY" \
+    -F 'metadata={"original_name":"idempotent.txt","mime_type":"text/plain","size_bytes":18,"client_encryption_algo":"AES-GCM-256"}' \
+    -F "file=@/tmp/trusty_idem.txt" | grep -o '"id":"[^"]*' | head -1 | cut -d'"' -f4
+}
+FIRST_IDEM_ID=$(idempotent_upload)
+SECOND_IDEM_ID=$(idempotent_upload)
+REPLAYED=$(grep -i '^idempotent-replayed: true' /tmp/trusty_idem_headers)
+IDEM_COPIES=$(curl -s "$API_BASE/api/files?q=idempotent.txt" -H "Authorization: Bearer $TOKEN" | grep -o '"original_name":"idempotent' | wc -l)
+
+if [ -n "$FIRST_IDEM_ID" ] && [ "$FIRST_IDEM_ID" = "$SECOND_IDEM_ID" ] && [ -n "$REPLAYED" ] && [ "$IDEM_COPIES" -eq 1 ]; then
+  echo "✅ Retried upload replayed without a second file"
+else
+  echo "❌ Idempotency-Key not honoured (ids $FIRST_IDEM_ID / $SECOND_IDEM_ID, copies $IDEM_COPIES)"
+fi
+curl -s -o /dev/null -X DELETE "$API_BASE/api/files/$FIRST_IDEM_ID" -H "Authorization: Bearer $TOKEN"
+
+IDEM_SIGNUP_BODY="{\"username\":\"${TEST_USERNAME}_idem\",\"password\":\"$TEST_PASSWORD\"}"
+FIRST_SIGNUP=$(curl -s -X POST "$API_BASE/api/auth/signup" \
+  -H "Content-Type: application/json" \
+  -H "Idempotency-Key: $IDEMPOTENCY_KEY" \
+  -d "$IDEM_SIGNUP_BODY")
+REPLAYED_SIGNUP=$(curl -s -X POST "$API_BASE/api/auth/signup" \
+  -H "Content-Type: application/json" \
+  -H "Idempotency-Key: $IDEMPOTENCY_KEY" \
+  -d "$IDEM_SIGNUP_BODY")
+if echo "$FIRST_SIGNUP" | grep -q '"access_token"' && \
+   ! echo "$REPLAYED_SIGNUP" | grep -q '"access_token"' && \
+   [ "$(echo "$FIRST_SIGNUP" | grep -o '"id":"[^"]*')" = "$(echo "$REPLAYED_SIGNUP" | grep -o '"id":"[^"]*')" ]; then
+  echo "✅ Replayed signup returns the same user without storing its token"
+else
+  echo "❌ Signup replay: first $FIRST_SIGNUP, replay $REPLAYED_SIGNUP"
+fi
+rm -f /tmp/trusty_idem.txt /tmp/trusty_idem_headers
+
+# Test 60: Maintenance mode toggle
+echo ""
+echo "60. Testing maintenance mode toggle requires the admin role..."
+MAINTENANCE_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X PUT "$API_BASE/api/admin/maintenance" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"enabled":false}')
+
+if [ "$MAINTENANCE_STATUS" = "403" ]; then
+  echo "✅ Regular user can't switch maintenance mode (403)"
+elif [ "$MAINTENANCE_STATUS" = "200" ]; then
+  echo "✅ Maintenance mode switched off (test user is an admin)"
+else
+  echo "❌ Maintenance toggle returned $MAINTENANCE_STATUS"
+fi
+
+# Test 61: Maintenance mode refuses writes
+echo ""
+echo "61. Testing maintenance mode refuses writes but serves reads..."
+MAINTENANCE_TOKEN="$ADMIN_TOKEN"
+if [ "$MAINTENANCE_STATUS" = "200" ]; then
+  MAINTENANCE_TOKEN="$TOKEN"
+fi
+
+if [ -z "$MAINTENANCE_TOKEN" ]; then
+  echo "⏭️  Skipped (set ADMIN_TOKEN to an admin's token to run it)"
+else
+  curl -s -o /dev/null -X PUT "$API_BASE/api/admin/maintenance" \
+    -H "Authorization: Bearer $MAINTENANCE_TOKEN" \
+    -H "Content-Type: application/json" \
+    -d '{"enabled":true}'
+  WRITE_HEADERS=$(curl -s -D - -o /dev/null -X POST "$API_BASE/api/folders" \
+    -H "Authorization: Bearer $TOKEN" \
+    -H "Content-Type: application/json" \
+    -d '{"name":"during-maintenance"}')
+  READ_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files" \
+    -H "Authorization: Bearer $TOKEN")
+  MAINTENANCE_LOGIN_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/login" \
+    -H "Content-Type: application/json" \
+    -d '{"username":"no_such_user","password":"password123"}')
+  curl -s -o /dev/null -X PUT "$API_BASE/api/admin/maintenance" \
+    -H "Authorization: Bearer $MAINTENANCE_TOKEN" \
+    -H "Content-Type: application/json" \
+    -d '{"enabled":false}'
+
+  if echo "$WRITE_HEADERS" | head -1 | grep -q " 503" && echo "$WRITE_HEADERS" | grep -qi '^retry-after:' \
+    && [ "$READ_STATUS" = "200" ] && [ "$MAINTENANCE_LOGIN_STATUS" = "401" ]; then
+    echo "✅ Writes get 503 with Retry-After; reads and logins are still served"
+  else
+    echo "❌ Maintenance mode (write: $(echo "$WRITE_HEADERS" | head -1 | tr -d '\r'), read $READ_STATUS, login $MAINTENANCE_LOGIN_STATUS)"
+  fi
+fi
+
+# Test 62: Conditional download by content hash
+echo ""
+echo "62. Testing conditional download by content hash..."
+HASH_HEADERS=$(curl -s -D - -o /dev/null "$API_BASE/api/files/$FILE_ID/download?hash_only=true" \
+  -H "Authorization: Bearer $TOKEN")
+CONTENT_HASH=$(echo "$HASH_HEADERS" | grep -i '^x-content-hash:' | cut -d' ' -f2 | tr -d '\r')
+MATCH_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files/$FILE_ID/download" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "If-None-Match: \"$CONTENT_HASH\"")
+STALE_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files/$FILE_ID/download" \
+  -H "Authorization: Bearer $TOKEN" \
+  -H "If-None-Match: \"0000\"")
+
+if [ -n "$CONTENT_HASH" ] && echo "$HASH_HEADERS" | head -1 | grep -q " 204" \
+  && [ "$MATCH_STATUS" = "304" ] && [ "$STALE_STATUS" = "200" ]; then
+  echo "✅ Hash-only answers 204 with the hash; a matching If-None-Match gets 304"
+else
+  echo "❌ Conditional download failed (hash '$CONTENT_HASH', match $MATCH_STATUS, stale $STALE_STATUS)"
+fi
+
+# Test 63: Reserved and malformed usernames
+echo ""
+echo "63. Testing reserved and malformed usernames are refused..."
+RESERVED_RESPONSE=$(curl -s -X POST "$API_BASE/api/auth/signup" \
+  -H "Content-Type: application/json" \
+  -d '{"username":"Admin","password":"password123"}')
+CHARACTERS_RESPONSE=$(curl -s -X POST "$API_BASE/api/auth/signup" \
+  -H "Content-Type: application/json" \
+  -d '{"username":"bad name!","password":"password123"}')
+
+if echo "$RESERVED_RESPONSE" | grep -q '"error":"Username is reserved"' \
+  && echo "$CHARACTERS_RESPONSE" | grep -q '"error":"Invalid username (only letters'; then
+  echo "✅ Reserved and malformed usernames rejected with distinct errors"
+else
+  echo "❌ Username rules not enforced: $RESERVED_RESPONSE / $CHARACTERS_RESPONSE"
+fi
+
+# Test 64: HTTPS enforcement
+echo ""
+echo "64. Testing HTTPS enforcement..."
+PLAIN_GET_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/api/files" -H "Authorization: Bearer $TOKEN")
+if [ "$PLAIN_GET_STATUS" != "308" ]; then
+  echo "⏭️  Skipped (run the server with FORCE_HTTPS=true, TRUST_FORWARDED_PROTO=true and TRUSTED_PROXY=127.0.0.1,::1)"
+else
+  # A login for an unknown user is harmless: 401 once it gets past the HTTPS check
+  PLAIN_POST_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/login" \
+    -H "Content-Type: application/json" \
+    -d '{"username":"no_such_user","password":"whatever"}')
+  PROXIED_POST_STATUS=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_BASE/api/auth/login" \
+    -H "Content-Type: application/json" \
+    -H "X-Forwarded-Proto: https" \
+    -d '{"username":"no_such_user","password":"whatever"}')
+  PLAIN_HEALTH_STATUS=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/health")
+  if [ "$PLAIN_POST_STATUS" = "403" ] && [ "$PROXIED_POST_STATUS" = "401" ] && [ "$PLAIN_HEALTH_STATUS" = "200" ]; then
+    echo "✅ Plain-HTTP write rejected, proxied HTTPS write allowed, /health not redirected"
+  else
+    echo "❌ HTTPS enforcement: plain POST $PLAIN_POST_STATUS, proxied POST $PROXIED_POST_STATUS, /health $PLAIN_HEALTH_STATUS"
+  fi
+fi
+
+# Test 65: Delete file
+echo ""
+echo "65. Testing file deletion..."
 DELETE_RESPONSE=$(curl -s -X DELETE "$API_BASE/api/files/$FILE_ID" \
   -H "Authorization: Bearer $TOKEN" \
   -w "%{http_code}")
@@ -153,9 +1355,9 @@ else
   echo $DELETE_RESPONSE
 fi
 
-# Test 10: Verify file is gone
+# Test 66: Verify file is gone
 echo ""
-echo "10. Verifying file was deleted..."
+echo "66. Verifying file was deleted..."
 FILES_RESPONSE=$(curl -s -X GET "$API_BASE/api/files" \
   -H "Authorization: Bearer $TOKEN")
 
@@ -165,6 +1367,38 @@ else
   echo "⚠️  File list: $FILES_RESPONSE"
 fi
 
+# Test 67: Rate limiter (last, since it exhausts this client's quota)
+echo ""
+echo "67. Testing request rate limiting..."
+HEALTH_LIMITED=0
+for i in $(seq 1 50); do
+  CODE=$(curl -s -o /dev/null -w "%{http_code}" "$API_BASE/health")
+  [ "$CODE" = "429" ] && HEALTH_LIMITED=1
+done
+if [ "$HEALTH_LIMITED" = "0" ]; then
+  echo "✅ Health checks are never rate limited"
+else
+  echo "❌ Health check was rate limited"
+fi
+
+LIMITED_HEADERS=""
+for i in $(seq 1 200); do
+  HEADERS=$(curl -s -D - -o /tmp/rate_limit_body.json "$API_BASE/api/openapi.json")
+  if echo "$HEADERS" | grep -q "^HTTP/[0-9.]* 429"; then
+    LIMITED_HEADERS="$HEADERS"
+    break
+  fi
+done
+if [ -z "$LIMITED_HEADERS" ]; then
+  echo "❌ No 429 after 200 requests (RATE_LIMIT_PER_SECOND=0 or a high limit?)"
+elif echo "$LIMITED_HEADERS" | grep -qi "^retry-after: [1-9]" && grep -q '"error"' /tmp/rate_limit_body.json; then
+  echo "✅ Excess requests get 429 with Retry-After"
+else
+  echo "❌ 429 response missing Retry-After or JSON error"
+  echo "$LIMITED_HEADERS"
+fi
+rm -f /tmp/rate_limit_body.json
+
 # Cleanup
 rm -f /tmp/$TEST_FILE /tmp/downloaded_$TEST_FILE
 